@@ -8,12 +8,59 @@
 //!
 //! Wait for token refresh
 //! dbus-monitor type='signal',sender='org.worldcoin.AuthTokenManager1'
+//!
+//! Get a token scoped to an audience
+//! gdbus call --session -d org.worldcoin.AuthTokenManager1 -o '/org/worldcoin/AuthTokenManager1' -m
+//! org.worldcoin.AuthTokenManager1.GetToken update-agent
+
+use std::future::Future;
+
+use zbus::{
+    interface,
+    message::Header,
+    zvariant::{OwnedValue, Type, Value},
+    SignalContext,
+};
 
-use zbus::interface;
+/// Whether the orb has a token to authenticate with the backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Type, Value, OwnedValue)]
+#[repr(u32)]
+pub enum Status {
+    /// A valid token is available and the last refresh succeeded.
+    Ok = 0,
+    /// A valid token is available, but refreshing it failed since.
+    Degraded = 1,
+    /// No valid token is available.
+    NoToken = 2,
+}
+
+/// The lifecycle of the orb's token, exposed as properties of the interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenHealth {
+    pub status: Status,
+    /// Seconds since the unix epoch of the last successful refresh, or 0 if there was none.
+    pub last_refresh: u64,
+    /// Seconds since the unix epoch of the next scheduled refresh, or 0 if there is none.
+    pub next_refresh: u64,
+    /// The number of failed refresh attempts since the last successful refresh.
+    pub consecutive_failures: u32,
+    /// The category of the last error while refreshing, e.g. `sign.not_provisioned`, or an
+    /// empty string if there was none.
+    pub last_error: String,
+}
 
+/// The methods of the interface don't require exclusive access, so that calls to
+/// [`AuthTokenManagerT::get_token`] waiting for a token don't block the others.
 pub trait AuthTokenManagerT: Send + Sync + 'static {
     fn token(&self) -> zbus::fdo::Result<String>;
-    fn force_token_refresh(&mut self, ctxt: zbus::SignalContext<'_>);
+    fn force_token_refresh(&self, ctxt: SignalContext<'_>);
+    fn token_health(&self) -> TokenHealth;
+    /// Returns the token scoped to `audience` if the unix user `caller_uid` may request it.
+    fn get_token(
+        &self,
+        audience: String,
+        caller_uid: u32,
+    ) -> impl Future<Output = zbus::fdo::Result<String>> + Send;
 }
 
 #[derive(Debug, derive_more::From)]
@@ -26,16 +73,82 @@ pub struct AuthTokenManager<T>(pub T);
         default_path = "/org/worldcoin/AuthTokenManager1",
     )
 )]
-impl<T: AuthTokenManagerT> AuthTokenManagerT for AuthTokenManager<T> {
+impl<T: AuthTokenManagerT> AuthTokenManager<T> {
     #[zbus(property)]
     fn token(&self) -> zbus::fdo::Result<String> {
         self.0.token()
     }
 
-    fn force_token_refresh(
-        &mut self,
-        #[zbus(signal_context)] ctxt: zbus::SignalContext<'_>,
-    ) {
+    /// Whether a valid token is available, see [`Status`].
+    #[zbus(property)]
+    fn status(&self) -> Status {
+        self.0.token_health().status
+    }
+
+    /// Seconds since the unix epoch of the last successful refresh, or 0 if there was none.
+    #[zbus(property)]
+    fn last_refresh(&self) -> u64 {
+        self.0.token_health().last_refresh
+    }
+
+    /// Seconds since the unix epoch of the next scheduled refresh, or 0 if there is none.
+    #[zbus(property)]
+    fn next_refresh(&self) -> u64 {
+        self.0.token_health().next_refresh
+    }
+
+    /// The number of failed refresh attempts since the last successful refresh.
+    #[zbus(property)]
+    fn consecutive_failures(&self) -> u32 {
+        self.0.token_health().consecutive_failures
+    }
+
+    /// The category of the last error while refreshing, or an empty string.
+    #[zbus(property)]
+    fn last_error(&self) -> String {
+        self.0.token_health().last_error
+    }
+
+    fn force_token_refresh(&self, #[zbus(signal_context)] ctxt: SignalContext<'_>) {
         self.0.force_token_refresh(ctxt)
     }
+
+    /// Returns the token scoped to `audience`, requesting it from the backend on first
+    /// use. Every audience's token is cached and refreshed independently.
+    async fn get_token(
+        &self,
+        audience: String,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+    ) -> zbus::fdo::Result<String> {
+        let sender = header.sender().ok_or_else(|| {
+            zbus::fdo::Error::AccessDenied("the caller of GetToken is unknown".into())
+        })?;
+        let caller_uid = zbus::fdo::DBusProxy::new(conn)
+            .await?
+            .get_connection_unix_user(sender.to_owned().into())
+            .await?;
+        self.0.get_token(audience, caller_uid).await
+    }
+
+    /// Emitted whenever the token scoped to `audience` changed.
+    #[zbus(signal, name = "TokenChanged")]
+    pub async fn audience_token_changed(
+        ctxt: &SignalContext<'_>,
+        audience: &str,
+    ) -> zbus::Result<()>;
+}
+
+impl<T: AuthTokenManagerT> AuthTokenManager<T> {
+    /// Notifies subscribers that the [`TokenHealth`] properties changed.
+    pub async fn token_health_changed(
+        &self,
+        ctxt: &SignalContext<'_>,
+    ) -> zbus::Result<()> {
+        self.status_changed(ctxt).await?;
+        self.last_refresh_changed(ctxt).await?;
+        self.next_refresh_changed(ctxt).await?;
+        self.consecutive_failures_changed(ctxt).await?;
+        self.last_error_changed(ctxt).await
+    }
 }
@@ -3,7 +3,8 @@ use std::sync::OnceLock;
 use orb_const_concat::const_concat;
 use reqwest::Client;
 use secrecy::ExposeSecret;
-use tracing::{error, info, warn};
+use tracing::{info, warn};
+use url::{Host, Url};
 
 use crate::BUILD_INFO;
 
@@ -34,6 +35,34 @@ pub fn client() -> &'static Client {
     })
 }
 
+/// Returns the shared http [`Client`] to use for requests to `url`.
+///
+/// Plain HTTP is allowed for loopback addresses, so that a local stand-in for the backend
+/// can be used during development and in CI.
+pub fn client_for(url: &Url) -> &'static Client {
+    static LOOPBACK_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    if !is_loopback(url) {
+        return client();
+    }
+    LOOPBACK_CLIENT.get_or_init(|| {
+        orb_security_utils::reqwest::http_client_builder()
+            .timeout(std::time::Duration::from_secs(60))
+            .user_agent(USER_AGENT)
+            .https_only(false)
+            .build()
+            .expect("Failed to build client")
+    })
+}
+
+fn is_loopback(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(domain)) => domain == "localhost",
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
+
 /// Contact the backend to verify that the token is valid.
 /// Returns Err(..) if failed to connect to the backend.
 /// Returns Ok(true) if the token is valid.
@@ -47,7 +76,7 @@ pub async fn validate_token(
     token: &crate::remote_api::Token,
     ping_url: &url::Url,
 ) -> Result<bool, Error> {
-    let resp = client()
+    let resp = client_for(ping_url)
         .get(ping_url.clone())
         .basic_auth(orb_id, Some(token.token.expose_secret()))
         .send()
@@ -66,3 +95,20 @@ pub async fn validate_token(
     info!(text = msg, "Static token is invalid, but that is ok.");
     Ok(false)
 }
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn only_loopback_urls_are_loopback() {
+        for url in [
+            "http://localhost:8080/",
+            "http://127.0.0.1/",
+            "http://[::1]/",
+        ] {
+            assert!(super::is_loopback(&url.parse().unwrap()), "{url}");
+        }
+        for url in ["https://auth.orb.worldcoin.org/", "http://10.0.0.1/"] {
+            assert!(!super::is_loopback(&url.parse().unwrap()), "{url}");
+        }
+    }
+}
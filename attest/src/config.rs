@@ -1,8 +1,7 @@
-use std::env;
+use orb_endpoints::{backend::BackendFromEnvError, Backend, Endpoints, OrbId};
+use tracing::warn;
 
-use eyre::{self, bail};
-
-const ORB_BACKEND_ENV_VAR_NAME: &str = "ORB_BACKEND";
+const DEFAULT_BACKEND: Backend = Backend::Prod;
 
 pub struct Config {
     pub auth_url: url::Url,
@@ -11,82 +10,78 @@ pub struct Config {
 
 impl Config {
     /// Create a new config for the given `backend` and `orb_id`.
-    ///
-    /// # Panics
-    ///  - If failed to parse the `auth_url` or `ping_url`
     #[must_use]
-    pub fn new(backend: Backend, orb_id: &str) -> Self {
-        let (auth, ping) = match backend {
-            Backend::Prod => ("auth.orb", "management.orb"),
-            Backend::Staging => ("auth.stage.orb", "management.stage.orb"),
-        };
+    pub fn new(backend: Backend, orb_id: &OrbId) -> Self {
+        let Endpoints { auth, ping, .. } = Endpoints::new(backend, orb_id);
         Config {
-            auth_url: url::Url::parse(&format!("https://{auth}.worldcoin.org/api/v1/"))
-                .unwrap(),
-            ping_url: url::Url::parse(&format!(
-                "https://{ping}.worldcoin.org/api/v1/orbs/{orb_id}"
-            ))
-            .unwrap(),
+            auth_url: auth,
+            ping_url: ping,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Backend {
-    Prod,
-    Staging,
-}
-
-const DEFAULT_BACKEND: Backend = Backend::Prod;
-
-impl Default for Backend {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl Backend {
-    /// Create a new backend config instance
-    #[must_use]
-    pub fn new() -> Self {
-        Self::from_env().unwrap_or(DEFAULT_BACKEND)
-    }
-
-    /// Choose the backend based on the `ORB_BACKEND` environment variable.
-    fn from_env() -> eyre::Result<Self> {
-        let v = env::var(ORB_BACKEND_ENV_VAR_NAME)?;
-        match v.trim().to_lowercase().as_str() {
-            "prod" => Ok(Backend::Prod),
-            "stage" | "dev" => Ok(Backend::Staging),
-            _ => {
-                bail!("unknown value for backend");
-            }
+/// Choose the backend based on the `ORB_BACKEND` environment variable, falling back to prod
+/// if it is not set or invalid.
+#[must_use]
+pub fn backend_from_env() -> Backend {
+    match Backend::from_env() {
+        Ok(backend) => backend,
+        Err(BackendFromEnvError::NotSet) => DEFAULT_BACKEND,
+        Err(e) => {
+            warn!("{e}; using the {DEFAULT_BACKEND:?} backend");
+            DEFAULT_BACKEND
         }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use orb_endpoints::{backend::ORB_BACKEND_ENV_VAR_NAME, Backend};
     use serial_test::serial;
 
     #[test]
     #[serial]
     fn default_backend() {
-        assert_eq!(super::Backend::new(), super::DEFAULT_BACKEND);
-        assert_eq!(super::Backend::default(), super::DEFAULT_BACKEND);
+        std::env::remove_var(ORB_BACKEND_ENV_VAR_NAME);
+        assert_eq!(super::backend_from_env(), super::DEFAULT_BACKEND);
     }
 
     #[test]
     #[serial]
-    fn custom_backend() {
-        std::env::set_var(super::ORB_BACKEND_ENV_VAR_NAME, "prod");
-        assert_eq!(super::Backend::new(), super::Backend::Prod);
-        std::env::set_var(super::ORB_BACKEND_ENV_VAR_NAME, "stage");
-        assert_eq!(super::Backend::new(), super::Backend::Staging);
-        std::env::set_var(super::ORB_BACKEND_ENV_VAR_NAME, "dev");
-        assert_eq!(super::Backend::new(), super::Backend::Staging);
-        std::env::set_var(super::ORB_BACKEND_ENV_VAR_NAME, "SOME RANDOM STRING");
-        assert_eq!(super::Backend::new(), super::DEFAULT_BACKEND);
-        std::env::remove_var(super::ORB_BACKEND_ENV_VAR_NAME);
+    fn backend_from_env() {
+        std::env::set_var(ORB_BACKEND_ENV_VAR_NAME, "prod");
+        assert_eq!(super::backend_from_env(), Backend::Prod);
+        std::env::set_var(ORB_BACKEND_ENV_VAR_NAME, "stage");
+        assert_eq!(super::backend_from_env(), Backend::Staging);
+        std::env::set_var(ORB_BACKEND_ENV_VAR_NAME, "dev");
+        assert_eq!(super::backend_from_env(), Backend::Staging);
+        std::env::set_var(ORB_BACKEND_ENV_VAR_NAME, "custom:http://127.0.0.1:8080");
+        assert_eq!(
+            super::backend_from_env(),
+            Backend::Custom {
+                base_url: "http://127.0.0.1:8080".parse().unwrap()
+            }
+        );
+        std::env::set_var(ORB_BACKEND_ENV_VAR_NAME, "custom:mailto:a@b");
+        assert_eq!(super::backend_from_env(), super::DEFAULT_BACKEND);
+        std::env::set_var(ORB_BACKEND_ENV_VAR_NAME, "SOME RANDOM STRING");
+        assert_eq!(super::backend_from_env(), super::DEFAULT_BACKEND);
+        std::env::remove_var(ORB_BACKEND_ENV_VAR_NAME);
+    }
+
+    #[test]
+    fn custom_config() {
+        let backend = Backend::Custom {
+            base_url: "http://127.0.0.1:8080/lab".parse().unwrap(),
+        };
+        let config = super::Config::new(backend, &"ea2ea744".parse().unwrap());
+        assert_eq!(
+            config.auth_url.as_str(),
+            "http://127.0.0.1:8080/lab/api/v1/"
+        );
+        assert_eq!(
+            config.ping_url.as_str(),
+            "http://127.0.0.1:8080/lab/api/v1/orbs/ea2ea744/"
+        );
     }
 }
@@ -8,41 +8,64 @@
 //!
 //! Wait for token refresh
 //! dbus-monitor type='signal',sender='org.worldcoin.AuthTokenManager1'
+//!
+//! Get a token scoped to an audience
+//! gdbus call --session -d org.worldcoin.AuthTokenManager1 -o '/org/worldcoin/AuthTokenManager1' -m
+//! org.worldcoin.AuthTokenManager1.GetToken update-agent
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use eyre::WrapErr;
-use orb_attest_dbus::AuthTokenManagerT;
-use tokio::sync::Notify;
+use orb_attest_dbus::{AuthTokenManagerT, TokenHealth};
+use tokio::{sync::Notify, time::timeout};
 use tracing::instrument;
 use zbus::ConnectionBuilder;
 
+use crate::{
+    health::Health,
+    scoped::{Allowlist, ScopedTokens},
+};
+
+/// How long `GetToken` waits for a new audience's token, below the default dbus timeout.
+const GET_TOKEN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20);
+
 /// Dbus interface for [`AuthTokenManager`].
 pub type AuthTokenManagerIface = orb_attest_dbus::AuthTokenManager<AuthTokenManager>;
 
 pub struct AuthTokenManager {
-    token: Option<String>,
+    token: Mutex<Option<String>>,
     refresh_token_event: Arc<Notify>,
+    scoped_tokens: Arc<ScopedTokens>,
+    allowlist: Allowlist,
+    health: Arc<Health>,
 }
 
 impl AuthTokenManager {
     #[must_use]
-    pub fn new(refresh_token_event: Arc<Notify>) -> Self {
+    pub fn new(
+        refresh_token_event: Arc<Notify>,
+        scoped_tokens: Arc<ScopedTokens>,
+        allowlist: Allowlist,
+        health: Arc<Health>,
+    ) -> Self {
         AuthTokenManager {
-            token: None,
+            token: Mutex::new(None),
             refresh_token_event,
+            scoped_tokens,
+            allowlist,
+            health,
         }
     }
 
-    pub fn update_token(&mut self, token: &str) {
-        self.token = Some(token.to_string());
+    pub fn update_token(&self, token: &str) {
+        *self.token.lock().unwrap() = Some(token.to_string());
     }
 }
 
 impl AuthTokenManagerT for AuthTokenManager {
     #[instrument(skip_all, err)]
     fn token(&self) -> zbus::fdo::Result<String> {
-        match self.token.as_deref() {
+        match self.token.lock().unwrap().as_deref() {
             Some("") => Err(zbus::fdo::Error::Failed(
                 "token was set, but is empty string".into(),
             )),
@@ -54,8 +77,44 @@ impl AuthTokenManagerT for AuthTokenManager {
     }
 
     #[instrument(skip_all)]
-    fn force_token_refresh(&mut self, _ctxt: zbus::SignalContext<'_>) {
+    fn force_token_refresh(&self, _ctxt: zbus::SignalContext<'_>) {
         self.refresh_token_event.notify_one();
+        self.scoped_tokens.force_refresh();
+    }
+
+    fn token_health(&self) -> TokenHealth {
+        self.health.token_health()
+    }
+
+    #[instrument(skip(self), err)]
+    async fn get_token(
+        &self,
+        audience: String,
+        caller_uid: u32,
+    ) -> zbus::fdo::Result<String> {
+        if !self.allowlist.is_allowed(&audience, caller_uid) {
+            return Err(zbus::fdo::Error::AccessDenied(format!(
+                "uid {caller_uid} is not allowed to get tokens for audience `{audience}`"
+            )));
+        }
+        let mut token = self
+            .scoped_tokens
+            .subscribe(&audience)
+            .map_err(|e| zbus::fdo::Error::InvalidArgs(e.to_string()))?;
+        let token = timeout(GET_TOKEN_TIMEOUT, token.wait_for(Option::is_some))
+            .await
+            .map_err(|_| {
+                zbus::fdo::Error::Failed(format!(
+                    "token for audience `{audience}` was not yet retrieved from backend, \
+                     wait for the TokenChanged signal"
+                ))
+            })?
+            .map_err(|_| {
+                zbus::fdo::Error::Failed(format!(
+                    "stopped refreshing the token for audience `{audience}`"
+                ))
+            })?;
+        Ok(token.clone().expect("waited for the token to be set"))
     }
 }
 
@@ -70,8 +129,12 @@ impl AuthTokenManagerT for AuthTokenManager {
 /// - if failed to connect to the session bus or create the service
 pub async fn create_dbus_connection(
     refresh_token_event: Arc<Notify>,
+    scoped_tokens: Arc<ScopedTokens>,
+    allowlist: Allowlist,
+    health: Arc<Health>,
 ) -> eyre::Result<zbus::Connection> {
-    let auth_token_manager = AuthTokenManager::new(refresh_token_event);
+    let auth_token_manager =
+        AuthTokenManager::new(refresh_token_event, scoped_tokens, allowlist, health);
     let dbus = ConnectionBuilder::session()
         .wrap_err("failed to establish user session dbus connection")?
         .name("org.worldcoin.AuthTokenManager1")
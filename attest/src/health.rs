@@ -0,0 +1,166 @@
+//! Tracks the lifecycle of the orb's default token, so that other services can tell why
+//! there is no token without digging through the journal.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use orb_attest_dbus::{Status, TokenHealth};
+use tokio::{sync::Notify, time::Instant};
+
+use crate::remote_api::{RefreshTokenError, Token};
+
+#[derive(Debug, Default)]
+struct State {
+    has_token: bool,
+    /// `None` if the token never expires.
+    expires_at: Option<Instant>,
+    last_refresh: Option<SystemTime>,
+    next_refresh: Option<SystemTime>,
+    consecutive_failures: u32,
+    last_error: Option<&'static str>,
+}
+
+#[derive(Debug, Default)]
+pub struct Health {
+    state: Mutex<State>,
+    changed: Notify,
+}
+
+impl Health {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `token` was retrieved and will be refreshed after `refresh_in`.
+    pub fn refreshed(&self, token: &Token, refresh_in: Duration) {
+        let now = SystemTime::now();
+        let mut state = self.state.lock().unwrap();
+        state.has_token = true;
+        state.expires_at = Instant::now().checked_add(token.valid_for());
+        state.last_refresh = Some(now);
+        state.next_refresh = now.checked_add(refresh_in);
+        state.consecutive_failures = 0;
+        drop(state);
+        self.changed.notify_one();
+    }
+
+    /// Records a failed attempt to refresh the token.
+    pub fn refresh_failed(&self, error: &RefreshTokenError) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        state.last_error = Some(error.category());
+        state.next_refresh = None;
+        drop(state);
+        self.changed.notify_one();
+    }
+
+    /// Waits until the health changed since the last call, including the token expiring.
+    pub async fn changed(&self) {
+        let expires_at = self.state.lock().unwrap().expires_at;
+        match expires_at {
+            Some(expires_at) if Instant::now() < expires_at => {
+                tokio::select! {
+                    () = self.changed.notified() => {}
+                    () = tokio::time::sleep_until(expires_at) => {}
+                }
+            }
+            _ => self.changed.notified().await,
+        }
+    }
+
+    #[must_use]
+    pub fn token_health(&self) -> TokenHealth {
+        let state = self.state.lock().unwrap();
+        let valid = state.has_token
+            && state
+                .expires_at
+                .map_or(true, |expires_at| Instant::now() < expires_at);
+        let status = match (valid, state.consecutive_failures) {
+            (false, _) => Status::NoToken,
+            (true, 0) => Status::Ok,
+            (true, _) => Status::Degraded,
+        };
+        TokenHealth {
+            status,
+            last_refresh: unix_secs(state.last_refresh),
+            next_refresh: unix_secs(state.next_refresh),
+            consecutive_failures: state.consecutive_failures,
+            last_error: state.last_error.unwrap_or_default().to_owned(),
+        }
+    }
+}
+
+fn unix_secs(time: Option<SystemTime>) -> u64 {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since_epoch| since_epoch.as_secs())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use orb_attest_dbus::Status;
+    use tokio::time::Instant;
+
+    use super::Health;
+    use crate::remote_api::{RefreshTokenError, SignError, Token};
+
+    fn token(duration_secs: u64) -> Token {
+        serde_json::from_value(serde_json::json!({
+            "token": "token",
+            "duration": duration_secs,
+            "expiryTime": "is not used by client",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn status_follows_refreshes() {
+        let health = Health::new();
+        let initial = health.token_health();
+        assert_eq!(initial.status, Status::NoToken);
+        assert_eq!((initial.last_refresh, initial.next_refresh), (0, 0));
+        assert_eq!(initial.last_error, "");
+
+        let not_provisioned = RefreshTokenError::SignError(SignError::NotProvisioned);
+        health.refresh_failed(&not_provisioned);
+        let failed = health.token_health();
+        assert_eq!(failed.status, Status::NoToken);
+        assert_eq!(failed.consecutive_failures, 1);
+        assert_eq!(failed.last_error, "sign.not_provisioned");
+
+        health.refreshed(&token(3600), Duration::from_secs(1800));
+        let refreshed = health.token_health();
+        assert_eq!(refreshed.status, Status::Ok);
+        assert_eq!(refreshed.consecutive_failures, 0);
+        assert_eq!(refreshed.next_refresh - refreshed.last_refresh, 1800);
+        assert_eq!(refreshed.last_error, "sign.not_provisioned");
+
+        health.refresh_failed(&RefreshTokenError::ChallengeExpired);
+        health.refresh_failed(&RefreshTokenError::ChallengeExpired);
+        let degraded = health.token_health();
+        assert_eq!(degraded.status, Status::Degraded);
+        assert_eq!(degraded.consecutive_failures, 2);
+        assert_eq!(degraded.last_error, "challenge.expired");
+
+        health.refreshed(&token(0), Duration::ZERO);
+        assert_eq!(health.token_health().status, Status::NoToken);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expiry_is_a_change() {
+        let health = Health::new();
+        health.refreshed(&token(60), Duration::from_secs(30));
+        health.refresh_failed(&RefreshTokenError::ChallengeExpired);
+        health.changed().await;
+        assert_eq!(health.token_health().status, Status::Degraded);
+
+        let start = Instant::now();
+        health.changed().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(60));
+        assert_eq!(health.token_health().status, Status::NoToken);
+    }
+}
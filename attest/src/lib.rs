@@ -3,7 +3,10 @@
 pub mod client;
 pub mod config;
 pub mod dbus;
+pub mod health;
 pub mod remote_api;
+pub mod scoped;
+pub mod signer;
 
 use std::sync::Arc;
 
@@ -11,10 +14,16 @@ use eyre::{self, bail, WrapErr};
 use futures::{FutureExt, StreamExt};
 use orb_build_info::{make_build_info, BuildInfo};
 use secrecy::ExposeSecret;
-use tokio::{select, sync::Notify, time::sleep};
+use tokio::{
+    select,
+    sync::{mpsc, Notify},
+    time::sleep,
+};
 use tracing::{info, warn};
 use url::Url;
 
+use crate::{health::Health, scoped::ScopedTokens, signer::Signer};
+
 const BUILD_INFO: BuildInfo = make_build_info!();
 
 const HTTP_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(3);
@@ -27,20 +36,70 @@ pub async fn main() -> eyre::Result<()> {
 
     let orb_id =
         std::env::var("ORB_ID").wrap_err("env variable `ORB_ID` should be set")?;
-    let config = config::Config::new(config::Backend::new(), &orb_id);
+    let config = config::Config::new(
+        config::backend_from_env(),
+        &orb_id
+            .parse()
+            .wrap_err("env variable `ORB_ID` should be a hex encoded orb id")?,
+    );
+    let signer = signer::from_env().wrap_err("failed to set up the signer")?;
+    info!("signing challenges with {signer:?}");
 
     let force_refresh_token = Arc::new(Notify::new());
+    let (scoped_changed_tx, mut scoped_changed_rx) = mpsc::unbounded_channel();
+    let scoped_tokens = Arc::new(ScopedTokens::new(
+        orb_id.clone(),
+        config.auth_url.clone(),
+        Arc::clone(&signer),
+        scoped_changed_tx,
+    ));
 
-    let iface_ref = setup_dbus(force_refresh_token.clone())
-        .await
-        .wrap_err("Initialization failed")?;
+    let allowlist = scoped::Allowlist::from_env()
+        .wrap_err("failed to read the audiences that callers may get tokens for")?;
+
+    let health = Arc::new(Health::new());
+
+    let iface_ref = setup_dbus(
+        force_refresh_token.clone(),
+        scoped_tokens,
+        allowlist,
+        health.clone(),
+    )
+    .await
+    .wrap_err("Initialization failed")?;
     let conn = iface_ref.signal_context().connection().clone();
+    let signal_ctx = iface_ref.signal_context().clone();
+    let scoped_signal_task = tokio::spawn(async move {
+        while let Some(audience) = scoped_changed_rx.recv().await {
+            dbus::AuthTokenManagerIface::audience_token_changed(&signal_ctx, &audience)
+                .await
+                .wrap_err("failed to send TokenChanged signal")?;
+        }
+        Ok(())
+    });
+    let health_iface_ref = iface_ref.clone();
+    let health_signal_task: tokio::task::JoinHandle<eyre::Result<()>> = tokio::spawn({
+        let health = health.clone();
+        async move {
+            loop {
+                health.changed().await;
+                health_iface_ref
+                    .get()
+                    .await
+                    .token_health_changed(health_iface_ref.signal_context())
+                    .await
+                    .wrap_err("failed to send token health changed signals")?;
+            }
+        }
+    });
     let run_fut = run(
         &orb_id,
         iface_ref,
         force_refresh_token.clone(),
         config.auth_url,
         config.ping_url,
+        signer,
+        health,
     );
 
     let mut msg_stream = zbus::MessageStream::from(conn);
@@ -49,24 +108,31 @@ pub async fn main() -> eyre::Result<()> {
         bail!("Lost DBus connection")
     });
 
-    let ((), ()) = tokio::try_join!(
+    let ((), (), (), ()) = tokio::try_join!(
         run_fut.map(|r| r.wrap_err("main task errored")),
         dbus_monitor_task
-            .map(|r| r.wrap_err("dbus monitor task terminated abnormally")?)
+            .map(|r| r.wrap_err("dbus monitor task terminated abnormally")?),
+        scoped_signal_task
+            .map(|r| r.wrap_err("scoped token signal task terminated abnormally")?),
+        health_signal_task
+            .map(|r| r.wrap_err("token health signal task terminated abnormally")?)
     )?;
     Ok(())
 }
 
 /// Return either a *proovenly working* static token, or a short lived token.
-#[tracing::instrument]
+#[tracing::instrument(skip(health))]
 async fn get_working_token(
     orb_id: &str,
     auth_url: &Url,
     ping_url: &Url,
+    signer: &Arc<dyn Signer>,
+    health: &Health,
 ) -> crate::remote_api::Token {
+    let on_error = |e: &remote_api::RefreshTokenError| health.refresh_failed(e);
     select! {
         Ok(token) = get_working_static_token(orb_id, ping_url) => token,
-        token = remote_api::get_token(orb_id, auth_url) => token,
+        token = remote_api::get_token(orb_id, auth_url, signer, None, on_error) => token,
     }
 }
 
@@ -89,10 +155,7 @@ async fn get_working_static_token(
             }
             // TODO make this error more specific
             Ok(false) => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "token was rejected by the backend",
-                ));
+                return Err(std::io::Error::other("token was rejected by the backend"));
             }
             Err(e) => {
                 failure_counter += 1;
@@ -104,13 +167,21 @@ async fn get_working_static_token(
     }
 }
 
-#[tracing::instrument]
+#[tracing::instrument(skip(scoped_tokens, health))]
 async fn setup_dbus(
     force_refresh_token: Arc<Notify>,
+    scoped_tokens: Arc<ScopedTokens>,
+    allowlist: scoped::Allowlist,
+    health: Arc<Health>,
 ) -> eyre::Result<zbus::InterfaceRef<crate::dbus::AuthTokenManagerIface>> {
-    let dbus = dbus::create_dbus_connection(force_refresh_token)
-        .await
-        .wrap_err("failed to create DBus connection")?;
+    let dbus = dbus::create_dbus_connection(
+        force_refresh_token,
+        scoped_tokens,
+        allowlist,
+        health,
+    )
+    .await
+    .wrap_err("failed to create DBus connection")?;
 
     let object_server = dbus.object_server();
     let iface_ref = object_server
@@ -127,19 +198,24 @@ async fn run(
     force_refresh_token: Arc<Notify>,
     auth_url: Url,
     ping_url: Url,
+    signer: Arc<dyn Signer>,
+    health: Arc<Health>,
 ) -> eyre::Result<()> {
     loop {
-        let token = get_working_token(orb_id, &auth_url, &ping_url).await;
+        let token =
+            get_working_token(orb_id, &auth_url, &ping_url, &signer, &health).await;
         let token_refresh_delay = token.get_best_refresh_time();
-        // get_mut() blocks access to the iface_ref object. So we never bind its result to be safe.
-        // https://docs.rs/zbus/3.7.0/zbus/struct.InterfaceRef.html#method.get_mut
+        health.refreshed(&token, token_refresh_delay);
+        // get() blocks mutable access to the iface_ref object. So we never bind its result to be
+        // safe.
+        // https://docs.rs/zbus/4.4.0/zbus/object_server/struct.InterfaceRef.html#method.get
         iface_ref
-            .get_mut()
+            .get()
             .await
             .0
             .update_token(token.token.expose_secret());
         iface_ref
-            .get_mut()
+            .get()
             .await
             .token_changed(iface_ref.signal_context())
             .await
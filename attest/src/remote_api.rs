@@ -1,22 +1,17 @@
 use data_encoding::BASE64;
-use ring::{digest, digest::digest};
 use secrecy::SecretString;
 use serde::{Deserialize, Serialize};
 use serde_with::{base64::Base64, serde_as};
-use std::num::Saturating;
-use std::sync::RwLock;
-use std::{
-    fmt,
-    io::Write,
-    process::{Command, Stdio},
-};
+use std::{fmt, sync::Arc};
 use tokio::{
     fs::read_to_string,
     time::{self, sleep},
 };
-use tracing::{error, event, info, warn, Level};
+use tracing::{error, info, warn};
 use url::Url;
 
+use crate::signer::Signer;
+
 /// Path to persistent token, don't use it directly, use `Token::from_usr_persistent()` instead
 #[cfg(test)]
 const STATIC_TOKEN_PATH: &str = "./test_token";
@@ -35,13 +30,6 @@ const NUMBER_OF_TOKEN_FETCH_RETRIES: u32 = NUMBER_OF_CHALLENGE_RETRIES;
 /// How long to wait before retrying to fetch the token
 const TOKEN_DELAY: time::Duration = CHALLENGE_DELAY;
 
-/// Sometimes the signing tool fails because SE050 is not responding, the
-/// only known way to recover it is to powercycle the whole security MCU.
-/// If INTERNAL_ERROR_COUNT hits INTERNAL_ERROR_COUNT_THRESHOLD, powercycle the security MCU but do that only once per boot.
-/// If signing succeeds, the counter is reset to 0.
-static INTERNAL_ERROR_COUNT: RwLock<Saturating<u32>> = RwLock::new(Saturating(0));
-const INTERNAL_ERROR_COUNT_THRESHOLD: Saturating<u32> = Saturating(3);
-
 #[derive(Debug, thiserror::Error)]
 pub enum ChallengeError {
     #[error("failed to initialized HTTP client: {}", .0)]
@@ -80,6 +68,8 @@ pub enum SignError {
     Timeout,
     #[error("incomprehensible output: {}, original output: \"{}\"", .0, .1)]
     BadOutput(#[source] data_encoding::DecodeError, String),
+    #[error("software key failed to sign the challenge")]
+    SoftwareSignFailed,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -108,6 +98,68 @@ pub enum RefreshTokenError {
     JoinError(#[source] tokio::task::JoinError),
 }
 
+impl ChallengeError {
+    /// A short, stable name for the kind of error, e.g. for monitoring.
+    #[must_use]
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::HTTPClientInitFailed(_) => "challenge.http_client",
+            Self::PostFailed(_) => "challenge.request",
+            Self::JsonParseFailed(_) => "challenge.parse",
+            Self::ServerReturnedError(..) => "challenge.server_error",
+        }
+    }
+}
+
+impl SignError {
+    /// A short, stable name for the kind of error, e.g. for monitoring.
+    #[must_use]
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::NoSignBinary => "sign.no_sign_binary",
+            Self::SpawnFailed(_) => "sign.spawn",
+            Self::WriteFailed(_) => "sign.write",
+            Self::ReadFailed(_) => "sign.read",
+            Self::SignFailed => "sign.failed",
+            Self::NotProvisioned => "sign.not_provisioned",
+            Self::BadInput => "sign.bad_input",
+            Self::InternalError => "sign.internal",
+            Self::NonZeroExitCode(_) => "sign.exit_code",
+            Self::TerminatedBySignal => "sign.signal",
+            Self::Timeout => "sign.timeout",
+            Self::BadOutput(..) => "sign.bad_output",
+            Self::SoftwareSignFailed => "sign.software",
+        }
+    }
+}
+
+impl TokenError {
+    /// A short, stable name for the kind of error, e.g. for monitoring.
+    #[must_use]
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::PostFailed(_) => "token.request",
+            Self::ServerReturnedError(..) => "token.server_error",
+            Self::JsonParseFailed(_) => "token.parse",
+            Self::EmptyResponse => "token.empty",
+        }
+    }
+}
+
+impl RefreshTokenError {
+    /// The category of the underlying [`ChallengeError`], [`SignError`] or [`TokenError`].
+    #[must_use]
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::ChallengeError(e) => e.category(),
+            Self::SignError(e) => e.category(),
+            Self::TokenError(e) => e.category(),
+            Self::ChallengeExpired => "challenge.expired",
+            Self::JoinError(_) => "sign.panicked",
+        }
+    }
+}
+
 /// helper for concealing part of a secret from the log.
 /// splits the secret in three parts and print the first and last part
 fn format_secret(val: &str) -> String {
@@ -117,16 +169,6 @@ fn format_secret(val: &str) -> String {
     format!("{begin:?}..{end:?}")
 }
 
-fn powercycle_security_mcu() -> std::io::Result<()> {
-    Command::new("/usr/bin/orb-mcu-util")
-        .arg("reboot")
-        .arg("security")
-        .output()?;
-    // It takes security MCU 3 seconds so reboot.
-    std::thread::sleep(std::time::Duration::from_secs(5));
-    Ok(())
-}
-
 #[serde_as]
 #[derive(Deserialize, Clone)]
 #[allow(dead_code)]
@@ -149,7 +191,7 @@ pub struct Challenge {
 impl Challenge {
     #[tracing::instrument]
     pub async fn request(orb_id: &str, url: &url::Url) -> Result<Self, ChallengeError> {
-        let client = crate::client::client();
+        let client = crate::client::client_for(url);
 
         let req = serde_json::json!({
             "orbId": orb_id,
@@ -196,65 +238,12 @@ impl Challenge {
         elapsed > (self.duration / 2)
     }
 
-    /// Try to sign the challenge using SE050. Could fail for multiple reasons,
+    /// Try to sign the challenge with `signer`. Could fail for multiple reasons,
     /// it is probably a good idea to retry signing.
     #[tracing::instrument]
-    pub fn sign(&self) -> Result<Signature, SignError> {
-        let digest = digest(&digest::SHA256, &self.challenge);
-
-        let encoded = BASE64.encode(digest.as_ref());
-
-        let command = Command::new("orb-sign-attestation")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(SignError::SpawnFailed)?;
-
-        writeln!(
-            command.stdin.as_ref().expect("child should have stdin"),
-            "{encoded}"
-        )
-        .map_err(SignError::WriteFailed)?;
-
-        // TODO add timeout of 10-20 seconds (or use tokio::process::CommandExt::timeout)
-        let output = command.wait_with_output().map_err(SignError::ReadFailed)?;
-        let sign_tool_log = String::from_utf8_lossy(&output.stderr);
-
-        // TODO check errkind
-        if !output.status.success() {
-            event!(Level::ERROR, sign_tool_log = ?sign_tool_log, orb_sign_attestation_success = output.status.success(), orb_sign_attestation_code = output.status.code());
-            return match output.status.code() {
-                Some(127) => Err(SignError::NoSignBinary),
-                Some(1) => Err(SignError::SignFailed),
-                Some(2) => Err(SignError::Timeout),
-                Some(3) => Err(SignError::NotProvisioned),
-                Some(4) => Err(SignError::BadInput),
-                Some(5) => {
-                    let mut lock = INTERNAL_ERROR_COUNT.write().unwrap();
-                    *lock += 1;
-                    if *lock == INTERNAL_ERROR_COUNT_THRESHOLD {
-                        if let Err(err) = powercycle_security_mcu() {
-                            error!("Failed to powercycle Security MCU: {err}")
-                        } else {
-                            info!("Powercycled Security MCU, trying to reset stuck se050.");
-                        }
-                    }
-                    Err(SignError::InternalError)
-                }
-                Some(code) => Err(SignError::NonZeroExitCode(code)),
-                None => Err(SignError::TerminatedBySignal),
-            };
-        } else {
-            *INTERNAL_ERROR_COUNT.write().unwrap() = Saturating(0);
-        }
+    pub fn sign(&self, signer: &dyn Signer) -> Result<Signature, SignError> {
         Ok(Signature {
-            signature: BASE64.decode(&output.stdout).map_err(|e| {
-                SignError::BadOutput(
-                    e,
-                    String::from_utf8_lossy(&output.stdout).to_string(),
-                )
-            })?,
+            signature: signer.sign(&self.challenge)?,
         })
     }
 }
@@ -301,6 +290,9 @@ struct TokenRequest {
     #[serde(rename = "signature")]
     #[serde_as(as = "Base64")]
     signature: Vec<u8>,
+    /// Scopes the token to an audience instead of requesting the orb's default token.
+    #[serde(rename = "audience", skip_serializing_if = "Option::is_none")]
+    audience: Option<String>,
 }
 
 #[serde_as]
@@ -328,8 +320,9 @@ impl Token {
         orb_id: &str,
         challenge: &Challenge,
         signature: &Signature,
+        audience: Option<&str>,
     ) -> Result<Self, TokenError> {
-        let client = crate::client::client();
+        let client = crate::client::client_for(url);
 
         info!("requesting token from {}", url);
 
@@ -337,6 +330,7 @@ impl Token {
             orb_id: orb_id.to_string(),
             challenge: challenge.challenge.clone(),
             signature: signature.signature.clone(),
+            audience: audience.map(str::to_owned),
         };
 
         let resp = client
@@ -377,6 +371,12 @@ impl Token {
         elapsed > (self.duration - std::time::Duration::from_secs(3600))
     }
 
+    /// How much longer the token is valid, according to the auth server.
+    #[must_use]
+    pub fn valid_for(&self) -> std::time::Duration {
+        self.duration.saturating_sub(self.start_time.elapsed())
+    }
+
     #[must_use]
     pub fn get_best_refresh_time(&self) -> std::time::Duration {
         let elapsed = self.start_time.elapsed();
@@ -407,6 +407,8 @@ async fn get_token_inner(
     orb_id: &str,
     token_challenge: &url::Url,
     token_fetch: &url::Url,
+    signer: &Arc<dyn Signer>,
+    audience: Option<&str>,
 ) -> Result<Token, RefreshTokenError> {
     let mut retry = 0;
 
@@ -435,9 +437,10 @@ async fn get_token_inner(
         }
 
         let clone_of_challenge = challenge.clone();
+        let signer = Arc::clone(signer);
         let val = tokio::task::spawn_blocking(move || {
             clone_of_challenge
-                .sign()
+                .sign(signer.as_ref())
                 .map_err(RefreshTokenError::SignError)
         })
         .await
@@ -461,7 +464,7 @@ async fn get_token_inner(
         if challenge.expired() {
             return Err(RefreshTokenError::ChallengeExpired);
         }
-        let val = Token::request(token_fetch, orb_id, &challenge, &signature)
+        let val = Token::request(token_fetch, orb_id, &challenge, &signature, audience)
             .await
             .map_err(RefreshTokenError::TokenError);
         if retry >= NUMBER_OF_TOKEN_FETCH_RETRIES {
@@ -480,21 +483,31 @@ async fn get_token_inner(
     Ok(token)
 }
 
-/// Try to refresh the token until succeeds
+/// Try to refresh the token until succeeds, scoped to `audience` if given. Every failed
+/// attempt is passed to `on_error`.
 ///
 /// Panics
 ///
 /// if fails to construct API URL
-#[tracing::instrument]
-pub async fn get_token(orb_id: &str, base_url: &Url) -> Token {
+#[tracing::instrument(skip(on_error))]
+pub async fn get_token(
+    orb_id: &str,
+    base_url: &Url,
+    signer: &Arc<dyn Signer>,
+    audience: Option<&str>,
+    on_error: impl Fn(&RefreshTokenError),
+) -> Token {
     let tokenchallenge_url = base_url.join("tokenchallenge").unwrap();
     let token_url = base_url.join("token").unwrap();
 
     loop {
-        match get_token_inner(orb_id, &tokenchallenge_url, &token_url).await {
+        match get_token_inner(orb_id, &tokenchallenge_url, &token_url, signer, audience)
+            .await
+        {
             Ok(token) => return token,
             Err(e) => {
                 error!("failed to get token: {}", e);
+                on_error(&e);
                 sleep(TOKEN_DELAY).await;
             }
         }
@@ -512,7 +525,9 @@ mod test {
         Mock, MockServer, ResponseTemplate,
     };
 
+    // Reads the digest first, so that writing it never fails with a broken pipe.
     const MOCK_ORB_SIGN_ATTESTATION: &str = r#"#!/bin/sh
+read -r _digest
 printf dmFsaWRzaWduYXR1cmU=
 "#;
     // A happy path
@@ -579,9 +594,11 @@ printf dmFsaWRzaWduYXR1cmU=
         std::env::set_var("PATH", path);
 
         // 2. sign challenge
-        let signature = tokio::task::spawn_blocking(move || clone_of_challenge.sign())
-            .await
-            .unwrap();
+        let signature = tokio::task::spawn_blocking(move || {
+            clone_of_challenge.sign(&crate::signer::SecureElement)
+        })
+        .await
+        .unwrap();
 
         assert!(
             signature.is_ok(),
@@ -595,9 +612,83 @@ printf dmFsaWRzaWduYXR1cmU=
             orb_id,
             &challenge,
             &signature.unwrap(),
+            None,
         )
         .await
         .unwrap();
         assert_eq!(server_token, token.token.expose_secret());
     }
+
+    // The whole flow against a stand-in auth server, without a secure element
+    #[tokio::test]
+    async fn get_token_with_software_key() {
+        use ring::{
+            rand::SystemRandom,
+            signature::{
+                EcdsaKeyPair, KeyPair as _, UnparsedPublicKey, ECDSA_P256_SHA256_ASN1,
+                ECDSA_P256_SHA256_ASN1_SIGNING,
+            },
+        };
+
+        let mock_server = MockServer::start().await;
+        let challenge =
+            b"challenge_token_DDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDD";
+        Mock::given(method("POST"))
+            .and(path("/api/v1/tokenchallenge"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "challenge": BASE64.encode(challenge),
+                "duration": 3600,
+                "expiryTime": "is not used by client",
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "token": "token_EEEE",
+                "duration": 36000,
+                "expiryTime": "is not used by client",
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(
+            &ECDSA_P256_SHA256_ASN1_SIGNING,
+            &SystemRandom::new(),
+        )
+        .unwrap();
+        let public_key =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref())
+                .unwrap()
+                .public_key()
+                .as_ref()
+                .to_vec();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let key_path = temp_dir.path().join("key.der");
+        std::fs::write(&key_path, pkcs8.as_ref()).unwrap();
+        let signer: std::sync::Arc<dyn crate::signer::Signer> = std::sync::Arc::new(
+            crate::signer::SoftwareKey::from_file(&key_path).unwrap(),
+        );
+
+        let base_url = format!("{}/api/v1/", mock_server.uri()).parse().unwrap();
+        let token =
+            crate::remote_api::get_token("TEST_ORB", &base_url, &signer, None, |_| ())
+                .await;
+        assert_eq!(token.token.expose_secret(), "token_EEEE");
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let token_request: serde_json::Value = requests
+            .iter()
+            .find(|request| request.url.path() == "/api/v1/token")
+            .unwrap()
+            .body_json()
+            .unwrap();
+        assert_eq!(token_request["orbId"], "TEST_ORB");
+        let signature = BASE64
+            .decode(token_request["signature"].as_str().unwrap().as_bytes())
+            .unwrap();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, public_key)
+            .verify(challenge, &signature)
+            .unwrap();
+    }
 }
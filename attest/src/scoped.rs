@@ -0,0 +1,321 @@
+//! Tokens scoped to an audience, requested on demand through `GetToken`.
+//!
+//! Every audience gets its own refresh task, so the tokens expire and are refreshed
+//! independently of each other and of the orb's default token.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use eyre::{eyre, WrapErr as _};
+
+use secrecy::ExposeSecret;
+use tokio::{
+    select,
+    sync::{mpsc, watch, Notify},
+    time::sleep,
+};
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{remote_api, signer::Signer};
+
+/// The maximum number of audiences, to bound the number of refresh tasks that callers
+/// can start.
+pub const MAX_AUDIENCES: usize = 16;
+const MAX_AUDIENCE_LEN: usize = 128;
+
+const ORB_ATTEST_AUDIENCES_ENV_VAR_NAME: &str = "ORB_ATTEST_AUDIENCES";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AudienceError {
+    #[error("audience must not be empty")]
+    Empty,
+    #[error(
+        "audience must be at most {MAX_AUDIENCE_LEN} printable ascii characters without \
+         whitespace"
+    )]
+    Invalid,
+    #[error("tokens are already scoped to the maximum of {MAX_AUDIENCES} audiences")]
+    TooMany,
+}
+
+pub struct ScopedTokens {
+    orb_id: String,
+    auth_url: Url,
+    signer: Arc<dyn Signer>,
+    refresh_event: Notify,
+    changed: mpsc::UnboundedSender<String>,
+    tokens: Mutex<HashMap<String, watch::Receiver<Option<String>>>>,
+}
+
+impl ScopedTokens {
+    /// Creates an empty set of scoped tokens. The audience of every token that changed is
+    /// sent to `changed`.
+    #[must_use]
+    pub fn new(
+        orb_id: String,
+        auth_url: Url,
+        signer: Arc<dyn Signer>,
+        changed: mpsc::UnboundedSender<String>,
+    ) -> Self {
+        Self {
+            orb_id,
+            auth_url,
+            signer,
+            refresh_event: Notify::new(),
+            changed,
+            tokens: Mutex::default(),
+        }
+    }
+
+    /// Returns a receiver for the token scoped to `audience`, which is `None` until the
+    /// token was retrieved. Starts refreshing the token if the audience is new.
+    ///
+    /// # Errors
+    /// - if `audience` is not a valid audience
+    /// - if there are already [`MAX_AUDIENCES`] audiences
+    pub fn subscribe(
+        self: &Arc<Self>,
+        audience: &str,
+    ) -> Result<watch::Receiver<Option<String>>, AudienceError> {
+        validate_audience(audience)?;
+        let mut tokens = self.tokens.lock().unwrap();
+        if let Some(rx) = tokens.get(audience) {
+            return Ok(rx.clone());
+        }
+        if tokens.len() >= MAX_AUDIENCES {
+            return Err(AudienceError::TooMany);
+        }
+        info!("requesting a token scoped to audience `{audience}`");
+        let (tx, rx) = watch::channel(None);
+        tokens.insert(audience.to_owned(), rx.clone());
+        tokio::spawn(Arc::clone(self).refresh(audience.to_owned(), tx));
+        Ok(rx)
+    }
+
+    /// Refreshes all scoped tokens right away.
+    pub fn force_refresh(&self) {
+        self.refresh_event.notify_waiters();
+    }
+
+    async fn refresh(
+        self: Arc<Self>,
+        audience: String,
+        tx: watch::Sender<Option<String>>,
+    ) {
+        loop {
+            let token = remote_api::get_token(
+                &self.orb_id,
+                &self.auth_url,
+                &self.signer,
+                Some(&audience),
+                |_| (),
+            )
+            .await;
+            let token_refresh_delay = token.get_best_refresh_time();
+            tx.send_replace(Some(token.token.expose_secret().to_owned()));
+            if self.changed.send(audience.clone()).is_err() {
+                warn!("nobody is notified about changes of the token for `{audience}`");
+            }
+
+            select! {
+                () = sleep(token_refresh_delay) => {
+                    info!("token for `{audience}` is about to expire, refreshing it");
+                }
+                () = self.refresh_event.notified() => {
+                    info!("refresh was requested, refreshing the token for `{audience}`");
+                }
+            };
+        }
+    }
+}
+
+/// The unix users that may get tokens for each audience, e.g. `update-agent=0;fleet=1000,1001`.
+///
+/// Tokens authenticate the orb, so no caller gets a token for an audience that is not listed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Allowlist(HashMap<String, HashSet<u32>>);
+
+impl Allowlist {
+    /// Reads the allowlist from the `ORB_ATTEST_AUDIENCES` environment variable. No audiences
+    /// are allowed if it is not set.
+    ///
+    /// # Errors
+    /// If the variable is set but can't be parsed.
+    pub fn from_env() -> eyre::Result<Self> {
+        let Ok(v) = std::env::var(ORB_ATTEST_AUDIENCES_ENV_VAR_NAME) else {
+            return Ok(Self::default());
+        };
+        v.parse().wrap_err_with(|| {
+            format!("invalid {ORB_ATTEST_AUDIENCES_ENV_VAR_NAME}: `{v}`")
+        })
+    }
+
+    /// Whether the unix user `uid` may get tokens for `audience`.
+    #[must_use]
+    pub fn is_allowed(&self, audience: &str, uid: u32) -> bool {
+        self.0.get(audience).is_some_and(|uids| uids.contains(&uid))
+    }
+}
+
+impl FromStr for Allowlist {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut allowlist = HashMap::new();
+        for entry in s.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (audience, uids) = entry.split_once('=').ok_or_else(|| {
+                eyre!("expected `<audience>=<uid>,...`, got `{entry}`")
+            })?;
+            let audience = audience.trim();
+            validate_audience(audience)
+                .wrap_err_with(|| format!("invalid audience `{audience}`"))?;
+            let uids = uids
+                .split(',')
+                .map(|uid| {
+                    uid.trim()
+                        .parse()
+                        .wrap_err_with(|| format!("invalid uid `{uid}`"))
+                })
+                .collect::<eyre::Result<HashSet<u32>>>()?;
+            allowlist.insert(audience.to_owned(), uids);
+        }
+        Ok(Self(allowlist))
+    }
+}
+
+fn validate_audience(audience: &str) -> Result<(), AudienceError> {
+    if audience.is_empty() {
+        return Err(AudienceError::Empty);
+    }
+    if audience.len() > MAX_AUDIENCE_LEN
+        || !audience.bytes().all(|b| b.is_ascii_graphic())
+    {
+        return Err(AudienceError::Invalid);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use data_encoding::BASE64;
+    use tokio::sync::mpsc;
+    use wiremock::{
+        matchers::{body_partial_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{Allowlist, AudienceError, ScopedTokens, MAX_AUDIENCES};
+
+    async fn mock_auth_server() -> MockServer {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/tokenchallenge"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "challenge": BASE64.encode(b"challenge"),
+                "duration": 3600,
+                "expiryTime": "is not used by client",
+            })))
+            .mount(&mock_server)
+            .await;
+        for audience in ["update-agent", "backend-state"] {
+            Mock::given(method("POST"))
+                .and(path("/api/v1/token"))
+                .and(body_partial_json(
+                    serde_json::json!({ "audience": audience }),
+                ))
+                .respond_with(ResponseTemplate::new(200).set_body_json(
+                    serde_json::json!({
+                        "token": format!("token_for_{audience}"),
+                        "duration": 36000,
+                        "expiryTime": "is not used by client",
+                    }),
+                ))
+                .mount(&mock_server)
+                .await;
+        }
+        mock_server
+    }
+
+    #[tokio::test]
+    async fn tokens_are_scoped_per_audience() {
+        let mock_server = mock_auth_server().await;
+        let (changed_tx, mut changed_rx) = mpsc::unbounded_channel();
+        let tokens = Arc::new(ScopedTokens::new(
+            "TEST_ORB".to_owned(),
+            format!("{}/api/v1/", mock_server.uri()).parse().unwrap(),
+            Arc::new(crate::signer::Mock::default()),
+            changed_tx,
+        ));
+
+        for audience in ["update-agent", "backend-state"] {
+            let mut rx = tokens.subscribe(audience).unwrap();
+            let token = rx.wait_for(Option::is_some).await.unwrap().clone();
+            assert_eq!(token.unwrap(), format!("token_for_{audience}"));
+            assert_eq!(changed_rx.recv().await.unwrap(), audience);
+        }
+
+        // Known audiences are served from the cache.
+        let rx = tokens.subscribe("update-agent").unwrap();
+        assert_eq!(rx.borrow().as_deref(), Some("token_for_update-agent"));
+        let token_requests = mock_server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|request| request.url.path() == "/api/v1/token")
+            .count();
+        assert_eq!(token_requests, 2);
+    }
+
+    #[tokio::test]
+    async fn audiences_are_validated_and_bounded() {
+        let (changed_tx, _changed_rx) = mpsc::unbounded_channel();
+        let tokens = Arc::new(ScopedTokens::new(
+            "TEST_ORB".to_owned(),
+            // Nothing listens here, so the refresh tasks keep retrying in the background.
+            "http://127.0.0.1:9/api/v1/".parse().unwrap(),
+            Arc::new(crate::signer::Mock::default()),
+            changed_tx,
+        ));
+
+        assert!(matches!(tokens.subscribe(""), Err(AudienceError::Empty)));
+        assert!(matches!(
+            tokens.subscribe("update agent"),
+            Err(AudienceError::Invalid)
+        ));
+        assert!(matches!(
+            tokens.subscribe(&"a".repeat(129)),
+            Err(AudienceError::Invalid)
+        ));
+        for i in 0..MAX_AUDIENCES {
+            tokens.subscribe(&format!("audience-{i}")).unwrap();
+        }
+        tokens.subscribe("audience-0").unwrap();
+        assert!(matches!(
+            tokens.subscribe("one-too-many"),
+            Err(AudienceError::TooMany)
+        ));
+    }
+
+    #[test]
+    fn allowlist_is_parsed() {
+        let allowlist: Allowlist =
+            " update-agent=0 ; fleet=1000, 1001;".parse().unwrap();
+        assert!(allowlist.is_allowed("update-agent", 0));
+        assert!(!allowlist.is_allowed("update-agent", 1000));
+        assert!(allowlist.is_allowed("fleet", 1000));
+        assert!(allowlist.is_allowed("fleet", 1001));
+        assert!(!allowlist.is_allowed("backend-state", 0));
+
+        assert!(!Allowlist::default().is_allowed("update-agent", 0));
+        assert!("update-agent".parse::<Allowlist>().is_err());
+        assert!("update-agent=root".parse::<Allowlist>().is_err());
+        assert!("update agent=0".parse::<Allowlist>().is_err());
+    }
+}
@@ -4,11 +4,11 @@ use std::sync::OnceLock;
 
 use color_eyre::{eyre::WrapErr, Result};
 use derive_more::{Display, From};
+use orb_endpoints::{Backend, Endpoints};
 use orb_header_parsing::time_until_max_age;
+use reqwest::Url;
 
-use crate::state::State;
-
-const API_BASE_URL: &str = "https://management.stage.orb.worldcoin.org";
+use crate::state::{Snapshot, State};
 
 /// Authorization token to the backend. Comes from short lived token daemon.
 #[derive(Debug, Display, From, Clone)]
@@ -17,11 +17,21 @@ pub struct Token(String);
 #[derive(Debug, Display, From, Clone)]
 pub struct OrbId(String);
 
-/// Retrieves the [`State`] from the backend.
-pub async fn get_state(id: &OrbId, token: &Token) -> Result<State> {
-    let c = get_http_client();
+/// The URL of the orb's state on `backend`.
+pub fn state_url(backend: Backend, id: &OrbId) -> Result<Url> {
+    let id = id.to_string();
+    let id = id.trim();
+    let id = id
+        .parse()
+        .wrap_err_with(|| format!("orb id `{id}` is not valid hex"))?;
+    Ok(Endpoints::new(backend, &id).state)
+}
+
+/// Retrieves the [`State`] from the backend at `url`.
+pub async fn get_state(url: &Url, id: &OrbId, token: &Token) -> Result<Snapshot> {
+    let c = get_http_client(url);
     let response = c
-        .get(format!("{API_BASE_URL}/api/v1/orbs/{id}/state"))
+        .get(url.clone())
         .basic_auth(id, Some(token))
         .send()
         .await
@@ -33,14 +43,93 @@ pub async fn get_state(id: &OrbId, token: &Token) -> Result<State> {
         .text()
         .await
         .wrap_err("Error while getting response body")?;
-    Ok(State::new(body, expires_in))
+    let state = State::from_json(&body).wrap_err_with(|| {
+        format!("failed to parse state from response body: {body}")
+    })?;
+    Ok(Snapshot::new(state, expires_in))
 }
 
-fn get_http_client() -> &'static reqwest::Client {
+fn get_http_client(url: &Url) -> &'static reqwest::Client {
     static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    static LOOPBACK_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    // Plain HTTP is only ever allowed to talk to a mock server on the same machine.
+    if is_loopback(url) {
+        return LOOPBACK_CLIENT.get_or_init(|| {
+            orb_security_utils::reqwest::http_client_builder()
+                .https_only(false)
+                .build()
+                .expect("Failed to build client")
+        });
+    }
     CLIENT.get_or_init(|| {
         orb_security_utils::reqwest::http_client_builder()
             .build()
             .expect("Failed to build client")
     })
 }
+
+fn is_loopback(url: &Url) -> bool {
+    match url.host() {
+        Some(url::Host::Domain(domain)) => domain == "localhost",
+        Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use httpmock::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_state_url() {
+        let id = OrbId::from("ea2ea744\n".to_owned());
+        assert_eq!(
+            state_url(Backend::Staging, &id).unwrap().as_str(),
+            "https://management.stage.orb.worldcoin.org/api/v1/orbs/ea2ea744/state"
+        );
+        assert!(state_url(Backend::Prod, &OrbId::from("xyz".to_owned())).is_err());
+    }
+
+    #[test]
+    fn test_only_loopback_is_plain_http() {
+        assert!(is_loopback(&Url::parse("http://localhost:80").unwrap()));
+        assert!(is_loopback(&Url::parse("http://[::1]:80").unwrap()));
+        assert!(!is_loopback(&Url::parse("http://10.0.0.1").unwrap()));
+        assert!(!is_loopback(
+            &Url::parse("https://orb.worldcoin.org").unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_state_from_mock_server() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/api/v1/orbs/ea2ea744/state")
+                    .header_exists("authorization");
+                then.status(200)
+                    .header("cache-control", "max-age=60")
+                    .body(r#"{"operator_assigned":true}"#);
+            })
+            .await;
+
+        let id = OrbId::from("ea2ea744".to_owned());
+        let backend = Backend::Custom {
+            base_url: Url::parse(&server.base_url()).unwrap(),
+        };
+        let url = state_url(backend, &id).unwrap();
+        let snapshot = get_state(&url, &id, &Token::from("token".to_owned()))
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert!(snapshot.operator_assigned);
+        assert_eq!(snapshot.expires_in(), Some(Duration::from_secs(60)));
+    }
+}
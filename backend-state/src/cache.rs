@@ -0,0 +1,108 @@
+//! Persists the last retrieved state, so that it can be served right after a reboot.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use color_eyre::{eyre::WrapErr, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+use crate::state::{Snapshot, State};
+
+pub const DEFAULT_CACHE_PATH: &str = "/usr/persistent/backend-state.json";
+
+/// The on-disk representation of a [`Snapshot`]. Times are wall-clock, as [`std::time::Instant`]
+/// does not survive reboots.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    /// The state as returned by the backend.
+    state: Box<RawValue>,
+    /// Seconds since the unix epoch.
+    retrieved_at: u64,
+    /// Seconds after `retrieved_at` until the state expires.
+    expires_in: Option<u64>,
+}
+
+/// Loads the cached state from `path` as a stale [`Snapshot`], or `None` if nothing was cached
+/// yet.
+pub fn load(path: &Path) -> Result<Option<Snapshot>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).wrap_err_with(|| {
+                format!("failed to read cache at {}", path.display())
+            })
+        }
+    };
+    let entry: Entry = serde_json::from_str(&contents)
+        .wrap_err_with(|| format!("failed to parse cache at {}", path.display()))?;
+    let state = State::from_json(entry.state.get()).wrap_err_with(|| {
+        format!("failed to parse cached state at {}", path.display())
+    })?;
+    Ok(Some(Snapshot::restore(
+        state,
+        UNIX_EPOCH + Duration::from_secs(entry.retrieved_at),
+        entry.expires_in.map(Duration::from_secs),
+    )))
+}
+
+/// Stores `snapshot` at `path`, replacing any previously cached state.
+pub fn store(path: &Path, snapshot: &Snapshot) -> Result<()> {
+    let entry = Entry {
+        state: RawValue::from_string(snapshot.to_json())
+            .wrap_err("failed to serialize cached state")?,
+        retrieved_at: snapshot
+            .retrieved_at_wall()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        expires_in: snapshot.expires_in().map(|d| d.as_secs()),
+    };
+    let contents =
+        serde_json::to_vec(&entry).wrap_err("failed to serialize cache entry")?;
+    // Write to a temporary file first, so that a crash never leaves a truncated cache behind.
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents)
+        .wrap_err_with(|| format!("failed to write cache to {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path)
+        .wrap_err_with(|| format!("failed to move cache to {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("backend-state.json");
+        assert!(load(&path).unwrap().is_none());
+
+        let state = State::from_json(r#"{"location":"berlin"}"#).unwrap();
+        let snapshot = Snapshot::new(state, Some(Duration::from_secs(60)));
+        store(&path, &snapshot).unwrap();
+
+        let restored = load(&path).unwrap().unwrap();
+        assert!(restored.is_stale());
+        assert_eq!(*restored, *snapshot);
+        assert_eq!(restored.to_json(), snapshot.to_json());
+        assert_eq!(restored.expires_in(), Some(Duration::from_secs(60)));
+        assert!(restored.retrieved_at_wall() <= SystemTime::now());
+        assert!(!restored.is_expired());
+    }
+
+    #[test]
+    fn test_corrupt_cache_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("backend-state.json");
+        fs::write(&path, "{").unwrap();
+        assert!(load(&path).is_err());
+    }
+}
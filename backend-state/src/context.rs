@@ -1,12 +1,14 @@
 //! Shared state/context
 
 use color_eyre::{eyre::WrapErr, Result};
-use std::sync::Arc;
+use orb_endpoints::Backend;
+use reqwest::Url;
+use std::{path::PathBuf, sync::Arc};
 use tokio::sync::watch;
 
 use crate::{
     api::{OrbId, Token},
-    state::State,
+    state::Snapshot,
 };
 
 /// Common shared state/context. Cheaply cloneable.
@@ -15,10 +17,20 @@ pub struct Context {
     pub token: watch::Receiver<Token>,
     pub state: SharedState,
     pub orb_id: OrbId,
+    /// Where the state is retrieved from.
+    pub state_url: Url,
+    /// Where the last retrieved state is persisted.
+    pub cache_path: PathBuf,
 }
 
 impl Context {
-    pub async fn new(token: watch::Receiver<Token>) -> Result<Self> {
+    /// Creates the context, retrieving the state from `base_url` instead of the backend
+    /// selected by `ORB_BACKEND` if given, like `ORB_BACKEND=custom:<base_url>` would.
+    pub async fn new(
+        token: watch::Receiver<Token>,
+        cache_path: PathBuf,
+        base_url: Option<&Url>,
+    ) -> Result<Self> {
         let orb_id = if let Ok(orb_id) = std::env::var("ORB_ID") {
             assert!(!orb_id.is_empty());
             OrbId::from(orb_id)
@@ -33,20 +45,31 @@ impl Context {
                 .map(OrbId::from)?
         };
 
+        let backend = match base_url {
+            Some(base_url) => Backend::Custom {
+                base_url: base_url.clone(),
+            },
+            None => Backend::from_env_or_build_type::<{ cfg!(feature = "stage") }>(),
+        };
+        let state_url = crate::api::state_url(backend, &orb_id)?;
+        tracing::info!("retrieving state from {state_url}");
+
         Ok(Self {
             token,
             state: Default::default(),
             orb_id,
+            state_url,
+            cache_path,
         })
     }
 }
 
-/// Cheaply cloneable, shareable version of `Option<State>`.
+/// Cheaply cloneable, shareable version of `Option<Snapshot>`.
 /// Also provides functions to wait on state changes.
 #[derive(Clone, Debug)]
 pub struct SharedState {
-    sender: Arc<watch::Sender<Option<State>>>,
-    receiver: watch::Receiver<Option<State>>,
+    sender: Arc<watch::Sender<Option<Snapshot>>>,
+    receiver: watch::Receiver<Option<Snapshot>>,
 }
 
 impl Default for SharedState {
@@ -61,12 +84,12 @@ impl Default for SharedState {
 
 impl SharedState {
     /// Gets a copy of the state.
-    pub fn get_cloned(&self) -> Option<State> {
+    pub fn get_cloned(&self) -> Option<Snapshot> {
         self.receiver.borrow().clone()
     }
 
     /// Replaces the current state with the new_state
-    pub fn update(&self, new_state: State) {
+    pub fn update(&self, new_state: Snapshot) {
         self.sender.send(Some(new_state)).expect(
             "Failed to send on watch channel, but this should be impossible.
             There is always at least one receiver",
@@ -74,7 +97,7 @@ impl SharedState {
     }
 
     /// Waits for an update to the `SharedState`.
-    pub async fn wait_for_update(&mut self) -> State {
+    pub async fn wait_for_update(&mut self) -> Snapshot {
         drop(self.receiver.borrow_and_update()); // mark as seen
         self.receiver
             .changed()
@@ -1,8 +1,8 @@
 //! Dbus interface definitions.
 
-use zbus::interface;
+use zbus::{interface, SignalContext};
 
-use crate::context::Context;
+use crate::{context::Context, state::State};
 
 pub struct Interface {
     ctx: Context,
@@ -12,18 +12,83 @@ impl Interface {
     pub(crate) fn new(ctx: Context) -> Self {
         Self { ctx }
     }
+
+    fn with_state<T>(&self, f: impl FnOnce(&State) -> T) -> zbus::fdo::Result<T> {
+        match self.ctx.state.get_cloned() {
+            Some(snapshot) => {
+                if snapshot.is_expired() {
+                    tracing::debug!(
+                        "serving expired state, retrieved {:?} ago",
+                        snapshot.retrieved_at().elapsed()
+                    );
+                }
+                Ok(f(&snapshot))
+            }
+            None => Err(zbus::fdo::Error::Failed(
+                "state was not yet or could not be retrieved from backend".into(),
+            )),
+        }
+    }
+
+    /// Notifies subscribers that the `changed` fields of the state changed, and whether it
+    /// became fresh or stale.
+    pub(crate) async fn notify_changed(
+        &self,
+        ctx: &SignalContext<'_>,
+        changed: &[String],
+        stale_changed: bool,
+    ) -> zbus::Result<()> {
+        if stale_changed {
+            self.stale_changed(ctx).await?;
+        }
+        if changed.is_empty() {
+            return Ok(());
+        }
+        self.state_changed(ctx).await?;
+        for field in changed {
+            match field.as_str() {
+                "schema_version" => self.schema_version_changed(ctx).await?,
+                "operator_assigned" => self.operator_assigned_changed(ctx).await?,
+                "location" => self.location_changed(ctx).await?,
+                _ => (),
+            }
+        }
+        Self::fields_changed(ctx, changed).await
+    }
 }
 
 #[interface(name = "org.worldcoin.BackendState1")]
 impl Interface {
-    /// Retrieves the cached state of the orb
+    /// Retrieves the cached state of the orb as JSON.
     #[zbus(property)]
     fn state(&self) -> zbus::fdo::Result<String> {
-        match self.ctx.state.get_cloned().as_deref() {
-            Some("") => Err(zbus::fdo::Error::Failed(
-                "state was set, but is an empty string".into(),
-            )),
-            Some(state) => Ok(state.to_string()),
+        self.with_state(State::to_json)
+    }
+
+    /// The schema version of the cached state.
+    #[zbus(property)]
+    fn schema_version(&self) -> zbus::fdo::Result<u32> {
+        self.with_state(|state| state.schema_version)
+    }
+
+    /// Whether an operator is assigned to the orb.
+    #[zbus(property)]
+    fn operator_assigned(&self) -> zbus::fdo::Result<bool> {
+        self.with_state(|state| state.operator_assigned)
+    }
+
+    /// The location the orb is assigned to, or an empty string if there is none.
+    #[zbus(property)]
+    fn location(&self) -> zbus::fdo::Result<String> {
+        self.with_state(|state| state.location.clone().unwrap_or_default())
+    }
+
+    /// Whether the state was restored from the cache after a restart and is not yet refreshed
+    /// from the backend.
+    #[zbus(property)]
+    fn stale(&self) -> zbus::fdo::Result<bool> {
+        match self.ctx.state.get_cloned() {
+            Some(snapshot) => Ok(snapshot.is_stale()),
             None => Err(zbus::fdo::Error::Failed(
                 "state was not yet or could not be retrieved from backend".into(),
             )),
@@ -33,11 +98,19 @@ impl Interface {
     /// Forces a request to the backend for the latest state.
     async fn refresh_state(&self) -> zbus::fdo::Result<String> {
         match crate::update_state(&self.ctx).await {
-            Ok(s) => Ok(s.into()),
+            Ok(s) => Ok(s.to_json()),
             Err(e) => {
                 tracing::error!(err=?e, "failed to refresh state");
                 Err(zbus::fdo::Error::Failed(format!("{e:?}")))
             }
         }
     }
+
+    /// Emitted whenever a new state differs from the previous one, with the names of the
+    /// top-level fields that changed.
+    #[zbus(signal, name = "StateChanged")]
+    async fn fields_changed(
+        ctx: &SignalContext<'_>,
+        fields: &[String],
+    ) -> zbus::Result<()>;
 }
@@ -1,11 +1,15 @@
 #![forbid(unsafe_code)]
 
 mod api;
+mod cache;
 mod context;
 mod dbus_interface;
 mod state;
 
-use std::time::{Duration, Instant};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use clap::Parser;
 use color_eyre::eyre::bail;
@@ -14,11 +18,12 @@ use context::Context;
 use futures::FutureExt;
 use orb_attest_dbus::AuthTokenManagerProxy;
 use orb_build_info::{make_build_info, BuildInfo};
+use reqwest::Url;
 use tokio::{select, sync::watch};
 use zbus::export::futures_util::StreamExt;
 
 use crate::api::Token;
-use crate::state::State;
+use crate::state::Snapshot;
 
 const ONE_DAY: Duration = Duration::from_secs(60 * 60 * 24);
 const RETRY_DELAY_MIN: Duration = Duration::from_secs(1);
@@ -30,7 +35,16 @@ const SYSLOG_IDENTIFIER: &str = "worldcoin-backend-state";
 
 #[derive(Parser, Debug)]
 #[command(about, author, version=BUILD_INFO.version, styles=make_clap_v3_styles())]
-struct Cli {}
+struct Cli {
+    /// Where the last retrieved state is persisted, so that it can be served across reboots.
+    #[arg(long, default_value = cache::DEFAULT_CACHE_PATH)]
+    cache_path: PathBuf,
+    /// Retrieve the state from this base URL instead of the backend selected by
+    /// `ORB_BACKEND`, e.g. to talk to a mock server. Same as `ORB_BACKEND=custom:<URL>`.
+    /// Plain HTTP is only allowed for localhost.
+    #[arg(long, env = "ORB_BACKEND_STATE_BASE_URL")]
+    base_url: Option<Url>,
+}
 
 // No need to waste RAM with a threadpool.
 #[tokio::main(flavor = "current_thread")]
@@ -40,7 +54,7 @@ async fn main() -> Result<()> {
         .with_journald(SYSLOG_IDENTIFIER)
         .init();
 
-    let _args = Cli::parse();
+    let args = Cli::parse();
 
     let conn = zbus::Connection::session()
         .await
@@ -62,7 +76,7 @@ async fn main() -> Result<()> {
             let (_send, recv) = watch::channel(Token::from(token));
             (
                 tokio::task::spawn(std::future::pending()),
-                Context::new(recv)
+                Context::new(recv, args.cache_path, args.base_url.as_ref())
                     .await
                     .wrap_err("failed to create context")?,
             )
@@ -74,10 +88,23 @@ async fn main() -> Result<()> {
                 watch_token_task(token_proxy)
                     .await
                     .wrap_err("failed to spawn watch token task")?;
-            (watch_token_task_handle, Context::new(token_watcher).await?)
+            (
+                watch_token_task_handle,
+                Context::new(token_watcher, args.cache_path, args.base_url.as_ref())
+                    .await?,
+            )
         }
     };
 
+    match cache::load(&ctx.cache_path) {
+        Ok(Some(snapshot)) => {
+            tracing::info!("Serving stale state from cache until it is refreshed");
+            ctx.state.update(snapshot);
+        }
+        Ok(None) => (),
+        Err(e) => tracing::warn!(err = ?e, "failed to load cached state"),
+    }
+
     let iface_ref: zbus::InterfaceRef<self::dbus_interface::Interface> = {
         const IFACE_PATH: &str = "/org/worldcoin/BackendState1";
         let conn = zbus::ConnectionBuilder::session()
@@ -134,16 +161,19 @@ async fn watch_token_task(
 }
 
 /// Updates the shared state by fetching it from the backend
-async fn update_state(ctx: &Context) -> Result<State> {
+async fn update_state(ctx: &Context) -> Result<Snapshot> {
     let token = ctx.token.borrow().to_owned();
     let orb_id = &ctx.orb_id;
-    let new_state = crate::api::get_state(orb_id, &token)
+    let new_state = crate::api::get_state(&ctx.state_url, orb_id, &token)
         .await
         .wrap_err("Error while fetching state from backend");
     match new_state {
         Ok(s) => {
             tracing::info!("Fetched new state: {s:?}");
             ctx.state.update(s.clone());
+            if let Err(e) = crate::cache::store(&ctx.cache_path, &s) {
+                tracing::warn!(err = ?e, "failed to persist state");
+            }
             Ok(s)
         }
         Err(e) => {
@@ -156,7 +186,12 @@ async fn update_state(ctx: &Context) -> Result<State> {
 /// Repeatedly polls the backend for the current state.
 async fn poll_backend(mut ctx: Context) -> ! {
     let mut delay = RETRY_DELAY_MIN;
-    let mut next_attempt = Instant::now();
+    // A state restored from the cache is served while it is refreshed right away, as it may
+    // have changed while the daemon was not running.
+    let mut next_attempt = match ctx.state.get_cloned() {
+        Some(s) if !s.is_stale() => s.expires_at(),
+        _ => Instant::now(),
+    };
     loop {
         select! {
             // Responsible for polling repeatedly
@@ -184,19 +219,28 @@ async fn poll_backend(mut ctx: Context) -> ! {
     }
 }
 
-/// Listens for changes to state, and signals that change to the dbus interface.
+/// Listens for changes to state, and signals the changed fields to the dbus interface.
 fn spawn_notify_state_task(
     iface: zbus::InterfaceRef<crate::dbus_interface::Interface>,
     mut ctx: Context,
 ) -> tokio::task::JoinHandle<Result<()>> {
     tokio::task::spawn(async move {
         let signal_ctx = iface.signal_context();
+        let mut last = ctx.state.get_cloned();
         loop {
-            ctx.state.wait_for_update().await;
+            let snapshot = ctx.state.wait_for_update().await;
+            let changed = snapshot.changed_fields(last.as_deref());
+            let stale_changed =
+                last.as_ref().map(|s| s.is_stale()) != Some(snapshot.is_stale());
+            last = Some(snapshot);
+            if changed.is_empty() && !stale_changed {
+                continue;
+            }
+            tracing::debug!(?changed, stale_changed, "state changed");
             iface
                 .get()
                 .await
-                .state_changed(signal_ctx)
+                .notify_changed(signal_ctx, &changed, stale_changed)
                 .await
                 .wrap_err("failed to signal state change")?;
         }
@@ -1,23 +1,137 @@
 use std::{
     ops::Deref,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
 use crate::ONE_DAY;
 
-#[derive(Debug, Clone)]
+/// The newest version of the [`State`] schema that this daemon understands.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The orb's state, as modeled by the orb-manager backend.
+///
+/// Fields that are not modeled yet are kept in `other`, so that they can still be compared.
+/// Consumers of the full state get the response body as is.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct State {
-    state: String,
+    /// Responses without a schema version predate versioning and use version 1.
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+    /// Whether an operator is assigned to the orb.
+    #[serde(default)]
+    pub operator_assigned: bool,
+    /// The location the orb is assigned to, if any.
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+    /// The response body the state was parsed from.
+    #[serde(skip)]
+    raw: String,
+}
+
+fn default_schema_version() -> u32 {
+    1
+}
+
+impl State {
+    /// Parses the state from the backend's JSON response.
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        let mut state: Self = serde_json::from_str(json)?;
+        state.raw = json.to_owned();
+        if state.schema_version > SCHEMA_VERSION {
+            tracing::warn!(
+                "state has schema version {}, but only up to {SCHEMA_VERSION} is supported; \
+                 unknown fields are passed through as-is",
+                state.schema_version
+            );
+        }
+        Ok(state)
+    }
+
+    /// The state exactly as the backend returned it.
+    pub fn to_json(&self) -> String {
+        self.raw.clone()
+    }
+
+    /// The names of the fields that differ between `old` and `self`. All fields changed if
+    /// there was no `old` state.
+    pub fn changed_fields(&self, old: Option<&State>) -> Vec<String> {
+        let Some(old) = old else {
+            return self.field_names().collect();
+        };
+        let mut changed = Vec::new();
+        if self.schema_version != old.schema_version {
+            changed.push("schema_version".to_owned());
+        }
+        if self.operator_assigned != old.operator_assigned {
+            changed.push("operator_assigned".to_owned());
+        }
+        if self.location != old.location {
+            changed.push("location".to_owned());
+        }
+        let mut other: Vec<_> = self
+            .other
+            .keys()
+            .chain(old.other.keys())
+            .filter(|key| self.other.get(*key) != old.other.get(*key))
+            .cloned()
+            .collect();
+        other.sort();
+        other.dedup();
+        changed.extend(other);
+        changed
+    }
+
+    fn field_names(&self) -> impl Iterator<Item = String> + '_ {
+        ["schema_version", "operator_assigned", "location"]
+            .into_iter()
+            .map(str::to_owned)
+            .chain(self.other.keys().cloned())
+    }
+}
+
+/// A [`State`] together with when it was retrieved from the backend.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    state: State,
     retrieved_at: Instant,
+    retrieved_at_wall: SystemTime,
     expires_in: Option<Duration>,
+    stale: bool,
 }
 
-impl State {
-    pub fn new(state: String, expires_in: Option<Duration>) -> Self {
+impl Snapshot {
+    pub fn new(state: State, expires_in: Option<Duration>) -> Self {
         Self {
             state,
             expires_in,
             retrieved_at: Instant::now(),
+            retrieved_at_wall: SystemTime::now(),
+            stale: false,
+        }
+    }
+
+    /// Restores a stale snapshot that was retrieved at the wall-clock time `retrieved_at`,
+    /// possibly before a reboot.
+    pub fn restore(
+        state: State,
+        retrieved_at: SystemTime,
+        expires_in: Option<Duration>,
+    ) -> Self {
+        // A wall-clock time in the future means that the clock was adjusted since. Treat the
+        // state as just retrieved then, its expiry still bounds how long it is used.
+        let age = retrieved_at.elapsed().unwrap_or_default();
+        let now = Instant::now();
+        Self {
+            state,
+            expires_in,
+            retrieved_at: now.checked_sub(age).unwrap_or(now),
+            retrieved_at_wall: retrieved_at,
+            stale: true,
         }
     }
 
@@ -26,6 +140,21 @@ impl State {
         self.retrieved_at
     }
 
+    /// The wall-clock time that the state was retrieved from the backend.
+    pub fn retrieved_at_wall(&self) -> SystemTime {
+        self.retrieved_at_wall
+    }
+
+    pub fn expires_in(&self) -> Option<Duration> {
+        self.expires_in
+    }
+
+    /// Whether the state was restored from the cache instead of being retrieved since the
+    /// daemon started.
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
     /// Whether the state is expried, and should be requested from the backend again.
     pub fn is_expired(&self) -> bool {
         self.retrieved_at.elapsed() >= self.expires_in.unwrap_or(Duration::MAX)
@@ -39,36 +168,92 @@ impl State {
     }
 }
 
-impl Deref for State {
-    type Target = str;
+impl Deref for Snapshot {
+    type Target = State;
 
     fn deref(&self) -> &Self::Target {
         &self.state
     }
 }
 
-impl From<State> for String {
-    fn from(value: State) -> Self {
-        value.state
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn state(json: &str) -> State {
+        State::from_json(json).unwrap()
+    }
+
     #[test]
     fn test_expires_at() {
-        let no_expire = State::new("foo".into(), None);
+        let no_expire = Snapshot::new(state("{}"), None);
         let expiry0 = Duration::from_secs(0);
         let expiry1 = Duration::from_secs(2);
-        let expires0 = State::new("2".into(), Some(expiry0));
+        let expires0 = Snapshot::new(state("{}"), Some(expiry0));
         assert!(expires0.is_expired());
-        let expires1 = State::new("1".into(), Some(expiry1));
+        let expires1 = Snapshot::new(state("{}"), Some(expiry1));
         assert!(!expires1.is_expired());
 
         assert_eq!(expires1.expires_at(), expires1.retrieved_at() + expiry1);
         assert_eq!(expires0.expires_at(), expires0.retrieved_at() + expiry0);
         assert_eq!(no_expire.expires_at(), no_expire.retrieved_at() + ONE_DAY);
     }
+
+    #[test]
+    fn test_restore_keeps_age() {
+        let hour = Duration::from_secs(60 * 60);
+        let retrieved_at = SystemTime::now() - hour;
+        let restored = Snapshot::restore(state("{}"), retrieved_at, Some(2 * hour));
+        assert!(restored.is_stale());
+        assert!(!restored.is_expired());
+        assert!(restored.retrieved_at().elapsed() >= hour);
+        assert_eq!(restored.retrieved_at_wall(), retrieved_at);
+
+        let expired = Snapshot::restore(state("{}"), retrieved_at, Some(hour / 2));
+        assert!(expired.is_expired());
+
+        let from_future =
+            Snapshot::restore(state("{}"), SystemTime::now() + hour, Some(hour));
+        assert!(!from_future.is_expired());
+        assert!(!Snapshot::new(state("{}"), None).is_stale());
+    }
+
+    #[test]
+    fn test_parse_keeps_unknown_fields() {
+        let parsed = state(
+            r#"{"operator_assigned":true,"location":"berlin","firmware":{"channel":"beta"}}"#,
+        );
+        assert_eq!(parsed.schema_version, 1);
+        assert!(parsed.operator_assigned);
+        assert_eq!(parsed.location.as_deref(), Some("berlin"));
+        assert_eq!(parsed.other["firmware"]["channel"], "beta");
+
+        assert_eq!(state(&parsed.to_json()), parsed);
+
+        let raw = r#"{ "location": "berlin", "firmware": {"channel": "beta"} }"#;
+        assert_eq!(state(raw).to_json(), raw);
+        assert!(State::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn test_changed_fields() {
+        let old = state(r#"{"location":"berlin","firmware":"1.0","removed":1}"#);
+        let new = state(r#"{"location":"paris","firmware":"1.0","added":2}"#);
+
+        assert_eq!(
+            new.changed_fields(Some(&old)),
+            ["location", "added", "removed"]
+        );
+        assert!(new.changed_fields(Some(&new)).is_empty());
+        assert_eq!(
+            new.changed_fields(None),
+            [
+                "schema_version",
+                "operator_assigned",
+                "location",
+                "added",
+                "firmware"
+            ]
+        );
+    }
 }
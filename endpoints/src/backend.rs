@@ -1,12 +1,22 @@
 use std::{env::VarError, str::FromStr};
 
+use url::Url;
+
 pub const ORB_BACKEND_ENV_VAR_NAME: &str = "ORB_BACKEND";
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Prefix of [`Backend::Custom`] when parsing, e.g. `custom:http://127.0.0.1:8080`.
+const CUSTOM_PREFIX: &str = "custom:";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Backend {
     Prod,
     Staging,
     Analysis,
+    /// A single server that serves all endpoints below `base_url`, such as a lab setup or a
+    /// mock server in tests. `base_url` must be usable as a base, i.e. have a path.
+    Custom {
+        base_url: Url,
+    },
 }
 
 impl Backend {
@@ -62,7 +72,18 @@ impl FromStr for Backend {
     type Err = BackendParseErr;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.trim().to_lowercase().as_str() {
+        let s = s.trim();
+        if let Some(prefix) = s.get(..CUSTOM_PREFIX.len()) {
+            if prefix.eq_ignore_ascii_case(CUSTOM_PREFIX) {
+                let base_url = Url::parse(&s[CUSTOM_PREFIX.len()..])
+                    .map_err(|_| BackendParseErr)?;
+                if base_url.cannot_be_a_base() {
+                    return Err(BackendParseErr);
+                }
+                return Ok(Self::Custom { base_url });
+            }
+        }
+        match s.to_lowercase().as_str() {
             "prod" | "production" => Ok(Self::Prod),
             "stage" | "staging" | "dev" | "development" => Ok(Self::Staging),
             "analysis" | "analysis.ml" | "analysis-ml" => Ok(Self::Analysis),
@@ -134,4 +155,17 @@ mod test {
         assert_eq!(Backend::from_str("analysis").unwrap(), Backend::Analysis);
         assert_eq!(Backend::from_str("foobar"), Err(BackendParseErr));
     }
+
+    #[test]
+    fn test_custom_backend_parse() {
+        assert_eq!(
+            Backend::from_str(" Custom:http://127.0.0.1:8080/Mock ").unwrap(),
+            Backend::Custom {
+                base_url: Url::parse("http://127.0.0.1:8080/Mock").unwrap()
+            }
+        );
+        assert_eq!(Backend::from_str("custom:"), Err(BackendParseErr));
+        assert_eq!(Backend::from_str("custom:not a url"), Err(BackendParseErr));
+        assert_eq!(Backend::from_str("custom:mailto:a@b"), Err(BackendParseErr));
+    }
 }
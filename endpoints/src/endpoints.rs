@@ -10,6 +10,7 @@ pub struct Endpoints {
     pub ai_volume: Url,
     pub auth: Url,
     pub ping: Url,
+    pub state: Url,
 }
 
 impl Endpoints {
@@ -18,35 +19,53 @@ impl Endpoints {
     /// # Errors
     /// Errors if the `orb_id` would result in an invalid URL.
     pub fn new(backend: Backend, orb_id: &OrbId) -> Self {
-        let subdomain = match backend {
-            Backend::Prod => "orb",
-            Backend::Staging => "stage.orb",
-            Backend::Analysis => "analysis.ml",
-        };
-
         /// Safer way to assemble URLs involving `OrbId`
-        fn concat_urls(prefix: &str, orb_id: &OrbId, suffix: &str) -> Url {
-            Url::parse(prefix)
-                .and_then(|url| url.join(&format!("{}/", orb_id.as_str())))
+        fn concat_urls(prefix: &Url, orb_id: &OrbId, suffix: &str) -> Url {
+            prefix
+                .join(&format!("{}/", orb_id.as_str()))
                 .and_then(|url| url.join(suffix))
                 .expect("urls with validated orb ids should always parse")
         }
 
+        /// The management and auth URLs of a backend on worldcoin.org.
+        fn hosted_urls(subdomain: &str) -> (Url, Url) {
+            let parse = |url: String| {
+                Url::parse(&url).expect("urls of hosted backends should always parse")
+            };
+            (
+                parse(format!(
+                    "https://management.{subdomain}.worldcoin.org/api/v1/orbs/"
+                )),
+                parse(format!("https://auth.{subdomain}.worldcoin.org/api/v1/")),
+            )
+        }
+
+        /// The management and auth URLs of a backend serving everything below `base_url`.
+        fn custom_urls(mut base_url: Url) -> (Url, Url) {
+            // Without a trailing slash, joining would replace the last path segment.
+            if !base_url.path().ends_with('/') {
+                base_url.set_path(&format!("{}/", base_url.path()));
+            }
+            let join = |path| {
+                base_url
+                    .join(path)
+                    .expect("relative paths should always join onto a base url")
+            };
+            (join("api/v1/orbs/"), join("api/v1/"))
+        }
+
+        let (management, auth) = match backend {
+            Backend::Prod => hosted_urls("orb"),
+            Backend::Staging => hosted_urls("stage.orb"),
+            Backend::Analysis => hosted_urls("analysis.ml"),
+            Backend::Custom { base_url } => custom_urls(base_url),
+        };
+
         Self {
-            ai_volume: concat_urls(
-                &format!("https://management.{subdomain}.worldcoin.org/api/v1/orbs/"),
-                orb_id,
-                "keys/aivolume",
-            ),
-            auth: Url::parse(&format!(
-                "https://auth.{subdomain}.worldcoin.org/api/v1/"
-            ))
-            .expect("urls with validated orb ids should always parse"),
-            ping: concat_urls(
-                &format!("https://management.{subdomain}.worldcoin.org/api/v1/orbs/"),
-                orb_id,
-                "",
-            ),
+            ai_volume: concat_urls(&management, orb_id, "keys/aivolume"),
+            auth,
+            ping: concat_urls(&management, orb_id, ""),
+            state: concat_urls(&management, orb_id, "state"),
         }
     }
 }
@@ -96,5 +115,42 @@ mod test {
             analysis.ping.as_str(),
             "https://management.analysis.ml.worldcoin.org/api/v1/orbs/ea2ea744/"
         );
+
+        assert_eq!(
+            stage.state.as_str(),
+            "https://management.stage.orb.worldcoin.org/api/v1/orbs/ea2ea744/state"
+        );
+        assert_eq!(
+            prod.state.as_str(),
+            "https://management.orb.worldcoin.org/api/v1/orbs/ea2ea744/state"
+        );
+        assert_eq!(
+            analysis.state.as_str(),
+            "https://management.analysis.ml.worldcoin.org/api/v1/orbs/ea2ea744/state"
+        );
+    }
+
+    #[test]
+    fn test_custom_config() {
+        for base_url in ["http://127.0.0.1:8080/lab", "http://127.0.0.1:8080/lab/"] {
+            let backend = Backend::Custom {
+                base_url: Url::parse(base_url).unwrap(),
+            };
+            let custom = Endpoints::new(backend, &"ea2ea744".parse().unwrap());
+
+            assert_eq!(
+                custom.ai_volume.as_str(),
+                "http://127.0.0.1:8080/lab/api/v1/orbs/ea2ea744/keys/aivolume"
+            );
+            assert_eq!(custom.auth.as_str(), "http://127.0.0.1:8080/lab/api/v1/");
+            assert_eq!(
+                custom.ping.as_str(),
+                "http://127.0.0.1:8080/lab/api/v1/orbs/ea2ea744/"
+            );
+            assert_eq!(
+                custom.state.as_str(),
+                "http://127.0.0.1:8080/lab/api/v1/orbs/ea2ea744/state"
+            );
+        }
     }
 }
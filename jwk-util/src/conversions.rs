@@ -1,3 +1,18 @@
+use std::collections::BTreeMap;
+
+use base64::Engine as _;
+use color_eyre::eyre::{bail, ensure, eyre, WrapErr as _};
+use ed25519_dalek::pkcs8::{
+    spki::der::pem::LineEnding, DecodePrivateKey as _, DecodePublicKey as _,
+    EncodePrivateKey as _, EncodePublicKey as _,
+};
+use jose_jwk::{
+    jose_jwa::{Algorithm, Signing},
+    Ec, EcCurves, Jwk, JwkSet, OkpCurves, Operations,
+};
+use sha2::{Digest as _, Sha256};
+use zeroize::Zeroizing;
+
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum ExportPrivKeys {
     True,
@@ -34,14 +49,251 @@ pub fn dalek_signing_key_to_jwk(
     }
 }
 
+/// A key of one of the types that the orb uses.
+#[derive(Debug, Clone)]
+pub enum Key {
+    Ed25519Private(ed25519_dalek::SigningKey),
+    Ed25519Public(ed25519_dalek::VerifyingKey),
+    /// Used by the secure element.
+    P256Private(p256::SecretKey),
+    P256Public(p256::PublicKey),
+}
+
+impl Key {
+    /// Parses a PEM encoded PKCS8 private key.
+    pub fn from_pkcs8_pem(pem: &str) -> color_eyre::Result<Self> {
+        if let Ok(key) = ed25519_dalek::SigningKey::from_pkcs8_pem(pem) {
+            return Ok(Self::Ed25519Private(key));
+        }
+        p256::SecretKey::from_pkcs8_pem(pem)
+            .map(Self::P256Private)
+            .wrap_err("failed to parse PEM contents into ed25519 or P-256 private key")
+    }
+
+    /// Parses a PEM encoded public key, as a SubjectPublicKeyInfo.
+    pub fn from_public_key_pem(pem: &str) -> color_eyre::Result<Self> {
+        if let Ok(key) = ed25519_dalek::VerifyingKey::from_public_key_pem(pem) {
+            return Ok(Self::Ed25519Public(key));
+        }
+        p256::PublicKey::from_public_key_pem(pem)
+            .map(Self::P256Public)
+            .wrap_err("failed to parse PEM contents into ed25519 or P-256 public key")
+    }
+
+    /// Converts a JWK. Its private key, if any, must match its public key.
+    pub fn from_jwk(jwk: &Jwk) -> color_eyre::Result<Self> {
+        match &jwk.key {
+            jose_jwk::Key::Okp(okp) => {
+                ensure!(
+                    okp.crv == OkpCurves::Ed25519,
+                    "unsupported OKP curve {:?}",
+                    okp.crv
+                );
+                let public = ed25519_dalek::VerifyingKey::try_from(&okp.x[..])
+                    .wrap_err("invalid ed25519 public key")?;
+                let Some(d) = &okp.d else {
+                    return Ok(Self::Ed25519Public(public));
+                };
+                let private = ed25519_dalek::SigningKey::try_from(&d[..])
+                    .wrap_err("invalid ed25519 private key")?;
+                ensure!(
+                    private.verifying_key() == public,
+                    "ed25519 private key doesn't match the public key"
+                );
+                Ok(Self::Ed25519Private(private))
+            }
+            jose_jwk::Key::Ec(ec) => {
+                ensure!(
+                    ec.crv == EcCurves::P256,
+                    "unsupported EC curve {:?}",
+                    ec.crv
+                );
+                let public = p256::PublicKey::try_from(ec)
+                    .map_err(|err| eyre!("invalid P-256 public key: {err:?}"))?;
+                if ec.d.is_none() {
+                    return Ok(Self::P256Public(public));
+                }
+                let private = p256::SecretKey::try_from(ec)
+                    .map_err(|err| eyre!("invalid P-256 private key: {err:?}"))?;
+                ensure!(
+                    private.public_key() == public,
+                    "P-256 private key doesn't match the public key"
+                );
+                Ok(Self::P256Private(private))
+            }
+            _ => bail!("unsupported key type, only OKP and EC keys are supported"),
+        }
+    }
+
+    /// Returns the public part of the key.
+    #[must_use]
+    pub fn to_public(&self) -> Self {
+        match self {
+            Self::Ed25519Private(key) => Self::Ed25519Public(key.verifying_key()),
+            Self::P256Private(key) => Self::P256Public(key.public_key()),
+            Self::Ed25519Public(_) | Self::P256Public(_) => self.clone(),
+        }
+    }
+
+    /// Converts the key to a JWK.
+    ///
+    /// # Errors
+    /// If the private key should be exported but this is a public key.
+    pub fn to_jwk(&self, export_priv_keys: ExportPrivKeys) -> color_eyre::Result<Jwk> {
+        let (key, alg) = match (self, export_priv_keys) {
+            (Self::Ed25519Private(key), _) => {
+                return Ok(dalek_signing_key_to_jwk(key, export_priv_keys));
+            }
+            (Self::Ed25519Public(_) | Self::P256Public(_), ExportPrivKeys::True) => {
+                bail!("input doesn't have a private key to export")
+            }
+            (Self::Ed25519Public(key), ExportPrivKeys::False) => (
+                jose_jwk::Key::Okp(jose_jwk::Okp {
+                    crv: OkpCurves::Ed25519,
+                    x: key.as_bytes().to_vec().into_boxed_slice().into(),
+                    d: None,
+                }),
+                Signing::EdDsa,
+            ),
+            (Self::P256Private(key), ExportPrivKeys::True) => {
+                (jose_jwk::Key::Ec(Ec::from(key)), Signing::Es256)
+            }
+            (Self::P256Private(key), ExportPrivKeys::False) => (
+                jose_jwk::Key::Ec(Ec::from(key.public_key())),
+                Signing::Es256,
+            ),
+            (Self::P256Public(key), ExportPrivKeys::False) => {
+                (jose_jwk::Key::Ec(Ec::from(key)), Signing::Es256)
+            }
+        };
+        Ok(Jwk {
+            key,
+            prm: jose_jwk::Parameters {
+                alg: Some(Algorithm::Signing(alg)),
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Encodes the private key as PEM encoded PKCS8.
+    pub fn to_pkcs8_pem(&self) -> color_eyre::Result<Zeroizing<String>> {
+        match self {
+            Self::Ed25519Private(key) => key.to_pkcs8_pem(LineEnding::LF),
+            Self::P256Private(key) => key.to_pkcs8_pem(LineEnding::LF),
+            Self::Ed25519Public(_) | Self::P256Public(_) => {
+                bail!("input doesn't have a private key to export")
+            }
+        }
+        .wrap_err("failed to encode private key as PKCS8")
+    }
+
+    /// Encodes the public key as a PEM encoded SubjectPublicKeyInfo.
+    pub fn to_public_key_pem(&self) -> color_eyre::Result<String> {
+        match self.to_public() {
+            Self::Ed25519Public(key) => key.to_public_key_pem(LineEnding::LF),
+            Self::P256Public(key) => key.to_public_key_pem(LineEnding::LF),
+            Self::Ed25519Private(_) | Self::P256Private(_) => unreachable!(),
+        }
+        .wrap_err("failed to encode public key as PEM")
+    }
+}
+
+/// Converts the key of `jwk`, keeping its parameters, e.g. `kid`, `use` or `key_ops`.
+///
+/// The `alg` is set from the key type, and an `alg` of `jwk` that contradicts it is an
+/// error. Unless private keys are exported, `key_ops` that require the private key are
+/// dropped, and `key_ops` is omitted if none remain.
+pub fn convert_jwk(
+    jwk: &Jwk,
+    export_priv_keys: ExportPrivKeys,
+) -> color_eyre::Result<Jwk> {
+    let converted = Key::from_jwk(jwk)?.to_jwk(export_priv_keys)?;
+    let mut prm = jwk.prm.clone();
+    if let Some(alg) = &prm.alg {
+        ensure!(
+            Some(alg) == converted.prm.alg.as_ref(),
+            "`alg` {alg:?} does not match the key, expected {:?}",
+            converted.prm.alg
+        );
+    }
+    prm.alg = converted.prm.alg;
+    if export_priv_keys == ExportPrivKeys::False {
+        if let Some(ops) = &mut prm.ops {
+            ops.retain(|op| {
+                matches!(
+                    op,
+                    Operations::Verify | Operations::Encrypt | Operations::WrapKey
+                )
+            });
+            if ops.is_empty() {
+                prm.ops = None;
+            }
+        }
+    }
+    Ok(Jwk {
+        key: converted.key,
+        prm,
+    })
+}
+
+/// Computes the RFC 7638 thumbprint of `jwk`, which is suitable as its `kid`.
+///
+/// The thumbprint is the base64url encoded SHA-256 hash of the required public members
+/// of the key, serialized as JSON without whitespace and in lexicographic order.
+pub fn thumbprint(jwk: &Jwk) -> color_eyre::Result<String> {
+    let members: &[&str] = match &jwk.key {
+        jose_jwk::Key::Ec(_) => &["crv", "kty", "x", "y"],
+        jose_jwk::Key::Rsa(_) => &["e", "kty", "n"],
+        jose_jwk::Key::Oct(_) => &["k", "kty"],
+        jose_jwk::Key::Okp(_) => &["crv", "kty", "x"],
+        _ => bail!("unsupported key type"),
+    };
+    let serde_json::Value::Object(mut key) = serde_json::to_value(&jwk.key)? else {
+        unreachable!("keys are serialized as objects");
+    };
+    let members = members
+        .iter()
+        .map(|&member| {
+            let value = key
+                .remove(member)
+                .ok_or_else(|| eyre!("key is missing the `{member}` member"))?;
+            Ok((member, value))
+        })
+        .collect::<color_eyre::Result<BTreeMap<_, _>>>()?;
+    let hash = Sha256::digest(serde_json::to_vec(&members)?);
+    Ok(base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(hash))
+}
+
+/// Sets the `kid` of `jwk` to its [`thumbprint`].
+pub fn set_thumbprint_kid(jwk: &mut Jwk) -> color_eyre::Result<()> {
+    jwk.prm.kid = Some(thumbprint(jwk)?);
+    Ok(())
+}
+
+/// Merges `jwks` into a JWKS document, in order. Duplicates of the same key are dropped.
+///
+/// # Errors
+/// If different keys have the same `kid`.
+pub fn merge_jwks(jwks: impl IntoIterator<Item = Jwk>) -> color_eyre::Result<JwkSet> {
+    let mut keys: Vec<Jwk> = Vec::new();
+    for jwk in jwks {
+        if keys.contains(&jwk) {
+            continue;
+        }
+        if let Some(kid) = &jwk.prm.kid {
+            ensure!(
+                keys.iter().all(|key| key.prm.kid.as_ref() != Some(kid)),
+                "different keys have the same kid `{kid}`"
+            );
+        }
+        keys.push(jwk);
+    }
+    Ok(JwkSet { keys })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use base64::Engine as _;
-    use jose_jwk::Jwk;
-
-    use ed25519_dalek::pkcs8::DecodePublicKey as _;
-    use pkcs8::DecodePrivateKey as _;
 
     #[test]
     fn test_signing_key_to_jwk_with_rfc_test_vector() {
@@ -147,4 +399,161 @@ MC4CAQAwBQYDK2VwBCIEIIVbQpRCadJy15vk6BQeli1HtD54/QP5a4QJMtZVV0P5
             "converting dalek to jwk should match the expected result"
         );
     }
+
+    #[test]
+    fn test_rfc8037_thumbprint() {
+        // See https://datatracker.ietf.org/doc/html/rfc8037#appendix-A.3
+        let jwk: Jwk = serde_json::from_value(serde_json::json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo",
+            "d": "nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A",
+        }))
+        .unwrap();
+        assert_eq!(
+            thumbprint(&jwk).unwrap(),
+            "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k"
+        );
+    }
+
+    #[test]
+    fn test_rfc7638_thumbprint() {
+        // See https://datatracker.ietf.org/doc/html/rfc7638#section-3.1
+        let jwk: Jwk = serde_json::from_value(serde_json::json!({
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29",
+        }))
+        .unwrap();
+        assert_eq!(
+            thumbprint(&jwk).unwrap(),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+    }
+
+    #[test]
+    fn test_p256_conversions() {
+        let secret_key = p256::SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let private = Key::P256Private(secret_key.clone());
+
+        // PKCS8 -> JWK -> PKCS8
+        let pem = private.to_pkcs8_pem().unwrap();
+        let jwk = Key::from_pkcs8_pem(&pem)
+            .unwrap()
+            .to_jwk(ExportPrivKeys::True)
+            .unwrap();
+        assert_eq!(jwk.prm.alg, Some(Algorithm::Signing(Signing::Es256)));
+        let jose_jwk::Key::Ec(ec) = &jwk.key else {
+            panic!("expected an EC key");
+        };
+        assert_eq!(ec.crv, EcCurves::P256);
+        assert_eq!(&ec.d.as_ref().unwrap()[..], &secret_key.to_bytes()[..]);
+        assert_eq!(*Key::from_jwk(&jwk).unwrap().to_pkcs8_pem().unwrap(), *pem);
+
+        // PEM public key -> JWK, same as the public part of the private key.
+        let public_pem = private.to_public_key_pem().unwrap();
+        let public_jwk = Key::from_public_key_pem(&public_pem)
+            .unwrap()
+            .to_jwk(ExportPrivKeys::False)
+            .unwrap();
+        assert_eq!(public_jwk, private.to_jwk(ExportPrivKeys::False).unwrap());
+        assert!(Key::from_jwk(&public_jwk).unwrap().to_pkcs8_pem().is_err());
+        assert!(Key::from_public_key_pem(&public_pem)
+            .unwrap()
+            .to_jwk(ExportPrivKeys::True)
+            .is_err());
+    }
+
+    #[test]
+    fn test_ed25519_jwk_to_pkcs8() {
+        let jwk: Jwk = serde_json::from_value(serde_json::json!({
+            "kty": "OKP",
+            "alg": "EdDSA",
+            "crv": "Ed25519",
+            "x": "qhVpW12CnO55bQ2625kaWNCz9Uh5SNk7bctS9ieVgL0",
+            "d": "hVtClEJp0nLXm-ToFB6WLUe0Pnj9A_lrhAky1lVXQ_k"
+        }))
+        .unwrap();
+        let pem = Key::from_jwk(&jwk).unwrap().to_pkcs8_pem().unwrap();
+        let signing_key = ed25519_dalek::SigningKey::from_pkcs8_pem(&pem).unwrap();
+        assert_eq!(
+            dalek_signing_key_to_jwk(&signing_key, ExportPrivKeys::True),
+            jwk
+        );
+
+        let mut mismatched = jwk.clone();
+        let jose_jwk::Key::Okp(okp) = &mut mismatched.key else {
+            unreachable!();
+        };
+        okp.x = vec![0; 32].into_boxed_slice().into();
+        assert!(Key::from_jwk(&mismatched).is_err());
+    }
+
+    #[test]
+    fn test_convert_jwk_keeps_parameters() {
+        let jwk: Jwk = serde_json::from_value(serde_json::json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": "qhVpW12CnO55bQ2625kaWNCz9Uh5SNk7bctS9ieVgL0",
+            "d": "hVtClEJp0nLXm-ToFB6WLUe0Pnj9A_lrhAky1lVXQ_k",
+            "kid": "signup",
+            "use": "sig",
+            "key_ops": ["sign", "verify"]
+        }))
+        .unwrap();
+        let public = convert_jwk(&jwk, ExportPrivKeys::False).unwrap();
+        assert_eq!(
+            serde_json::to_value(&public).unwrap(),
+            serde_json::json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": "qhVpW12CnO55bQ2625kaWNCz9Uh5SNk7bctS9ieVgL0",
+                "alg": "EdDSA",
+                "kid": "signup",
+                "use": "sig",
+                "key_ops": ["verify"]
+            })
+        );
+
+        let mut with_alg = jwk.clone();
+        with_alg.prm.alg = Some(Algorithm::Signing(Signing::EdDsa));
+        let private = convert_jwk(&with_alg, ExportPrivKeys::True).unwrap();
+        assert_eq!(private.prm, with_alg.prm);
+        assert_eq!(private.key, jwk.key);
+
+        let mut sign_only = jwk.clone();
+        sign_only.prm.ops = Some([Operations::Sign].into());
+        let public = convert_jwk(&sign_only, ExportPrivKeys::False).unwrap();
+        assert_eq!(public.prm.ops, None);
+    }
+
+    #[test]
+    fn test_convert_jwk_rejects_mismatched_alg() {
+        let key = Key::Ed25519Private(ed25519_dalek::SigningKey::from_bytes(&[1; 32]));
+        let mut jwk = key.to_jwk(ExportPrivKeys::True).unwrap();
+        jwk.prm.alg = Some(Algorithm::Signing(Signing::Es256));
+        assert!(convert_jwk(&jwk, ExportPrivKeys::False).is_err());
+        assert!(convert_jwk(&jwk, ExportPrivKeys::True).is_err());
+    }
+
+    #[test]
+    fn test_merge_jwks() {
+        let key = |byte| {
+            let mut jwk =
+                Key::P256Private(p256::SecretKey::from_slice(&[byte; 32]).unwrap())
+                    .to_jwk(ExportPrivKeys::False)
+                    .unwrap();
+            set_thumbprint_kid(&mut jwk).unwrap();
+            jwk
+        };
+        let jwks = merge_jwks([key(1), key(2), key(1)]).unwrap();
+        assert_eq!(jwks.keys, [key(1), key(2)]);
+        assert_ne!(jwks.keys[0].prm.kid, jwks.keys[1].prm.kid);
+
+        let mut conflicting = key(3);
+        conflicting.prm.kid.clone_from(&key(1).prm.kid);
+        assert!(merge_jwks([key(1), conflicting]).is_err());
+    }
 }
@@ -4,12 +4,15 @@ use std::io::{Read as _, Write as _};
 
 use clap::{
     builder::{styling::AnsiColor, Styles},
-    command, Parser,
+    Parser,
 };
-use color_eyre::eyre::{ensure, WrapErr as _};
-use ed25519_dalek::pkcs8::DecodePrivateKey as _;
+use color_eyre::eyre::{bail, ensure, WrapErr as _};
+use jose_jwk::{Jwk, JwkSet};
+use zeroize::Zeroizing;
 
-use crate::conversions::{dalek_signing_key_to_jwk, ExportPrivKeys};
+use crate::conversions::{
+    convert_jwk, merge_jwks, set_thumbprint_kid, ExportPrivKeys, Key,
+};
 
 const BUILD_INFO: orb_build_info::BuildInfo = orb_build_info::make_build_info!();
 
@@ -34,9 +37,10 @@ struct Args {
     /// The output format
     #[clap(long)]
     out_fmt: Format,
-    /// The file to read as input.
-    #[clap(long)]
-    in_file: std::path::PathBuf,
+    /// The file to read as input. Can be passed several times to merge the keys into a
+    /// JWKS document.
+    #[clap(long, required = true)]
+    in_file: Vec<std::path::PathBuf>,
     /// The file to write as output.
     #[clap(long)]
     out_file: Option<std::path::PathBuf>,
@@ -44,52 +48,122 @@ struct Args {
     /// input format doesn't have any private keys
     #[clap(long)]
     export_priv_keys: bool,
+    /// Sets the `kid` of the output JWKs to their RFC 7638 thumbprint.
+    #[clap(long)]
+    thumbprint_kid: bool,
 }
 
 #[derive(Debug, Eq, PartialEq, clap::ValueEnum, Clone, Copy)]
 enum Format {
+    /// PEM encoded PKCS8 private key.
     Pkcs8,
+    /// PEM encoded public key.
+    Spki,
+    /// JSON Web Key.
     Jwk,
+    /// JSON Web Key Set.
+    Jwks,
 }
 
 fn main() -> color_eyre::Result<()> {
     let args = Args::parse();
 
     ensure!(
-        args.in_fmt == Format::Pkcs8,
-        "todo: input formats other than PKCS8 are not yet supported"
+        args.in_file.len() == 1 || args.out_fmt == Format::Jwks,
+        "several input files can only be merged into JWKS"
     );
+    ensure!(
+        !args.thumbprint_kid || matches!(args.out_fmt, Format::Jwk | Format::Jwks),
+        "--thumbprint-kid requires JWK or JWKS output"
+    );
+    let export_priv_keys = if args.export_priv_keys {
+        ExportPrivKeys::True
+    } else {
+        ExportPrivKeys::False
+    };
 
-    let mut pem_contents = String::new();
-    std::fs::File::open(args.in_file)
-        .wrap_err("failed to open in_file")?
-        .read_to_string(&mut pem_contents)
-        .wrap_err("error while reading file")?;
-
-    // TODO: We should also support pem public keys
-    let signing_key = ed25519_dalek::SigningKey::from_pkcs8_pem(&pem_contents)
-        .wrap_err("failed to parse PEM contents into ed25519 signing key")?;
+    let mut jwks = Vec::new();
+    for in_file in &args.in_file {
+        let mut contents = Zeroizing::new(String::new());
+        std::fs::File::open(in_file)
+            .wrap_err("failed to open in_file")?
+            .read_to_string(&mut contents)
+            .wrap_err("error while reading file")?;
+        jwks.extend(
+            read_keys(args.in_fmt, &contents)
+                .wrap_err_with(|| format!("failed to read {}", in_file.display()))?,
+        );
+    }
 
-    let jwk = dalek_signing_key_to_jwk(
-        &signing_key,
-        if args.export_priv_keys {
-            ExportPrivKeys::True
-        } else {
-            ExportPrivKeys::False
-        },
-    );
-    let jwk_string =
-        serde_json::to_string(&jwk).wrap_err("failed to serialize jwk to string")?;
+    let out_string = match args.out_fmt {
+        Format::Pkcs8 => {
+            let [key] = jwks.as_slice() else {
+                bail!("PKCS8 output requires exactly one key");
+            };
+            Key::from_jwk(key)?.to_pkcs8_pem()?
+        }
+        Format::Spki => {
+            ensure!(
+                !args.export_priv_keys,
+                "PEM public keys can't include private keys"
+            );
+            let [key] = jwks.as_slice() else {
+                bail!("PEM public key output requires exactly one key");
+            };
+            Zeroizing::new(Key::from_jwk(key)?.to_public_key_pem()?)
+        }
+        Format::Jwk | Format::Jwks => {
+            let mut out_jwks = jwks
+                .iter()
+                .map(|jwk| convert_jwk(jwk, export_priv_keys))
+                .collect::<color_eyre::Result<Vec<_>>>()?;
+            if args.thumbprint_kid {
+                for jwk in &mut out_jwks {
+                    set_thumbprint_kid(jwk)?;
+                }
+            }
+            let json = if args.out_fmt == Format::Jwks {
+                serde_json::to_string_pretty(&merge_jwks(out_jwks)?)
+            } else {
+                let [jwk] = out_jwks.as_slice() else {
+                    bail!("JWK output requires exactly one key, use JWKS for more");
+                };
+                serde_json::to_string(jwk)
+            };
+            Zeroizing::new(json.wrap_err("failed to serialize jwk to string")?)
+        }
+    };
 
     if let Some(out_path) = args.out_file {
         let mut out_file =
             std::fs::File::create(out_path).wrap_err("failed to create output file")?;
         out_file
-            .write_all(jwk_string.as_bytes())
+            .write_all(out_string.as_bytes())
             .wrap_err("failed to write to file")?;
     } else {
-        println!("{jwk_string}");
+        println!("{}", out_string.as_str());
     }
 
     Ok(())
 }
+
+/// Reads all keys in `contents` as JWKs, including their private keys.
+fn read_keys(format: Format, contents: &str) -> color_eyre::Result<Vec<Jwk>> {
+    // PEM decoding is strict about surrounding whitespace.
+    let contents = contents.trim();
+    let jwks = match format {
+        Format::Pkcs8 => {
+            vec![Key::from_pkcs8_pem(contents)?.to_jwk(ExportPrivKeys::True)?]
+        }
+        Format::Spki => {
+            vec![Key::from_public_key_pem(contents)?.to_jwk(ExportPrivKeys::False)?]
+        }
+        Format::Jwk => vec![serde_json::from_str(contents).wrap_err("invalid JWK")?],
+        Format::Jwks => {
+            serde_json::from_str::<JwkSet>(contents)
+                .wrap_err("invalid JWKS")?
+                .keys
+        }
+    };
+    Ok(jwks)
+}
@@ -0,0 +1,78 @@
+//! Canonical encoding of [`UserData`](crate::UserData) for hashing.
+//!
+//! Every field is encoded as `tag (1) || length (8, little endian) || value`, in ascending
+//! tag order. The tags and length prefixes make the encoding injective, so no two distinct
+//! values hash the same input. Optional fields that are `None` are omitted entirely, so that
+//! adding a new optional field keeps the hashes of all data that doesn't use it.
+
+/// Builder for a canonical encoding.
+#[derive(Default)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+    last_tag: Option<u8>,
+}
+
+impl Encoder {
+    /// Appends the field `tag` with `value`.
+    ///
+    /// # Panics
+    ///
+    /// If `tag` is not greater than the previous tag, which would make the encoding
+    /// ambiguous.
+    pub fn field(&mut self, tag: u8, value: &[u8]) -> &mut Self {
+        assert!(
+            self.last_tag.map_or(true, |last_tag| tag > last_tag),
+            "fields must be encoded in ascending tag order"
+        );
+        self.last_tag = Some(tag);
+        self.bytes.push(tag);
+        self.bytes
+            .extend_from_slice(&(value.len() as u64).to_le_bytes());
+        self.bytes.extend_from_slice(value);
+        self
+    }
+
+    /// Appends the field `tag` with `value` if it is `Some`.
+    pub fn optional_field(&mut self, tag: u8, value: Option<&[u8]>) -> &mut Self {
+        if let Some(value) = value {
+            self.field(tag, value);
+        }
+        self
+    }
+
+    /// Returns the encoded fields.
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_layout() {
+        let bytes = Encoder::default()
+            .field(1, b"ab")
+            .optional_field(2, None)
+            .optional_field(3, Some(b""))
+            .finish();
+        assert_eq!(
+            bytes,
+            [
+                &[1][..],
+                &2u64.to_le_bytes(),
+                b"ab",
+                &[3],
+                &0u64.to_le_bytes()
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    #[should_panic = "ascending tag order"]
+    fn test_tags_must_ascend() {
+        Encoder::default().field(2, b"").field(1, b"");
+    }
+}
@@ -2,6 +2,8 @@ use data_encoding::BASE64_NOPAD;
 use thiserror::Error;
 use uuid::Uuid;
 
+use crate::{crc::crc16, Flags, HashVersion, UserData};
+
 /// QR-code decoding error returned by [`decode_qr`].
 #[derive(Error, Debug)]
 pub enum DecodeError {
@@ -14,23 +16,105 @@ pub enum DecodeError {
     /// Error decoding BASE64.
     #[error("invalid base64")]
     Base64,
+    /// Checksum doesn't match, the QR-code was likely misread.
+    #[error("qr-code checksum mismatch")]
+    Checksum,
+}
+
+/// Format version of a QR-code.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum QrVersion {
+    /// Session id and user data hash.
+    V3,
+    /// Flags, session id, user data hash and a checksum.
+    V4,
+}
+
+impl QrVersion {
+    /// How the user data hash of QR-codes of this version is calculated.
+    #[must_use]
+    pub fn hash_version(self) -> HashVersion {
+        match self {
+            Self::V3 => HashVersion::Legacy,
+            Self::V4 => HashVersion::V1,
+        }
+    }
+}
+
+/// Contents of a QR-code, returned by [`decode_qr`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DecodedQr {
+    /// Format version the QR-code was encoded with.
+    pub version: QrVersion,
+    /// Key of the user data on the backend.
+    pub session_id: Uuid,
+    /// Truncated hash of the user data, see [`UserData::verify`](crate::UserData::verify).
+    pub user_data_hash: Vec<u8>,
+    /// Signup flags, always empty for [`QrVersion::V3`].
+    pub flags: Flags,
+}
+
+impl DecodedQr {
+    /// Returns `true` if `user_data_hash` is the hash of `user_data`.
+    ///
+    /// The hash version is bound to the QR-code version, so that the legacy hash can't be
+    /// selected for version `'4'` QR-codes.
+    #[must_use]
+    pub fn verify(&self, user_data: &UserData) -> bool {
+        user_data.verify(self.version.hash_version(), &self.user_data_hash)
+    }
 }
 
-/// Parses `session_id` and `user_data_hash` from a QR-code string.
-pub fn decode_qr(qr: &str) -> Result<(Uuid, Vec<u8>), DecodeError> {
+/// Parses a QR-code string of any supported version.
+pub fn decode_qr(qr: &str) -> Result<DecodedQr, DecodeError> {
     let Some(version) = qr.bytes().next() else {
         return Err(DecodeError::Malformed);
     };
     match version {
         b'3' => decode_v3(qr),
+        b'4' => decode_v4(qr),
         _ => Err(DecodeError::UnsupportedVersion),
     }
 }
 
-fn decode_v3(qr: &str) -> Result<(Uuid, Vec<u8>), DecodeError> {
-    let Ok(payload) = BASE64_NOPAD.decode(qr[1..].as_bytes()) else {
+fn decode_v3(qr: &str) -> Result<DecodedQr, DecodeError> {
+    let Ok(payload) = BASE64_NOPAD.decode(&qr.as_bytes()[1..]) else {
         return Err(DecodeError::Base64);
     };
+    let (session_id, user_data_hash) = split_session_id(&payload)?;
+    Ok(DecodedQr {
+        version: QrVersion::V3,
+        session_id,
+        user_data_hash: user_data_hash.to_vec(),
+        flags: Flags::empty(),
+    })
+}
+
+fn decode_v4(qr: &str) -> Result<DecodedQr, DecodeError> {
+    let Ok(payload) = BASE64_NOPAD.decode(&qr.as_bytes()[1..]) else {
+        return Err(DecodeError::Base64);
+    };
+    let Some(crc_offset) = payload.len().checked_sub(2) else {
+        return Err(DecodeError::Malformed);
+    };
+    let (payload, crc) = payload.split_at(crc_offset);
+    let crc = u16::from_be_bytes(crc.try_into().unwrap());
+    if crc16(&[&qr.as_bytes()[..1], payload].concat()) != crc {
+        return Err(DecodeError::Checksum);
+    }
+    let Some((flags, payload)) = payload.split_first() else {
+        return Err(DecodeError::Malformed);
+    };
+    let (session_id, user_data_hash) = split_session_id(payload)?;
+    Ok(DecodedQr {
+        version: QrVersion::V4,
+        session_id,
+        user_data_hash: user_data_hash.to_vec(),
+        flags: Flags::from_bits(*flags),
+    })
+}
+
+fn split_session_id(payload: &[u8]) -> Result<(Uuid, &[u8]), DecodeError> {
     let Some(session_id) = payload.get(0..16) else {
         return Err(DecodeError::Malformed);
     };
@@ -38,6 +122,5 @@ fn decode_v3(qr: &str) -> Result<(Uuid, Vec<u8>), DecodeError> {
         return Err(DecodeError::Malformed);
     };
     let session_id = u128::from_be_bytes(session_id.try_into().unwrap());
-    let session_id = Uuid::from_u128(session_id);
-    Ok((session_id, user_data_hash.to_vec()))
+    Ok((Uuid::from_u128(session_id), user_data_hash))
 }
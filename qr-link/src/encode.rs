@@ -1,10 +1,16 @@
 use data_encoding::BASE64_NOPAD;
 use uuid::Uuid;
 
+use crate::{crc::crc16, Flags};
+
 /// QR-code version prefix.
 pub const QR_VERSION: u8 = b'3';
 
-/// Generates a QR-code string from `session_id` and `user_data_hash`.
+/// QR-code version prefix of the format with flags and a checksum.
+pub const QR_VERSION_V4: u8 = b'4';
+
+/// Generates a QR-code string from `session_id` and `user_data_hash`, which must be
+/// calculated with [`HashVersion::Legacy`](crate::HashVersion::Legacy).
 pub fn encode_qr(session_id: &Uuid, user_data_hash: impl AsRef<[u8]>) -> String {
     let mut payload = Vec::new();
     payload.extend_from_slice(&session_id.as_u128().to_be_bytes());
@@ -15,3 +21,25 @@ pub fn encode_qr(session_id: &Uuid, user_data_hash: impl AsRef<[u8]>) -> String
     BASE64_NOPAD.encode_append(&payload, &mut qr);
     qr
 }
+
+/// Generates a version `'4'` QR-code string from `flags`, `session_id` and
+/// `user_data_hash`, which must be calculated with [`HashVersion::V1`](crate::HashVersion::V1).
+///
+/// The payload is `flags || session_id || user_data_hash || crc`, where `crc` is a big
+/// endian CRC-16/CCITT-FALSE of the version prefix and everything before it.
+pub fn encode_qr_v4(
+    session_id: &Uuid,
+    flags: Flags,
+    user_data_hash: impl AsRef<[u8]>,
+) -> String {
+    let mut payload = vec![flags.bits()];
+    payload.extend_from_slice(&session_id.as_u128().to_be_bytes());
+    payload.extend_from_slice(user_data_hash.as_ref());
+    let crc = crc16(&[&[QR_VERSION_V4], payload.as_slice()].concat());
+    payload.extend_from_slice(&crc.to_be_bytes());
+
+    let mut qr = String::new();
+    qr.push(QR_VERSION_V4.into());
+    BASE64_NOPAD.encode_append(&payload, &mut qr);
+    qr
+}
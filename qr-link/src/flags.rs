@@ -0,0 +1,53 @@
+use std::ops::{BitOr, BitOrAssign};
+
+/// Signup flags carried by QR-codes since version `'4'`.
+///
+/// Unknown bits are preserved, so that flags added by newer Apps survive decoding by older
+/// Orbs.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Default)]
+pub struct Flags(u8);
+
+impl Flags {
+    /// The Orb should perform a user-centric signup.
+    pub const USER_CENTRIC_SIGNUP: Self = Self(1 << 0);
+    /// The Orb should perform an app-centric signup.
+    pub const APP_CENTRIC_SIGNUP: Self = Self(1 << 1);
+
+    /// No flags set.
+    #[must_use]
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Creates flags from their raw byte, keeping unknown bits.
+    #[must_use]
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    /// The raw byte of the flags.
+    #[must_use]
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Whether all flags in `other` are set.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Flags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
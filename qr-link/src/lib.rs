@@ -19,7 +19,7 @@
 //! Worldcoin App uploads user data and generates a QR-code.
 //!
 //! ```rust
-//! use orb_qr_link::{encode_qr, DataPolicy, UserData};
+//! use orb_qr_link::{encode_qr_v4, DataPolicy, Flags, HashVersion, UserData};
 //! use uuid::Uuid;
 //!
 //! // Generate a new session id and user data.
@@ -38,10 +38,12 @@
 //! // Upload `user_data` to the backend by the `session_id` key.
 //! // ...
 //!
-//! // Calculate a variable-length hash of `user_data`.
-//! let user_data_hash = user_data.hash(16);
-//! // Encode a new QR-code.
-//! let qr = encode_qr(&session_id, user_data_hash);
+//! // Calculate a variable-length hash of `user_data`. Version `'4'` QR-codes carry
+//! // `HashVersion::V1` hashes, which hash an unambiguous encoding of the fields.
+//! let user_data_hash = user_data.hash(HashVersion::V1, 16);
+//! // Encode a new QR-code. The version `'4'` format carries signup flags and a
+//! // checksum, `encode_qr` produces the legacy version `'3'` format.
+//! let qr = encode_qr_v4(&session_id, Flags::USER_CENTRIC_SIGNUP, user_data_hash);
 //!
 //! // Allow the Orb to scan the generated QR-code.
 //! // ...
@@ -57,8 +59,9 @@
 //! // Scan QR-code generated by the App.
 //! let qr = "3WVd+tbAtSgyH0Ce9uiKT9i063t/xG2HxTIhuNa+gNnM";
 //!
-//! // Decode the QR-code string.
-//! let (session_id, user_data_hash) = decode_qr(qr).unwrap();
+//! // Decode the QR-code string. Both version `'3'` and `'4'` are accepted, misread
+//! // version `'4'` QR-codes are rejected by their checksum.
+//! let decoded = decode_qr(qr).unwrap();
 //!
 //! // Download `user_data` from the backend by the `session_id` key.
 //! let user_data = UserData {
@@ -72,21 +75,27 @@
 //! };
 //!
 //! // Verify that the `user_data_hash` from the QR-code matches `user_data`
-//! // from the backend.
-//! let success = user_data.verify(user_data_hash);
+//! // from the backend. The hash version is selected by the QR-code version, never
+//! // by the downloaded data.
+//! let success = decoded.verify(&user_data);
 //! ```
 
 #![forbid(unsafe_code)]
 #![warn(missing_docs)]
 
+mod canonical;
+#[cfg(any(feature = "encode", feature = "decode"))]
+mod crc;
 #[cfg(feature = "decode")]
 mod decode;
 #[cfg(feature = "encode")]
 mod encode;
+mod flags;
 mod user_data;
 
 #[cfg(feature = "decode")]
-pub use decode::{decode_qr, DecodeError};
+pub use decode::{decode_qr, DecodeError, DecodedQr, QrVersion};
 #[cfg(feature = "encode")]
-pub use encode::encode_qr;
-pub use user_data::{DataPolicy, UserData};
+pub use encode::{encode_qr, encode_qr_v4};
+pub use flags::Flags;
+pub use user_data::{DataPolicy, HashVersion, UserData};
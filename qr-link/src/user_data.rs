@@ -1,13 +1,28 @@
 use blake3::Hasher;
 use serde::{Deserialize, Serialize};
 
+use crate::canonical::Encoder;
+
 const PCP_VERSION_DEFAULT: u16 = 2;
 
+/// BLAKE3 key derivation context of [`HashVersion::V1`], for domain separation.
+const HASH_V1_CONTEXT: &str = "orb-qr-link UserData hash v1";
+
+// Tags of the fields in the canonical encoding. Never reuse or reorder them, new fields get
+// new tags.
+const TAG_IDENTITY_COMMITMENT: u8 = 1;
+const TAG_SELF_CUSTODY_PUBLIC_KEY: u8 = 2;
+const TAG_DATA_POLICY: u8 = 3;
+const TAG_PCP_VERSION: u8 = 4;
+const TAG_USER_CENTRIC_SIGNUP: u8 = 5;
+const TAG_ORB_RELAY_APP_ID: u8 = 6;
+const TAG_BYPASS_AGE_VERIFICATION_TOKEN: u8 = 7;
+
 // TODO(andronat): Some of these flags and types should be refactored (e.g. delete `user_centric_signup`) after both Orb
 // and Worldcoin App are rolled out with their latest versions.
 
 /// User's data to transfer from Worldcoin App to Orb.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct UserData {
     /// Identity commitment.
@@ -28,6 +43,19 @@ pub struct UserData {
     pub bypass_age_verification_token: Option<String>,
 }
 
+/// How [`UserData`] is hashed.
+///
+/// The version is never taken from the user data itself, which is downloaded from the
+/// backend, but from the QR-code that carries the hash.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum HashVersion {
+    /// Fields are concatenated as-is, which is ambiguous, e.g. `"ab" + "c"` hashes the same
+    /// as `"a" + "bc"`. Only kept to verify version `'3'` QR-codes of older Apps.
+    Legacy,
+    /// Fields are hashed in their [canonical encoding](UserData::canonical_encoding).
+    V1,
+}
+
 /// User's biometric data policy. Part of [`UserData`].
 #[derive(Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
@@ -41,27 +69,77 @@ pub enum DataPolicy {
 }
 
 impl UserData {
-    /// Returns `true` if `hash` is a BLAKE3 hash of this [`UserData`].
+    /// Returns `true` if `hash` is a BLAKE3 hash of this [`UserData`] of the given
+    /// `version`.
     ///
     /// This method calculates its own hash of the same length as the input
     /// `hash` and checks if both hashes are identical.
-    pub fn verify(&self, hash: impl AsRef<[u8]>) -> bool {
+    pub fn verify(&self, version: HashVersion, hash: impl AsRef<[u8]>) -> bool {
         let external_hash = hash.as_ref();
-        let internal_hash = self.hash(external_hash.len());
+        let internal_hash = self.hash(version, external_hash.len());
         external_hash == internal_hash
     }
 
-    /// Calculates a BLAKE3 hash of the length `n`.
-    pub fn hash(&self, n: usize) -> Vec<u8> {
-        let mut hasher = Hasher::new();
-        self.hasher_update(&mut hasher);
+    /// Calculates a BLAKE3 hash of the given `version` and of the length `n`.
+    pub fn hash(&self, version: HashVersion, n: usize) -> Vec<u8> {
+        let hasher = match version {
+            HashVersion::Legacy => {
+                let mut hasher = Hasher::new();
+                self.legacy_hasher_update(&mut hasher);
+                hasher
+            }
+            HashVersion::V1 => {
+                let mut hasher = Hasher::new_derive_key(HASH_V1_CONTEXT);
+                hasher.update(&self.canonical_encoding());
+                hasher
+            }
+        };
         let mut output = vec![0; n];
         hasher.finalize_xof().fill(&mut output);
         output
     }
 
-    // This method must hash every field.
-    fn hasher_update(&self, hasher: &mut Hasher) {
+    /// Returns the unambiguous encoding of every field that is hashed by
+    /// [`HashVersion::V1`].
+    ///
+    /// Every field is encoded as `tag (1) || length (8, little endian) || value`, in
+    /// ascending tag order. Integers are little endian, booleans and [`DataPolicy`] a single
+    /// byte, and optional fields are omitted if they are `None`.
+    #[must_use]
+    pub fn canonical_encoding(&self) -> Vec<u8> {
+        // This method must encode every field.
+        let Self {
+            identity_commitment,
+            self_custody_public_key,
+            data_policy,
+            pcp_version,
+            user_centric_signup,
+            orb_relay_app_id,
+            bypass_age_verification_token,
+        } = self;
+        Encoder::default()
+            .field(TAG_IDENTITY_COMMITMENT, identity_commitment.as_bytes())
+            .field(
+                TAG_SELF_CUSTODY_PUBLIC_KEY,
+                self_custody_public_key.as_bytes(),
+            )
+            .field(TAG_DATA_POLICY, &[*data_policy as u8])
+            .field(TAG_PCP_VERSION, &pcp_version.to_le_bytes())
+            .field(TAG_USER_CENTRIC_SIGNUP, &[u8::from(*user_centric_signup)])
+            .optional_field(
+                TAG_ORB_RELAY_APP_ID,
+                orb_relay_app_id.as_deref().map(str::as_bytes),
+            )
+            .optional_field(
+                TAG_BYPASS_AGE_VERIFICATION_TOKEN,
+                bypass_age_verification_token.as_deref().map(str::as_bytes),
+            )
+            .finish()
+    }
+
+    // This method must hash every field. New fields should only be added to the canonical
+    // encoding.
+    fn legacy_hasher_update(&self, hasher: &mut Hasher) {
         let Self {
             identity_commitment,
             self_custody_public_key,
@@ -117,3 +195,173 @@ const fn pcp_version_default() -> u16 {
 const fn default_false() -> bool {
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn user_data() -> UserData {
+        UserData {
+            identity_commitment: "0xabcd".to_string(),
+            self_custody_public_key: "key".to_string(),
+            data_policy: DataPolicy::OptOut,
+            pcp_version: 3,
+            user_centric_signup: true,
+            orb_relay_app_id: Some("123123".to_string()),
+            bypass_age_verification_token: None,
+        }
+    }
+
+    /// Inverse of [`UserData::canonical_encoding`], its existence proves the encoding
+    /// is injective.
+    fn decode_canonical(mut bytes: &[u8]) -> UserData {
+        let mut fields = Vec::new();
+        while let Some((&tag, rest)) = bytes.split_first() {
+            let (len, rest) = rest.split_at(8);
+            let len =
+                usize::try_from(u64::from_le_bytes(len.try_into().unwrap())).unwrap();
+            let (value, rest) = rest.split_at(len);
+            fields.push((tag, value));
+            bytes = rest;
+        }
+        let field = |tag| {
+            fields
+                .iter()
+                .find(|(field_tag, _)| *field_tag == tag)
+                .map(|(_, value)| *value)
+        };
+        let string = |tag| {
+            field(tag).map(|value: &[u8]| String::from_utf8(value.to_vec()).unwrap())
+        };
+        UserData {
+            identity_commitment: string(TAG_IDENTITY_COMMITMENT).unwrap(),
+            self_custody_public_key: string(TAG_SELF_CUSTODY_PUBLIC_KEY).unwrap(),
+            data_policy: match field(TAG_DATA_POLICY).unwrap() {
+                [0] => DataPolicy::OptOut,
+                [1] => DataPolicy::FullDataOptIn,
+                value => panic!("invalid data policy {value:?}"),
+            },
+            pcp_version: u16::from_le_bytes(
+                field(TAG_PCP_VERSION).unwrap().try_into().unwrap(),
+            ),
+            user_centric_signup: match field(TAG_USER_CENTRIC_SIGNUP).unwrap() {
+                [0] => false,
+                [1] => true,
+                value => panic!("invalid bool {value:?}"),
+            },
+            orb_relay_app_id: string(TAG_ORB_RELAY_APP_ID),
+            bypass_age_verification_token: string(TAG_BYPASS_AGE_VERIFICATION_TOKEN),
+        }
+    }
+
+    fn arb_user_data() -> impl Strategy<Value = UserData> {
+        (
+            ".*",
+            ".*",
+            any::<bool>(),
+            any::<u16>(),
+            any::<bool>(),
+            proptest::option::of(".*"),
+            proptest::option::of(".*"),
+        )
+            .prop_map(
+                |(
+                    identity_commitment,
+                    self_custody_public_key,
+                    full_data_opt_in,
+                    pcp_version,
+                    user_centric_signup,
+                    orb_relay_app_id,
+                    bypass_age_verification_token,
+                )| UserData {
+                    identity_commitment,
+                    self_custody_public_key,
+                    data_policy: if full_data_opt_in {
+                        DataPolicy::FullDataOptIn
+                    } else {
+                        DataPolicy::OptOut
+                    },
+                    pcp_version,
+                    user_centric_signup,
+                    orb_relay_app_id,
+                    bypass_age_verification_token,
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn test_canonical_encoding_roundtrips(user_data in arb_user_data()) {
+            prop_assert_eq!(decode_canonical(&user_data.canonical_encoding()), user_data);
+        }
+
+        #[test]
+        fn test_distinct_user_data_hash_differently(
+            a in arb_user_data(),
+            b in arb_user_data(),
+        ) {
+            prop_assume!(a != b);
+            prop_assert_ne!(a.canonical_encoding(), b.canonical_encoding());
+            prop_assert_ne!(a.hash(HashVersion::V1, 32), b.hash(HashVersion::V1, 32));
+            prop_assert!(!a.verify(HashVersion::V1, b.hash(HashVersion::V1, 16)));
+        }
+
+        #[test]
+        fn test_moved_field_boundary_is_detected(
+            a in ".*",
+            b in ".*",
+            split in any::<prop::sample::Index>(),
+        ) {
+            let joined = format!("{a}{b}");
+            let boundaries = joined
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain([joined.len()])
+                .collect::<Vec<_>>();
+            let split = boundaries[split.index(boundaries.len())];
+            prop_assume!(split != a.len());
+            let mut first = user_data();
+            first.identity_commitment = a;
+            first.self_custody_public_key = b;
+            let mut second = user_data();
+            second.identity_commitment = joined[..split].to_string();
+            second.self_custody_public_key = joined[split..].to_string();
+            prop_assert!(!first.verify(HashVersion::V1, second.hash(HashVersion::V1, 16)));
+        }
+    }
+
+    #[test]
+    fn test_legacy_hash_is_ambiguous() {
+        let mut first = user_data();
+        first.identity_commitment = "ab".to_string();
+        first.self_custody_public_key = "c".to_string();
+        let mut second = first.clone();
+        second.identity_commitment = "a".to_string();
+        second.self_custody_public_key = "bc".to_string();
+        assert!(first.verify(HashVersion::Legacy, second.hash(HashVersion::Legacy, 16)));
+        assert!(!first.verify(HashVersion::V1, second.hash(HashVersion::V1, 16)));
+    }
+
+    #[test]
+    fn test_hash_versions_differ() {
+        let user_data = user_data();
+        let legacy = user_data.hash(HashVersion::Legacy, 16);
+        let v1 = user_data.hash(HashVersion::V1, 16);
+        assert_ne!(legacy, v1);
+        assert!(!user_data.verify(HashVersion::Legacy, &v1));
+        assert!(!user_data.verify(HashVersion::V1, &legacy));
+    }
+
+    /// Legacy hash of `user_data()`, as calculated before the canonical encoding was
+    /// introduced.
+    const LEGACY_HASH: [u8; 16] = [
+        241, 103, 205, 101, 18, 42, 117, 58, 93, 17, 10, 178, 127, 21, 127, 197,
+    ];
+
+    #[test]
+    fn test_legacy_hash_is_stable() {
+        assert_eq!(user_data().hash(HashVersion::Legacy, 16), LEGACY_HASH);
+    }
+}
@@ -1,4 +1,7 @@
-use orb_qr_link::{decode_qr, encode_qr, DataPolicy, UserData};
+use orb_qr_link::{
+    decode_qr, encode_qr, encode_qr_v4, DataPolicy, DecodeError, Flags, HashVersion,
+    QrVersion, UserData,
+};
 use uuid::Uuid;
 
 #[test]
@@ -17,10 +20,11 @@ MCowBQYDK2VuAyEA2boNBmJX4lGkA9kjthS5crXOBxu2BPycKRMakpzgLG4=
         orb_relay_app_id: Some("123123".to_string()),
         bypass_age_verification_token: None,
     };
-    let qr = encode_qr(&session_id, user_data.hash(16));
-    let (parsed_session_id, parsed_user_data_hash) = decode_qr(&qr).unwrap();
-    assert_eq!(parsed_session_id, session_id);
-    assert!(user_data.verify(parsed_user_data_hash));
+    let qr = encode_qr(&session_id, user_data.hash(HashVersion::Legacy, 16));
+    let decoded = decode_qr(&qr).unwrap();
+    assert_eq!(decoded.session_id, session_id);
+    assert_eq!(decoded.version, QrVersion::V3);
+    assert!(decoded.verify(&user_data));
 }
 
 #[test]
@@ -40,8 +44,96 @@ MCowBQYDK2VuAyEA2boNBmJX4lGkA9kjthS5crXOBxu2BPycKRMakpzgLG4=
         orb_relay_app_id: Some("123123".to_string()),
         bypass_age_verification_token: Some(sample_jwt_token.to_string()),
     };
-    let qr = encode_qr(&session_id, user_data.hash(16));
-    let (parsed_session_id, parsed_user_data_hash) = decode_qr(&qr).unwrap();
-    assert_eq!(parsed_session_id, session_id);
-    assert!(user_data.verify(parsed_user_data_hash));
+    let qr = encode_qr(&session_id, user_data.hash(HashVersion::Legacy, 16));
+    let decoded = decode_qr(&qr).unwrap();
+    assert_eq!(decoded.session_id, session_id);
+    assert_eq!(decoded.version, QrVersion::V3);
+    assert!(decoded.verify(&user_data));
+}
+
+#[test]
+fn test_encode_decode_verify_v4() {
+    let session_id = Uuid::new_v4();
+    let user_data = UserData {
+        identity_commitment: "0xabcd".to_string(),
+        self_custody_public_key: String::new(),
+        data_policy: DataPolicy::FullDataOptIn,
+        pcp_version: 3,
+        user_centric_signup: true,
+        orb_relay_app_id: Some("123123".to_string()),
+        bypass_age_verification_token: None,
+    };
+    let flags = Flags::USER_CENTRIC_SIGNUP | Flags::from_bits(0x80);
+    let qr = encode_qr_v4(&session_id, flags, user_data.hash(HashVersion::V1, 16));
+    assert!(qr.starts_with('4'));
+    let decoded = decode_qr(&qr).unwrap();
+    assert_eq!(decoded.version, QrVersion::V4);
+    assert_eq!(decoded.session_id, session_id);
+    assert_eq!(decoded.flags, flags);
+    assert!(decoded.flags.contains(Flags::USER_CENTRIC_SIGNUP));
+    assert!(!decoded.flags.contains(Flags::APP_CENTRIC_SIGNUP));
+    assert!(decoded.verify(&user_data));
+}
+
+#[test]
+fn test_v4_rejects_legacy_hash() {
+    let session_id = Uuid::new_v4();
+    let user_data = UserData {
+        identity_commitment: "0xabcd".to_string(),
+        self_custody_public_key: String::new(),
+        data_policy: DataPolicy::OptOut,
+        pcp_version: 3,
+        user_centric_signup: true,
+        orb_relay_app_id: None,
+        bypass_age_verification_token: None,
+    };
+    let legacy_hash = user_data.hash(HashVersion::Legacy, 16);
+    let decoded =
+        decode_qr(&encode_qr_v4(&session_id, Flags::empty(), &legacy_hash)).unwrap();
+    assert_eq!(decoded.version.hash_version(), HashVersion::V1);
+    assert!(!decoded.verify(&user_data));
+
+    let decoded = decode_qr(&encode_qr(&session_id, &legacy_hash)).unwrap();
+    assert_eq!(decoded.version.hash_version(), HashVersion::Legacy);
+    assert!(decoded.verify(&user_data));
+}
+
+#[test]
+fn test_decode_v3_fixture() {
+    let decoded = decode_qr("3WVd+tbAtSgyH0Ce9uiKT9i063t/xG2HxTIhuNa+gNnM").unwrap();
+    assert_eq!(decoded.version, QrVersion::V3);
+    assert_eq!(
+        decoded.session_id,
+        "59577eb5-b02d-4a0c-87d0-27bdba2293f6"
+            .parse::<Uuid>()
+            .unwrap()
+    );
+    assert_eq!(decoded.user_data_hash.len(), 16);
+    assert_eq!(decoded.flags, Flags::empty());
+}
+
+#[test]
+fn test_decode_v4_rejects_misreads() {
+    let qr = encode_qr_v4(&Uuid::new_v4(), Flags::APP_CENTRIC_SIGNUP, [7; 16]);
+    // Flip every character of the payload to another base64 character.
+    for i in 1..qr.len() {
+        let mut misread = qr.clone().into_bytes();
+        misread[i] = if misread[i] == b'A' { b'B' } else { b'A' };
+        let misread = String::from_utf8(misread).unwrap();
+        assert!(
+            matches!(
+                decode_qr(&misread),
+                Err(DecodeError::Checksum | DecodeError::Base64)
+            ),
+            "{misread} was accepted"
+        );
+    }
+    // A misread version prefix.
+    let misread = format!("3{}", &qr[1..]);
+    assert_ne!(decode_qr(&misread).unwrap().user_data_hash, [7; 16]);
+    assert!(matches!(decode_qr("4AA"), Err(DecodeError::Malformed)));
+    assert!(matches!(
+        decode_qr("5AAAA"),
+        Err(DecodeError::UnsupportedVersion)
+    ));
 }
@@ -27,6 +27,11 @@ impl BootChainEfiVars {
             next: db.get_var(PATH_NEXT)?,
         })
     }
+
+    /// All bootchain efivars.
+    pub fn vars(&self) -> [&EfiVar; 2] {
+        [&self.current, &self.next]
+    }
 }
 
 /// Throws an `Error` if the given slot is invalid.
@@ -82,6 +82,14 @@ pub struct EfiVar {
 }
 
 impl EfiVar {
+    /// The name of the efivar, including its vendor GUID.
+    pub fn name(&self) -> &str {
+        self.path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+    }
+
     /// Read the efivar data from a `path`.
     ///
     /// Errors: i/o specific on file operations and `InvalidEfiVarLen` if the data length is invalid.
@@ -94,6 +102,19 @@ impl EfiVar {
         Ok(buffer)
     }
 
+    /// Read the efivar data from a `path`, or `None` if the efivar does not exist.
+    pub fn read_if_exists(&self) -> Result<Option<Vec<u8>>, Error> {
+        match self.read() {
+            Ok(buf) => Ok(Some(buf)),
+            Err(Error::OpenFile { source, .. })
+                if source.kind() == io::ErrorKind::NotFound =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     /// Read the efivar data from a `path`.
     /// Validates the expected data length and saves the data to a `buffer`.
     ///
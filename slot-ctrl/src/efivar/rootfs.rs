@@ -70,6 +70,17 @@ impl RootfsEfiVars {
         })
     }
 
+    /// All rootfs efivars.
+    pub fn vars(&self) -> [&EfiVar; 5] {
+        [
+            &self.status_a,
+            &self.status_b,
+            &self.retry_count_a,
+            &self.retry_count_b,
+            &self.retry_count_max,
+        ]
+    }
+
     /// Get the raw rootfs status for a certain `slot`.
     pub fn get_rootfs_status(&self, slot: u8) -> Result<u8, Error> {
         let efivar = match slot {
@@ -3,23 +3,27 @@
 #![allow(clippy::missing_errors_doc)]
 
 use std::{
+    collections::BTreeMap,
     fmt, io,
     path::{Path, PathBuf},
 };
 
+use serde::Serialize;
+
 mod efivar;
 mod ioctl;
 pub mod program;
+pub mod rollback;
 
 pub mod test_utils;
 
 use efivar::{
-    bootchain::BootChainEfiVars, rootfs::RootfsEfiVars, EfiVarDbErr,
-    ROOTFS_STATUS_NORMAL, ROOTFS_STATUS_UNBOOTABLE, ROOTFS_STATUS_UPD_DONE,
-    ROOTFS_STATUS_UPD_IN_PROCESS, SLOT_A, SLOT_B,
+    bootchain::BootChainEfiVars, rootfs::RootfsEfiVars, ROOTFS_STATUS_NORMAL,
+    ROOTFS_STATUS_UNBOOTABLE, ROOTFS_STATUS_UPD_DONE, ROOTFS_STATUS_UPD_IN_PROCESS,
+    SLOT_A, SLOT_B,
 };
 
-pub use crate::efivar::{EfiVar, EfiVarDb};
+pub use crate::efivar::{EfiVar, EfiVarDb, EfiVarDbErr};
 
 /// Error definition for library.
 #[allow(missing_docs)]
@@ -55,6 +59,10 @@ pub enum Error {
     InvalidRootFsStatusData,
     #[error("invalid retry counter({counter}), exceeding the maximum ({max})")]
     ExceedingRetryCount { counter: u8, max: u8 },
+    #[error("refusing to roll back to slot {0}, its rootfs status is unbootable")]
+    RollbackTargetUnbootable(Slot),
+    #[error("rolled back, but failed recording the rollback: {0}")]
+    RecordRollback(#[from] rollback::Error),
 }
 
 #[allow(missing_docs)]
@@ -104,7 +112,8 @@ impl Error {
 }
 
 /// Representation of the slot.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum Slot {
     /// The Slot A is represented as 0.
@@ -124,7 +133,7 @@ impl fmt::Display for Slot {
 }
 
 /// Representation of the rootfs status.
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
 #[repr(u8)]
 pub enum RootFsStatus {
     /// Default status of the rootfs.
@@ -177,6 +186,34 @@ impl TryFrom<u8> for RootFsStatus {
     }
 }
 
+/// The rootfs state of a single slot.
+#[derive(Debug, Serialize)]
+pub struct SlotState {
+    /// The rootfs status of the slot.
+    pub rootfs_status: RootFsStatus,
+    /// The boot attempts left before falling back to the other slot.
+    pub retry_count: u8,
+}
+
+/// The complete slot and rootfs state of the Orb.
+#[derive(Debug, Serialize)]
+pub struct State {
+    /// The current active slot.
+    pub current_slot: Slot,
+    /// The slot set for the next boot.
+    pub next_slot: Slot,
+    /// The slot that is not active.
+    pub inactive_slot: Slot,
+    /// The state of slot A.
+    pub slot_a: SlotState,
+    /// The state of slot B.
+    pub slot_b: SlotState,
+    /// The maximum retry count before fallback.
+    pub max_retry_count: u8,
+    /// The raw contents of all efivars by name, `None` for efivars that do not exist yet.
+    pub efi_vars: BTreeMap<String, Option<Vec<u8>>>,
+}
+
 pub struct OrbSlotCtrl {
     bootchain: BootChainEfiVars,
     rootfs: RootfsEfiVars,
@@ -279,4 +316,51 @@ impl OrbSlotCtrl {
         let max_count = self.rootfs.get_max_retry_count()?;
         self.rootfs.set_retry_count(max_count, slot as u8)
     }
+
+    /// Get the complete slot and rootfs state, including the raw efivars it is read from.
+    pub fn get_state(&self) -> Result<State, Error> {
+        let slot_state = |slot| -> Result<SlotState, Error> {
+            Ok(SlotState {
+                rootfs_status: self.get_rootfs_status(slot)?,
+                retry_count: self.get_retry_count(slot)?,
+            })
+        };
+        let efi_vars = self
+            .bootchain
+            .vars()
+            .into_iter()
+            .chain(self.rootfs.vars())
+            .map(|var| Ok((var.name().to_string(), var.read_if_exists()?)))
+            .collect::<Result<_, Error>>()?;
+        Ok(State {
+            current_slot: self.get_current_slot()?,
+            next_slot: self.get_next_boot_slot()?,
+            inactive_slot: self.get_inactive_slot()?,
+            slot_a: slot_state(Slot::A)?,
+            slot_b: slot_state(Slot::B)?,
+            max_retry_count: self.get_max_retry_count()?,
+            efi_vars,
+        })
+    }
+
+    /// Roll back from the current slot to the inactive one, recording `reason` in the rollback
+    /// log at `log`.
+    ///
+    /// The rootfs of the current slot is marked unbootable and the inactive slot is set for the
+    /// next boot. The rollback takes effect on the next reboot and is refused if the inactive
+    /// slot is unbootable itself. Returns the slot that will be booted next.
+    ///
+    /// The rollback log is written last, so a [`Error::RecordRollback`] means that the slots were
+    /// switched nonetheless.
+    pub fn rollback(&self, reason: &str, log: &Path) -> Result<Slot, Error> {
+        let from = self.get_current_slot()?;
+        let to = self.get_inactive_slot()?;
+        if self.get_rootfs_status(to)?.is_unbootable() {
+            return Err(Error::RollbackTargetUnbootable(to));
+        }
+        self.set_rootfs_status(RootFsStatus::Unbootable, from)?;
+        self.set_next_boot_slot(to)?;
+        rollback::append(log, &rollback::Record::now(from, to, reason))?;
+        Ok(to)
+    }
 }
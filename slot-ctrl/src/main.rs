@@ -1,14 +1,19 @@
-use clap::Parser;
-use orb_slot_ctrl::{
-    program::{self, Cli},
-    EfiVarDb, OrbSlotCtrl,
-};
-
-fn main() -> eyre::Result<()> {
-    let cli = Cli::parse();
+use std::{env, process::ExitCode};
 
-    let db = EfiVarDb::from_rootfs("/")?;
-    let orb_slot_ctrl = OrbSlotCtrl::new(&db)?;
+use clap::Parser;
+use orb_slot_ctrl::program::{self, Cli};
 
-    program::run(&orb_slot_ctrl, cli)
+fn main() -> ExitCode {
+    match Cli::try_parse() {
+        Ok(cli) => program::main(&cli),
+        // Whether `--json` was given is unknown if parsing failed, so look for it directly.
+        // Help and version are printed as usual.
+        Err(error)
+            if error.use_stderr() && env::args_os().any(|arg| arg == "--json") =>
+        {
+            println!("{}", program::usage_error_json(&error));
+            ExitCode::from(program::EXIT_USAGE)
+        }
+        Err(error) => error.exit(),
+    }
 }
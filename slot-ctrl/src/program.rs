@@ -1,7 +1,8 @@
-use crate::OrbSlotCtrl;
+use crate::{rollback, EfiVarDb, EfiVarDbErr, OrbSlotCtrl, RootFsStatus, Slot, State};
 use clap::{Parser, Subcommand};
 use orb_build_info::{make_build_info, BuildInfo};
-use std::{env, process::exit};
+use serde_json::json;
+use std::{env, fmt, path::PathBuf, process::ExitCode};
 
 const BUILD_INFO: BuildInfo = make_build_info!();
 
@@ -13,6 +14,9 @@ const BUILD_INFO: BuildInfo = make_build_info!();
 )]
 #[allow(missing_docs)]
 pub struct Cli {
+    /// Print results and errors as JSON.
+    #[arg(long, global = true)]
+    json: bool,
     #[command(subcommand)]
     subcmd: Commands,
 }
@@ -36,6 +40,21 @@ enum Commands {
         #[command(subcommand)]
         subcmd: StatusCommands,
     },
+    /// Mark the current slot unbootable and boot the other slot next.
+    Rollback {
+        /// Why the rollback is done; recorded in the rollback log.
+        reason: String,
+        /// The rollback log to append to.
+        #[arg(long, default_value = crate::rollback::DEFAULT_LOG_PATH)]
+        log: PathBuf,
+    },
+    /// Print the rollbacks recorded in the rollback log.
+    #[command(name = "rollback-log")]
+    RollbackLog {
+        /// The rollback log to read.
+        #[arg(long, default_value = crate::rollback::DEFAULT_LOG_PATH)]
+        log: PathBuf,
+    },
     /// Get the git commit used for this build.
     #[command(name = "git", short_flag = 'g')]
     GitDescribe,
@@ -61,130 +80,305 @@ enum StatusCommands {
     /// Get a full list of rootfs status variants.
     #[command(name = "list", short_flag = 'l')]
     ListStatusVariants,
+    /// Get the complete slot and rootfs state of both slots, including the raw efivars.
+    #[command(name = "all", short_flag = 'a')]
+    GetState,
+}
+
+/// Exit code for failures without a more specific exit code.
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code for invalid arguments, matching the one used for command line parsing errors.
+pub const EXIT_USAGE: u8 = 2;
+/// Exit code for writes that failed because the tool does not run as root.
+pub const EXIT_PERMISSION_DENIED: u8 = 3;
+/// Exit code for efivars with unexpected contents.
+pub const EXIT_INVALID_EFIVARS: u8 = 4;
+/// Exit code for a refused rollback, because the other slot is unbootable.
+pub const EXIT_ROLLBACK_REFUSED: u8 = 5;
+
+/// Rootfs status variants with the aliases accepted by `status set`.
+const ROOTFS_STATUS_ALIASES: [(RootFsStatus, &[&str]); 4] = [
+    (RootFsStatus::Normal, &["normal", "0"]),
+    (
+        RootFsStatus::UpdateInProcess,
+        &["updateinprocess", "updinprocess", "1"],
+    ),
+    (RootFsStatus::UpdateDone, &["updatedone", "upddone", "2"]),
+    (RootFsStatus::Unbootable, &["unbootable", "3"]),
+];
+
+/// Errors of the command line tool.
+#[allow(missing_docs)]
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid slot `{0}`, please use either A/a/0 or B/b/1")]
+    InvalidSlot(String),
+    #[error("invalid rootfs status `{0}`, run `orb-slot-ctrl status list` for a full list of available rootfs status")]
+    InvalidRootFsStatus(String),
+    #[error("failed opening efivars: {0}")]
+    EfiVarDb(#[from] EfiVarDbErr),
+    #[error("{0}; please try again as root user")]
+    NotRoot(crate::Error),
+    #[error(transparent)]
+    SlotCtrl(#[from] crate::Error),
+    #[error(transparent)]
+    RollbackLog(#[from] rollback::Error),
+}
+
+impl Error {
+    /// The exit code the tool terminates with on this error.
+    #[must_use]
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::InvalidSlot(_) | Error::InvalidRootFsStatus(_) => EXIT_USAGE,
+            Error::NotRoot(_) => EXIT_PERMISSION_DENIED,
+            Error::SlotCtrl(
+                crate::Error::InvalidEfiVarLen
+                | crate::Error::InvalidSlotData
+                | crate::Error::InvalidRootFsStatusData
+                | crate::Error::ExceedingRetryCount { .. },
+            ) => EXIT_INVALID_EFIVARS,
+            Error::SlotCtrl(crate::Error::RollbackTargetUnbootable(_)) => {
+                EXIT_ROLLBACK_REFUSED
+            }
+            Error::EfiVarDb(_) | Error::SlotCtrl(_) | Error::RollbackLog(_) => {
+                EXIT_FAILURE
+            }
+        }
+    }
 }
 
-fn check_running_as_root(error: crate::Error) {
+/// Classifies an error from writing efivars, which requires root.
+fn write_error(error: crate::Error) -> Error {
     let uid = rustix::process::getuid();
     let euid = rustix::process::geteuid();
-    if !(uid.is_root() && euid.is_root()) {
-        println!("Please try again as root user.");
-        exit(1)
+    if uid.is_root() && euid.is_root() {
+        Error::SlotCtrl(error)
+    } else {
+        Error::NotRoot(error)
+    }
+}
+
+/// The result of a command.
+///
+/// Printed as plain text by default, which matches what the tool always printed, or as a JSON
+/// document with `--json`.
+#[derive(Debug)]
+pub enum Output {
+    /// The command only changed state.
+    Done,
+    Slot(Slot),
+    RootFsStatus(RootFsStatus),
+    RetryCount(u8),
+    MaxRetryCount(u8),
+    RootFsStatusVariants,
+    State(Box<State>),
+    RollbackLog(Vec<rollback::Record>),
+    GitDescribe(&'static str),
+}
+
+impl Output {
+    /// The JSON representation of the output.
+    #[must_use]
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Output::Done => json!({}),
+            Output::Slot(slot) => json!({ "slot": slot }),
+            Output::RootFsStatus(status) => json!({ "rootfs_status": status }),
+            Output::RetryCount(count) => json!({ "retry_count": count }),
+            Output::MaxRetryCount(count) => json!({ "max_retry_count": count }),
+            Output::RootFsStatusVariants => {
+                let variants: Vec<_> = ROOTFS_STATUS_ALIASES
+                    .iter()
+                    .map(|(status, aliases)| {
+                        json!({ "rootfs_status": status, "aliases": aliases })
+                    })
+                    .collect();
+                json!({ "variants": variants })
+            }
+            Output::State(state) => json!(state),
+            Output::RollbackLog(records) => json!({ "rollbacks": records }),
+            Output::GitDescribe(describe) => json!({ "git_describe": describe }),
+        }
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::Done => Ok(()),
+            Output::Slot(slot) => writeln!(f, "{slot}"),
+            Output::RootFsStatus(status) => writeln!(f, "{status:?}"),
+            Output::RetryCount(count) | Output::MaxRetryCount(count) => {
+                writeln!(f, "{count}")
+            }
+            Output::RootFsStatusVariants => {
+                writeln!(f, "Available Rootfs status variants with their aliases):")?;
+                for (status, aliases) in ROOTFS_STATUS_ALIASES {
+                    writeln!(f, "  {status:?} ({})", aliases.join(", "))?;
+                }
+                Ok(())
+            }
+            Output::State(state) => {
+                writeln!(f, "current slot: {}", state.current_slot)?;
+                writeln!(f, "next slot: {}", state.next_slot)?;
+                writeln!(f, "inactive slot: {}", state.inactive_slot)?;
+                for (slot, slot_state) in
+                    [(Slot::A, &state.slot_a), (Slot::B, &state.slot_b)]
+                {
+                    writeln!(
+                        f,
+                        "slot {slot}: rootfs status {:?}, retry count {}",
+                        slot_state.rootfs_status, slot_state.retry_count
+                    )?;
+                }
+                writeln!(f, "max retry count: {}", state.max_retry_count)?;
+                for (name, bytes) in &state.efi_vars {
+                    match bytes {
+                        Some(bytes) => {
+                            let hex: Vec<_> =
+                                bytes.iter().map(|b| format!("{b:02x}")).collect();
+                            writeln!(f, "{name}: {}", hex.join(" "))?;
+                        }
+                        None => writeln!(f, "{name}: <missing>")?,
+                    }
+                }
+                Ok(())
+            }
+            Output::RollbackLog(records) => {
+                for record in records {
+                    writeln!(
+                        f,
+                        "{}\t{} -> {}\t{}",
+                        record.timestamp, record.from, record.to, record.reason
+                    )?;
+                }
+                Ok(())
+            }
+            Output::GitDescribe(describe) => writeln!(f, "{describe}"),
+        }
     }
-    panic!("{}", error)
 }
 
-pub fn run(orb_slot_ctrl: &OrbSlotCtrl, cli: Cli) -> eyre::Result<()> {
-    match cli.subcmd {
-        Commands::GetSlot => {
-            println!("{}", orb_slot_ctrl.get_current_slot()?);
+/// Runs `cli` on the efivars of the running system, prints its output or error and returns the
+/// exit code to terminate with.
+#[must_use]
+pub fn main(cli: &Cli) -> ExitCode {
+    let result = EfiVarDb::from_rootfs("/")
+        .and_then(|db| OrbSlotCtrl::new(&db))
+        .map_err(Error::from)
+        .and_then(|orb_slot_ctrl| run(&orb_slot_ctrl, cli));
+    match result {
+        Ok(output) if cli.json => {
+            println!("{}", output.to_json());
+            ExitCode::SUCCESS
         }
-        Commands::GetNextSlot => {
-            println!("{}", orb_slot_ctrl.get_next_boot_slot()?);
+        Ok(output) => {
+            print!("{output}");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            let code = error.exit_code();
+            if cli.json {
+                println!("{}", error_json(code, &error.to_string()));
+            } else {
+                eprintln!("Error: {error}");
+            }
+            ExitCode::from(code)
         }
+    }
+}
+
+/// The JSON document printed with `--json` if parsing the command line failed.
+#[must_use]
+pub fn usage_error_json(error: &clap::Error) -> serde_json::Value {
+    let rendered = error.render().to_string();
+    let message = rendered.lines().next().unwrap_or_default();
+    error_json(
+        EXIT_USAGE,
+        message.strip_prefix("error: ").unwrap_or(message),
+    )
+}
+
+fn error_json(code: u8, message: &str) -> serde_json::Value {
+    json!({ "error": { "code": code, "message": message } })
+}
+
+fn parse_slot(slot: &str) -> Result<Slot, Error> {
+    match slot.to_lowercase().as_str() {
+        // Slot A alias.
+        "a" | "0" => Ok(Slot::A),
+        // Slot B alias.
+        "b" | "1" => Ok(Slot::B),
+        _ => Err(Error::InvalidSlot(slot.to_string())),
+    }
+}
+
+fn parse_rootfs_status(status: &str) -> Result<RootFsStatus, Error> {
+    let alias = status.to_lowercase();
+    ROOTFS_STATUS_ALIASES
+        .iter()
+        .find(|(_, aliases)| aliases.contains(&alias.as_str()))
+        .map(|(status, _)| *status)
+        .ok_or_else(|| Error::InvalidRootFsStatus(status.to_string()))
+}
+
+/// Runs the command of `cli`.
+pub fn run(orb_slot_ctrl: &OrbSlotCtrl, cli: &Cli) -> Result<Output, Error> {
+    let output = match &cli.subcmd {
+        Commands::GetSlot => Output::Slot(orb_slot_ctrl.get_current_slot()?),
+        Commands::GetNextSlot => Output::Slot(orb_slot_ctrl.get_next_boot_slot()?),
         Commands::SetNextSlot { slot } => {
-            let slot = match slot.to_lowercase().as_str() {
-                // Slot A alias.
-                "a" | "0" => crate::Slot::A,
-                // Slot B alias.
-                "b" | "1" => crate::Slot::B,
-                _ => {
-                    println!(
-                        "Invalid slot provided, please use either A/a/0 or B/b/1."
-                    );
-                    exit(1)
-                }
-            };
-            if let Err(e) = orb_slot_ctrl.set_next_boot_slot(slot) {
-                check_running_as_root(e);
-            };
+            orb_slot_ctrl
+                .set_next_boot_slot(parse_slot(slot)?)
+                .map_err(write_error)?;
+            Output::Done
         }
         Commands::Status { inactive, subcmd } => {
+            let slot = if *inactive {
+                orb_slot_ctrl.get_inactive_slot()?
+            } else {
+                orb_slot_ctrl.get_current_slot()?
+            };
             match subcmd {
                 StatusCommands::GetRootfsStatus => {
-                    if inactive {
-                        println!(
-                            "{:?}",
-                            orb_slot_ctrl.get_rootfs_status(
-                                orb_slot_ctrl.get_inactive_slot()?
-                            )?
-                        );
-                    } else {
-                        println!("{:?}", orb_slot_ctrl.get_current_rootfs_status()?);
-                    }
+                    Output::RootFsStatus(orb_slot_ctrl.get_rootfs_status(slot)?)
                 }
                 StatusCommands::SetRootfsStatus { status } => {
-                    let status = match status.to_lowercase().as_str() {
-                        // Status Normal alias.
-                        "normal" | "0" => crate::RootFsStatus::Normal,
-                        // Status UpdateInProcess alias.
-                        "updateinprocess" | "updinprocess" | "1" => {
-                            crate::RootFsStatus::UpdateInProcess
-                        }
-                        // Status UpdateDone alias.
-                        "updatedone" | "upddone" | "2" => {
-                            crate::RootFsStatus::UpdateDone
-                        }
-                        // Status Unbootable alias.
-                        "unbootable" | "3" => crate::RootFsStatus::Unbootable,
-                        _ => {
-                            println!("Invalid status provided. For a full list of available rootfs status run:");
-                            println!("slot-ctrl status --list");
-                            exit(1)
-                        }
-                    };
-                    if inactive {
-                        if let Err(e) = orb_slot_ctrl.set_rootfs_status(
-                            status,
-                            orb_slot_ctrl.get_inactive_slot()?,
-                        ) {
-                            check_running_as_root(e);
-                        }
-                    } else if let Err(e) =
-                        orb_slot_ctrl.set_current_rootfs_status(status)
-                    {
-                        check_running_as_root(e);
-                    }
+                    orb_slot_ctrl
+                        .set_rootfs_status(parse_rootfs_status(status)?, slot)
+                        .map_err(write_error)?;
+                    Output::Done
                 }
                 StatusCommands::GetRetryCounter => {
-                    if inactive {
-                        println!(
-                            "{}",
-                            orb_slot_ctrl
-                                .get_retry_count(orb_slot_ctrl.get_inactive_slot()?)?
-                        );
-                    } else {
-                        println!("{}", orb_slot_ctrl.get_current_retry_count()?);
-                    }
+                    Output::RetryCount(orb_slot_ctrl.get_retry_count(slot)?)
                 }
                 StatusCommands::GetMaxRetryCounter => {
-                    println!("{}", orb_slot_ctrl.get_max_retry_count()?);
+                    Output::MaxRetryCount(orb_slot_ctrl.get_max_retry_count()?)
                 }
                 StatusCommands::ResetRetryCounter => {
-                    if inactive {
-                        if let Err(e) = orb_slot_ctrl.reset_retry_count_to_max(
-                            orb_slot_ctrl.get_inactive_slot()?,
-                        ) {
-                            check_running_as_root(e);
-                        }
-                    } else if let Err(e) =
-                        orb_slot_ctrl.reset_current_retry_count_to_max()
-                    {
-                        check_running_as_root(e);
-                    }
+                    orb_slot_ctrl
+                        .reset_retry_count_to_max(slot)
+                        .map_err(write_error)?;
+                    Output::Done
                 }
-                StatusCommands::ListStatusVariants => {
-                    println!("Available Rootfs status variants with their aliases):");
-                    println!("  Normal (normal, 0)");
-                    println!("  UpdateInProcess (updateinprocess, updinprocess, 1)");
-                    println!("  UpdateDone (updatedone, upddone, 2)");
-                    println!("  Unbootable (unbootable, 3)");
+                StatusCommands::ListStatusVariants => Output::RootFsStatusVariants,
+                StatusCommands::GetState => {
+                    Output::State(Box::new(orb_slot_ctrl.get_state()?))
                 }
             }
         }
-        Commands::GitDescribe => {
-            println!("{}", BUILD_INFO.git.describe);
-        }
-    }
-
-    Ok(())
+        Commands::Rollback { reason, log } => match orb_slot_ctrl.rollback(reason, log)
+        {
+            Ok(slot) => Output::Slot(slot),
+            Err(
+                e @ (crate::Error::RollbackTargetUnbootable(_)
+                | crate::Error::RecordRollback(_)),
+            ) => return Err(e.into()),
+            Err(e) => return Err(write_error(e)),
+        },
+        Commands::RollbackLog { log } => Output::RollbackLog(rollback::read(log)?),
+        Commands::GitDescribe => Output::GitDescribe(BUILD_INFO.git.describe),
+    };
+    Ok(output)
 }
@@ -0,0 +1,161 @@
+//! Persistent record of rollbacks from one slot to the other.
+//!
+//! Every rollback is appended as a single line to the rollback log, which lives on the persistent
+//! partition so that it survives switching slots. Lines consist of tab separated fields:
+//!
+//! ```text
+//! <seconds since unix epoch>\t<slot rolled back from>\t<slot rolled back to>\t<reason>
+//! ```
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::Slot;
+
+/// The default location of the rollback log.
+pub const DEFAULT_LOG_PATH: &str = "/usr/persistent/rollback.log";
+
+/// A single rollback recorded in the log.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Record {
+    /// Seconds since the unix epoch at which the rollback happened.
+    pub timestamp: u64,
+    /// The slot that was marked unbootable.
+    pub from: Slot,
+    /// The slot that was set for the next boot.
+    pub to: Slot,
+    /// Why the rollback happened.
+    pub reason: String,
+}
+
+impl Record {
+    /// Creates a record of a rollback from `from` to `to` that happens now.
+    #[must_use]
+    pub fn now(from: Slot, to: Slot, reason: &str) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Self {
+            timestamp,
+            from,
+            to,
+            reason: reason.to_string(),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        // Keep every record on one line and the reason in the last field.
+        let reason: String = self
+            .reason
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        format!(
+            "{}\t{}\t{}\t{}\n",
+            self.timestamp, self.from, self.to, reason
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(4, '\t');
+        let timestamp = fields.next()?.parse().ok()?;
+        let from = parse_slot(fields.next()?)?;
+        let to = parse_slot(fields.next()?)?;
+        let reason = fields.next()?.to_string();
+        Some(Self {
+            timestamp,
+            from,
+            to,
+            reason,
+        })
+    }
+}
+
+fn parse_slot(s: &str) -> Option<Slot> {
+    match s {
+        "a" => Some(Slot::A),
+        "b" => Some(Slot::B),
+        _ => None,
+    }
+}
+
+/// Errors from reading or writing the rollback log.
+#[allow(missing_docs)]
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("failed appending to rollback log {path}: {source}")]
+    Append { path: PathBuf, source: io::Error },
+    #[error("failed reading rollback log {path}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+}
+
+/// Appends `record` to the log at `path`, creating the log if needed.
+pub fn append(path: &Path, record: &Record) -> Result<(), Error> {
+    let append_err = |source| Error::Append {
+        path: path.to_path_buf(),
+        source,
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(append_err)?;
+    }
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(append_err)?;
+    log.write_all(record.to_line().as_bytes())
+        .and_then(|()| log.sync_all())
+        .map_err(append_err)
+}
+
+/// Reads all records from the log at `path`, oldest first.
+///
+/// A missing log means that no rollback happened yet. Malformed lines are skipped.
+pub fn read(path: &Path) -> Result<Vec<Record>, Error> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents.lines().filter_map(Record::from_line).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(source) => Err(Error::Read {
+            path: path.to_path_buf(),
+            source,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{append, read, Record};
+    use crate::Slot;
+
+    #[test]
+    fn records_roundtrip_through_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("persistent/rollback.log");
+        assert_eq!(read(&path).unwrap(), []);
+
+        let first = Record {
+            timestamp: 1,
+            from: Slot::A,
+            to: Slot::B,
+            reason: "health check `mcu version` failed:\n\tversion mismatch".into(),
+        };
+        let second = Record::now(Slot::B, Slot::A, "manual");
+        append(&path, &first).unwrap();
+        append(&path, &second).unwrap();
+
+        let records = read(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].reason,
+            "health check `mcu version` failed:  version mismatch"
+        );
+        assert_eq!((records[0].from, records[0].to), (Slot::A, Slot::B));
+        assert_eq!(records[1], second);
+    }
+}
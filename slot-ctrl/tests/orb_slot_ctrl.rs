@@ -1,3 +1,5 @@
+use clap::Parser as _;
+use orb_slot_ctrl::program::{self, Cli};
 use orb_slot_ctrl::test_utils::Fixture;
 use orb_slot_ctrl::{RootFsStatus, Slot};
 
@@ -75,3 +77,118 @@ fn it_gets_and_resets_current_retry_count_to_max_on_specific_slot() {
     let count = fx.slot_ctrl.get_retry_count(Slot::B).unwrap();
     assert_eq!(count, 5);
 }
+
+#[test]
+fn it_rolls_back_to_the_inactive_slot() {
+    let fx = Fixture::new(Slot::A, 5);
+    let log_dir = tempfile::tempdir().unwrap();
+    let log = log_dir.path().join("rollback.log");
+
+    let next = fx.slot_ctrl.rollback("health check failed", &log).unwrap();
+    assert_eq!(next, Slot::B);
+    assert_eq!(fx.slot_ctrl.get_next_boot_slot().unwrap(), Slot::B);
+    assert_eq!(
+        fx.slot_ctrl.get_rootfs_status(Slot::A).unwrap(),
+        RootFsStatus::Unbootable
+    );
+    assert_eq!(fx.slot_ctrl.get_retry_count(Slot::B).unwrap(), 5);
+
+    let records = orb_slot_ctrl::rollback::read(&log).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!((records[0].from, records[0].to), (Slot::A, Slot::B));
+    assert_eq!(records[0].reason, "health check failed");
+}
+
+#[test]
+fn it_refuses_to_roll_back_to_an_unbootable_slot() {
+    let fx = Fixture::new(Slot::A, 5);
+    let log_dir = tempfile::tempdir().unwrap();
+    let log = log_dir.path().join("rollback.log");
+    fx.slot_ctrl
+        .set_rootfs_status(RootFsStatus::Unbootable, Slot::B)
+        .unwrap();
+
+    assert!(matches!(
+        fx.slot_ctrl.rollback("health check failed", &log),
+        Err(orb_slot_ctrl::Error::RollbackTargetUnbootable(Slot::B))
+    ));
+    assert_eq!(fx.slot_ctrl.get_next_boot_slot().unwrap(), Slot::A);
+    assert_eq!(
+        fx.slot_ctrl.get_rootfs_status(Slot::A).unwrap(),
+        RootFsStatus::Normal
+    );
+    assert!(orb_slot_ctrl::rollback::read(&log).unwrap().is_empty());
+}
+
+#[test]
+fn it_gets_the_complete_state() {
+    let fx = Fixture::new(Slot::B, 5);
+    fx.slot_ctrl.reset_retry_count_to_max(Slot::B).unwrap();
+    fx.slot_ctrl
+        .set_rootfs_status(RootFsStatus::UpdateDone, Slot::A)
+        .unwrap();
+
+    let state = fx.slot_ctrl.get_state().unwrap();
+    assert_eq!(
+        (state.current_slot, state.next_slot, state.inactive_slot),
+        (Slot::B, Slot::B, Slot::A)
+    );
+    assert_eq!(state.slot_a.rootfs_status, RootFsStatus::UpdateDone);
+    assert_eq!(state.slot_b.rootfs_status, RootFsStatus::Normal);
+    assert_eq!((state.slot_a.retry_count, state.slot_b.retry_count), (0, 5));
+    assert_eq!(state.max_retry_count, 5);
+    assert_eq!(state.efi_vars.len(), 7);
+    assert_eq!(
+        state.efi_vars["RootfsStatusSlotA-781e084c-a330-417c-b678-38e696380cb9"],
+        Some(vec![0x07, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00])
+    );
+}
+
+#[test]
+fn cli_prints_the_state_as_json() {
+    let fx = Fixture::new(Slot::A, 5);
+    let cli =
+        Cli::try_parse_from(["orb-slot-ctrl", "status", "all", "--json"]).unwrap();
+
+    let json = program::run(&fx.slot_ctrl, &cli).unwrap().to_json();
+    assert_eq!(json["current_slot"], "a");
+    assert_eq!(json["inactive_slot"], "b");
+    assert_eq!(json["slot_b"]["rootfs_status"], "Normal");
+    assert_eq!(json["max_retry_count"], 5);
+    assert_eq!(
+        json["efi_vars"]["BootChainFwCurrent-781e084c-a330-417c-b678-38e696380cb9"],
+        serde_json::json!([0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])
+    );
+}
+
+#[test]
+fn cli_errors_have_exit_codes() {
+    let fx = Fixture::new(Slot::A, 5);
+    let run = |args: &[&str]| {
+        let cli = Cli::try_parse_from(["orb-slot-ctrl"].iter().chain(args)).unwrap();
+        program::run(&fx.slot_ctrl, &cli).unwrap_err().exit_code()
+    };
+
+    assert_eq!(run(&["set", "c"]), program::EXIT_USAGE);
+    assert_eq!(run(&["status", "set", "broken"]), program::EXIT_USAGE);
+
+    fx.slot_ctrl
+        .set_rootfs_status(RootFsStatus::Unbootable, Slot::B)
+        .unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+    let log = log_dir.path().join("rollback.log");
+    assert_eq!(
+        run(&["rollback", "test", "--log", log.to_str().unwrap()]),
+        program::EXIT_ROLLBACK_REFUSED
+    );
+}
+
+#[test]
+fn cli_usage_errors_are_printed_as_json() {
+    let error = Cli::try_parse_from(["orb-slot-ctrl", "--json", "status", "sett"])
+        .err()
+        .unwrap();
+    let json = program::usage_error_json(&error);
+    assert_eq!(json["error"]["code"], program::EXIT_USAGE);
+    assert_eq!(json["error"]["message"], "unrecognized subcommand 'sett'");
+}
@@ -1,3 +1,7 @@
+pub mod metrics;
+#[cfg(feature = "otlp")]
+mod otlp;
+
 use std::io::IsTerminal as _;
 
 use tracing::level_filters::LevelFilter;
@@ -5,10 +9,41 @@ use tracing_subscriber::{
     layer::SubscriberExt as _, util::SubscriberInitExt as _, EnvFilter,
 };
 
+#[cfg(feature = "otlp")]
+pub use otlp::DEFAULT_OTLP_ENDPOINT;
+
 #[derive(Debug)]
 pub struct TelemetryConfig {
     syslog_identifier: Option<String>,
     global_filter: EnvFilter,
+    #[cfg(feature = "otlp")]
+    otlp_traces_endpoint: Option<String>,
+    #[cfg(feature = "otlp")]
+    otlp_metrics_endpoint: Option<String>,
+}
+
+/// Error returned by [`TelemetryConfig::try_init`].
+#[derive(Debug, thiserror::Error)]
+pub enum InitError {
+    #[error(transparent)]
+    Subscriber(#[from] tracing_subscriber::util::TryInitError),
+    #[cfg(feature = "otlp")]
+    #[error("failed to build the OTLP trace exporter")]
+    OtlpTraces(#[source] opentelemetry::trace::TraceError),
+    #[cfg(feature = "otlp")]
+    #[error("failed to build the OTLP metrics exporter")]
+    OtlpMetrics(#[source] opentelemetry_sdk::metrics::MetricError),
+}
+
+/// Returned by [`TelemetryConfig::init`], flushes telemetry that wasn't exported yet.
+///
+/// Dropping it does nothing, exporting continues until the program exits.
+#[derive(Debug, Default)]
+pub struct TelemetryShutdownHandler {
+    #[cfg(feature = "otlp")]
+    tracer_provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+    #[cfg(feature = "otlp")]
+    meter_provider: Option<opentelemetry_sdk::metrics::SdkMeterProvider>,
 }
 
 impl TelemetryConfig {
@@ -22,6 +57,10 @@ impl TelemetryConfig {
             global_filter: EnvFilter::builder()
                 .with_default_directive(LevelFilter::INFO.into())
                 .from_env_lossy(),
+            #[cfg(feature = "otlp")]
+            otlp_traces_endpoint: None,
+            #[cfg(feature = "otlp")]
+            otlp_metrics_endpoint: None,
         }
     }
 
@@ -46,7 +85,55 @@ impl TelemetryConfig {
         }
     }
 
-    pub fn try_init(self) -> Result<(), tracing_subscriber::util::TryInitError> {
+    /// Exports spans over OTLP/gRPC to the collector at `endpoint`, usually
+    /// [`DEFAULT_OTLP_ENDPOINT`]. Spans are still subject to the global filter.
+    ///
+    /// The telemetry must then be initialized within a tokio runtime.
+    #[cfg(feature = "otlp")]
+    #[must_use]
+    pub fn with_otlp_traces(self, endpoint: &str) -> Self {
+        Self {
+            otlp_traces_endpoint: Some(endpoint.to_owned()),
+            ..self
+        }
+    }
+
+    /// Exports the [`metrics`] over OTLP/gRPC to the collector at `endpoint`, usually
+    /// [`DEFAULT_OTLP_ENDPOINT`].
+    ///
+    /// The telemetry must then be initialized within a tokio runtime.
+    #[cfg(feature = "otlp")]
+    #[must_use]
+    pub fn with_otlp_metrics(self, endpoint: &str) -> Self {
+        Self {
+            otlp_metrics_endpoint: Some(endpoint.to_owned()),
+            ..self
+        }
+    }
+
+    pub fn try_init(self) -> Result<TelemetryShutdownHandler, InitError> {
+        #[cfg(feature = "otlp")]
+        let (tracer_provider, meter_provider) = {
+            let resource = otlp::resource(self.syslog_identifier.as_deref());
+            let tracer_provider = self
+                .otlp_traces_endpoint
+                .as_deref()
+                .map(|endpoint| otlp::tracer_provider(endpoint, resource.clone()))
+                .transpose()
+                .map_err(InitError::OtlpTraces)?;
+            let meter_provider = self
+                .otlp_metrics_endpoint
+                .as_deref()
+                .map(|endpoint| otlp::meter_provider(endpoint, resource))
+                .transpose()
+                .map_err(InitError::OtlpMetrics)?;
+            (tracer_provider, meter_provider)
+        };
+        #[cfg(not(feature = "otlp"))]
+        let otlp_layer: Option<tracing_subscriber::layer::Identity> = None;
+        #[cfg(feature = "otlp")]
+        let otlp_layer = tracer_provider.as_ref().map(otlp::layer);
+
         let registry = tracing_subscriber::registry();
         // The type is only there to get it to compile.
         let tokio_console_layer: Option<tracing_subscriber::layer::Identity> = None;
@@ -76,8 +163,20 @@ impl TelemetryConfig {
             .with(tokio_console_layer)
             .with(stderr_layer)
             .with(journald_layer)
+            .with(otlp_layer)
             .with(self.global_filter)
-            .try_init()
+            .try_init()?;
+
+        #[cfg(feature = "otlp")]
+        if let Some(meter_provider) = &meter_provider {
+            opentelemetry::global::set_meter_provider(meter_provider.clone());
+        }
+        Ok(TelemetryShutdownHandler {
+            #[cfg(feature = "otlp")]
+            tracer_provider,
+            #[cfg(feature = "otlp")]
+            meter_provider,
+        })
     }
 
     /// Initializes the telemetry config. Call this only once, at the beginning of the
@@ -85,7 +184,26 @@ impl TelemetryConfig {
     ///
     /// Calling this more than once or when another tracing subscriber is registered
     /// will cause a panic.
-    pub fn init(self) {
+    pub fn init(self) -> TelemetryShutdownHandler {
         self.try_init().expect("failed to initialize orb-telemetry")
     }
 }
+
+impl TelemetryShutdownHandler {
+    /// Exports all pending telemetry and stops exporting. Call this right before the
+    /// program exits.
+    ///
+    /// This blocks until the export finished, so call it from a blocking context in
+    /// async code, e.g. in `tokio::task::spawn_blocking`.
+    pub fn flush(self) {
+        #[cfg(feature = "otlp")]
+        {
+            if let Some(Err(err)) = self.tracer_provider.map(|p| p.shutdown()) {
+                tracing::warn!("failed to flush OTLP traces: {err}");
+            }
+            if let Some(Err(err)) = self.meter_provider.map(|p| p.shutdown()) {
+                tracing::warn!("failed to flush OTLP metrics: {err}");
+            }
+        }
+    }
+}
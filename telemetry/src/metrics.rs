@@ -0,0 +1,180 @@
+//! A small metrics facade shared by all orb daemons.
+//!
+//! Instruments record into the global OpenTelemetry meter provider, which exports them
+//! over OTLP if [`TelemetryConfig::with_otlp_metrics`](crate::TelemetryConfig) was
+//! enabled, and discards them otherwise.
+//!
+//! Create instruments only after the telemetry was initialized, instruments created
+//! before are not exported. Creating them lazily, e.g. in a
+//! [`LazyLock`](std::sync::LazyLock), takes care of that:
+//!
+//! ```
+//! use std::{sync::LazyLock, time::Duration};
+//!
+//! use orb_telemetry::metrics::{Counter, Histogram, KeyValue};
+//!
+//! static TOKEN_REFRESHES: LazyLock<Counter> = LazyLock::new(|| {
+//!     Counter::new("orb.attest.token_refreshes", "Number of token refreshes")
+//! });
+//! static UPDATE_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+//!     Histogram::new("orb.update.duration", "Duration of an update", "s")
+//! });
+//!
+//! TOKEN_REFRESHES.add(1, &[KeyValue::new("result", "ok")]);
+//! UPDATE_DURATION.record_duration(Duration::from_secs(95), &[]);
+//! ```
+//!
+//! Names follow the OpenTelemetry conventions: dot-separated and prefixed with `orb.`
+//! and the daemon's name. Units are [UCUM](https://ucum.org) codes, e.g. `s` or `By`.
+
+use std::time::Duration;
+
+use opentelemetry::{global, metrics::Meter};
+
+pub use opentelemetry::KeyValue;
+
+/// Name of the meter of all orb instruments.
+const METER_NAME: &str = "orb";
+
+fn meter() -> Meter {
+    global::meter(METER_NAME)
+}
+
+/// A monotonically increasing value, e.g. the number of token refreshes.
+#[derive(Clone, Debug)]
+pub struct Counter(opentelemetry::metrics::Counter<u64>);
+
+impl Counter {
+    #[must_use]
+    pub fn new(name: &'static str, description: &'static str) -> Self {
+        Self(
+            meter()
+                .u64_counter(name)
+                .with_description(description)
+                .build(),
+        )
+    }
+
+    /// Adds `value` to the counter.
+    pub fn add(&self, value: u64, attributes: &[KeyValue]) {
+        self.0.add(value, attributes);
+    }
+}
+
+/// A value that goes up and down, e.g. the temperature of a sensor.
+#[derive(Clone, Debug)]
+pub struct Gauge(opentelemetry::metrics::Gauge<f64>);
+
+impl Gauge {
+    #[must_use]
+    pub fn new(
+        name: &'static str,
+        description: &'static str,
+        unit: &'static str,
+    ) -> Self {
+        Self(
+            meter()
+                .f64_gauge(name)
+                .with_description(description)
+                .with_unit(unit)
+                .build(),
+        )
+    }
+
+    /// Sets the gauge to `value`.
+    pub fn record(&self, value: f64, attributes: &[KeyValue]) {
+        self.0.record(value, attributes);
+    }
+}
+
+/// A distribution of values, e.g. the latency of MCU acks.
+#[derive(Clone, Debug)]
+pub struct Histogram(opentelemetry::metrics::Histogram<f64>);
+
+impl Histogram {
+    #[must_use]
+    pub fn new(
+        name: &'static str,
+        description: &'static str,
+        unit: &'static str,
+    ) -> Self {
+        Self(
+            meter()
+                .f64_histogram(name)
+                .with_description(description)
+                .with_unit(unit)
+                .build(),
+        )
+    }
+
+    /// Records `value`.
+    pub fn record(&self, value: f64, attributes: &[KeyValue]) {
+        self.0.record(value, attributes);
+    }
+
+    /// Records `duration` in seconds, for histograms with the unit `s`.
+    pub fn record_duration(&self, duration: Duration, attributes: &[KeyValue]) {
+        self.record(duration.as_secs_f64(), attributes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use opentelemetry::global;
+    use opentelemetry_sdk::{
+        metrics::{
+            data::{Gauge as GaugeData, Histogram as HistogramData, Sum},
+            PeriodicReader, SdkMeterProvider,
+        },
+        runtime,
+        testing::metrics::InMemoryMetricExporter,
+    };
+
+    use super::{Counter, Gauge, Histogram, KeyValue};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn instruments_record_into_global_provider() {
+        let exporter = InMemoryMetricExporter::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(
+                PeriodicReader::builder(exporter.clone(), runtime::Tokio).build(),
+            )
+            .build();
+        global::set_meter_provider(provider.clone());
+
+        let counter = Counter::new("orb.test.counter", "A counter");
+        counter.add(1, &[KeyValue::new("result", "ok")]);
+        counter.add(2, &[KeyValue::new("result", "ok")]);
+        Gauge::new("orb.test.gauge", "A gauge", "Cel").record(42.5, &[]);
+        Histogram::new("orb.test.histogram", "A histogram", "s")
+            .record_duration(Duration::from_millis(1500), &[]);
+        provider.force_flush().unwrap();
+
+        let metrics = exporter.get_finished_metrics().unwrap();
+        let scope = &metrics.last().unwrap().scope_metrics[0];
+        assert_eq!(scope.scope.name(), "orb");
+        let metric = |name| {
+            scope
+                .metrics
+                .iter()
+                .find(|metric| metric.name == name)
+                .unwrap()
+                .data
+                .as_any()
+        };
+        let sum = metric("orb.test.counter")
+            .downcast_ref::<Sum<u64>>()
+            .unwrap();
+        assert_eq!(sum.data_points[0].value, 3);
+        let gauge = metric("orb.test.gauge")
+            .downcast_ref::<GaugeData<f64>>()
+            .unwrap();
+        assert_eq!(gauge.data_points[0].value, 42.5);
+        let histogram = metric("orb.test.histogram")
+            .downcast_ref::<HistogramData<f64>>()
+            .unwrap();
+        assert_eq!(histogram.data_points[0].sum, 1.5);
+    }
+}
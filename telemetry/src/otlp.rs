@@ -0,0 +1,87 @@
+//! Export of traces and metrics over OTLP, to a collector on the orb.
+
+use opentelemetry::{trace::TraceError, trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig as _};
+use opentelemetry_sdk::{
+    metrics::{MetricError, PeriodicReader, SdkMeterProvider},
+    runtime,
+    trace::{Tracer, TracerProvider},
+    Resource,
+};
+use tracing::{level_filters::LevelFilter, Subscriber};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::{filter::Targets, registry::LookupSpan, Layer as _};
+
+/// The default endpoint of the OTLP/gRPC collector.
+pub const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4317";
+
+/// Crates used by the exporter itself. Exporting their spans would produce more spans
+/// on every export.
+const EXPORTER_TARGETS: &[&str] = &[
+    "h2",
+    "hyper",
+    "hyper_util",
+    "opentelemetry",
+    "tonic",
+    "tower",
+];
+
+/// Describes the exporting daemon. Its `service.name` is the syslog identifier, or the
+/// name of the executable.
+pub fn resource(syslog_identifier: Option<&str>) -> Resource {
+    let service_name = syslog_identifier.map(str::to_owned).or_else(|| {
+        let exe = std::env::current_exe().ok()?;
+        Some(exe.file_name()?.to_string_lossy().into_owned())
+    });
+    let Some(service_name) = service_name else {
+        return Resource::default();
+    };
+    Resource::default().merge(&Resource::new([KeyValue::new(
+        "service.name",
+        service_name,
+    )]))
+}
+
+pub fn tracer_provider(
+    endpoint: &str,
+    resource: Resource,
+) -> Result<TracerProvider, TraceError> {
+    let exporter = SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(resource)
+        .build())
+}
+
+pub fn meter_provider(
+    endpoint: &str,
+    resource: Resource,
+) -> Result<SdkMeterProvider, MetricError> {
+    let exporter = MetricExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    Ok(SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(exporter, runtime::Tokio).build())
+        .with_resource(resource)
+        .build())
+}
+
+/// Returns a layer that exports spans to `tracer_provider`.
+pub fn layer<S>(tracer_provider: &TracerProvider) -> impl tracing_subscriber::Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter_filter = Targets::new()
+        .with_default(LevelFilter::TRACE)
+        .with_targets(
+            EXPORTER_TARGETS
+                .iter()
+                .map(|target| (*target, LevelFilter::OFF)),
+        );
+    let tracer: Tracer = tracer_provider.tracer("orb-telemetry");
+    OpenTelemetryLayer::new(tracer).with_filter(exporter_filter)
+}
@@ -5,6 +5,7 @@ mod fake_progress;
 pub mod idle;
 pub mod milky_way;
 pub mod progress;
+pub mod scripted;
 mod segmented;
 pub mod simple_spinner;
 pub mod slider;
@@ -17,6 +18,7 @@ pub use self::idle::Idle;
 pub use self::milky_way::MilkyWay;
 pub use self::progress::Progress;
 pub use self::r#static::Static;
+pub use self::scripted::Scripted;
 pub use self::simple_spinner::SimpleSpinner;
 pub use self::slider::Slider;
 pub use self::spinner::Spinner;
@@ -0,0 +1,636 @@
+//! Animations defined in data rather than in code.
+//!
+//! A [`Definition`] is loaded from a TOML or JSON file, or received within an
+//! [`Event::PlayAnimation`](crate::engine::Event::PlayAnimation), so that colors and
+//! timings can be tweaked without rebuilding the daemon:
+//!
+//! ```toml
+//! leds = "ring"
+//! level = "foreground"
+//! duration = 2.0
+//! rotation = 0.25
+//! fade_in = 0.5
+//! background = [0, 0, 0]
+//!
+//! [[segments]]
+//! start = 0.0
+//! end = 0.25
+//! keyframes = [
+//!     { time = 0.0, color = [0, 0, 0] },
+//!     { time = 0.5, color = [0, 60, 90], easing = "ease_in_out" },
+//!     { time = 1.0, color = [0, 0, 0], easing = "ease_in_out" },
+//! ]
+//! ```
+//!
+//! Positions on the LEDs are fractions of the whole strip, in the order of the LEDs,
+//! and times within an iteration are fractions of `duration`. Colors are either
+//! `[red, green, blue]`, for Pearl, or `[dimming, red, green, blue]`, for Diamond.
+
+use crate::engine::animations::{SimpleSpinner, Static, Wave};
+use crate::engine::{
+    Animation, AnimationState, Transition, TransitionStatus, LEVEL_BACKGROUND,
+    LEVEL_FOREGROUND, LEVEL_NOTICE,
+};
+use eyre::{bail, ensure, eyre, Result, WrapErr};
+use orb_rgb::Argb;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+
+/// LEDs an animation is played on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    #[default]
+    Ring,
+    Center,
+}
+
+/// Layer of the animations stack an animation is played on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Background,
+    #[default]
+    Foreground,
+    Notice,
+}
+
+impl Level {
+    /// Returns the level in the animations stack.
+    #[must_use]
+    pub fn value(self) -> u8 {
+        match self {
+            Level::Background => LEVEL_BACKGROUND,
+            Level::Foreground => LEVEL_FOREGROUND,
+            Level::Notice => LEVEL_NOTICE,
+        }
+    }
+}
+
+/// Curve of the interpolation towards a keyframe.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    /// Holds the previous color until the keyframe.
+    Step,
+}
+
+impl Easing {
+    /// Maps the linear progress `t`, between 0 and 1, onto the curve.
+    #[must_use]
+    pub fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => 1.0 - (t * PI / 2.0).cos(),
+            Easing::EaseOut => (t * PI / 2.0).sin(),
+            Easing::EaseInOut => (1.0 - (t * PI).cos()) / 2.0,
+            Easing::Step => {
+                if t < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+        }
+    }
+}
+
+/// LED color, written `[red, green, blue]` or `[dimming, red, green, blue]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "Vec<u8>", into = "Vec<u8>")]
+pub struct Color(pub Argb);
+
+impl TryFrom<Vec<u8>> for Color {
+    type Error = String;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        match *value.as_slice() {
+            [r, g, b] => Ok(Color(Argb(None, r, g, b))),
+            [a, r, g, b] => Ok(Color(Argb(Some(a), r, g, b))),
+            _ => Err(format!(
+                "expected [red, green, blue] or [dimming, red, green, blue], got {} \
+                 values",
+                value.len()
+            )),
+        }
+    }
+}
+
+impl From<Color> for Vec<u8> {
+    fn from(Color(Argb(dimming, r, g, b)): Color) -> Self {
+        match dimming {
+            Some(a) => vec![a, r, g, b],
+            None => vec![r, g, b],
+        }
+    }
+}
+
+/// Color of a segment at a point in time.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Keyframe {
+    /// Fraction of the iteration, between 0 and 1.
+    pub time: f64,
+    pub color: Color,
+    /// Curve of the interpolation from the previous keyframe.
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+/// Contiguous range of LEDs sharing the same keyframes.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Segment {
+    /// Position of the first LED, between 0 and 1.
+    #[serde(default)]
+    pub start: f64,
+    /// Position after the last LED, between 0 and 1. The segment wraps around the
+    /// strip if it is lower than `start`.
+    #[serde(default = "default_segment_end")]
+    pub end: f64,
+    /// Keyframes in ascending order of time. The color is held before the first
+    /// one and after the last one.
+    pub keyframes: Vec<Keyframe>,
+}
+
+fn default_segment_end() -> f64 {
+    1.0
+}
+
+fn default_background() -> Color {
+    Color(Argb::OFF)
+}
+
+/// Definition of a [`Scripted`] animation.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Definition {
+    #[serde(default)]
+    pub leds: Target,
+    #[serde(default)]
+    pub level: Level,
+    /// Duration of an iteration, in seconds.
+    pub duration: f64,
+    /// Number of iterations, forever if not set.
+    #[serde(default)]
+    pub repeat: Option<u32>,
+    /// Rotation of the segments, in turns per second.
+    #[serde(default)]
+    pub rotation: f64,
+    /// Duration of the crossfade from the superseded animation, or of the fade in,
+    /// in seconds.
+    #[serde(default)]
+    pub fade_in: f64,
+    /// Color of the LEDs outside of all segments.
+    #[serde(default = "default_background")]
+    pub background: Color,
+    /// Segments, drawn in order on top of each other.
+    #[serde(default)]
+    pub segments: Vec<Segment>,
+}
+
+impl Definition {
+    /// Loads a definition from a `.toml` or `.json` file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        let definition: Self = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents)?,
+            Some("json") => serde_json::from_str(&contents)?,
+            _ => bail!("{}: expected a .toml or .json file", path.display()),
+        };
+        definition
+            .validate()
+            .wrap_err_with(|| format!("invalid animation {}", path.display()))?;
+        Ok(definition)
+    }
+
+    /// Checks that the definition can be played.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.duration.is_finite() && self.duration > 0.0,
+            "duration must be positive"
+        );
+        ensure!(self.repeat != Some(0), "repeat must be positive");
+        ensure!(self.rotation.is_finite(), "rotation must be finite");
+        ensure!(
+            self.fade_in.is_finite() && self.fade_in >= 0.0,
+            "fade_in must not be negative"
+        );
+        for (i, segment) in self.segments.iter().enumerate() {
+            ensure!(
+                (0.0..=1.0).contains(&segment.start)
+                    && (0.0..=1.0).contains(&segment.end)
+                    && segment.start != segment.end,
+                "segment {i}: start and end must be distinct and between 0 and 1"
+            );
+            ensure!(
+                !segment.keyframes.is_empty(),
+                "segment {i}: at least one keyframe is needed"
+            );
+            let mut previous = None;
+            for keyframe in &segment.keyframes {
+                ensure!(
+                    (0.0..=1.0).contains(&keyframe.time)
+                        && previous.map_or(true, |previous| keyframe.time > previous),
+                    "segment {i}: keyframe times must be ascending and between 0 and 1"
+                );
+                previous = Some(keyframe.time);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Segment {
+    /// Whether the LED at `position` belongs to the segment.
+    fn contains(&self, position: f64) -> bool {
+        if self.start < self.end {
+            (self.start..self.end).contains(&position)
+        } else {
+            position >= self.start || position < self.end
+        }
+    }
+
+    /// Returns the color at `time`, a fraction of the iteration.
+    fn color(&self, time: f64) -> Argb {
+        let next = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time);
+        match (next.checked_sub(1), self.keyframes.get(next)) {
+            (Some(i), Some(to)) => {
+                let from = &self.keyframes[i];
+                let t = (time - from.time) / (to.time - from.time);
+                mix(from.color.0, to.color.0, to.easing.apply(t))
+            }
+            (Some(i), None) => self.keyframes[i].color.0,
+            (None, _) => self.keyframes[0].color.0,
+        }
+    }
+}
+
+/// Interpolates all the components of two colors, including the dimming.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn mix(from: Argb, to: Argb, t: f64) -> Argb {
+    let t = t.clamp(0.0, 1.0);
+    let mix = |from: u8, to: u8| {
+        (f64::from(from) + (f64::from(to) - f64::from(from)) * t).round() as u8
+    };
+    let dimming = match (from.0, to.0) {
+        (Some(from), Some(to)) => Some(mix(from, to)),
+        (_, dimming) => dimming,
+    };
+    Argb(
+        dimming,
+        mix(from.1, to.1),
+        mix(from.2, to.2),
+        mix(from.3, to.3),
+    )
+}
+
+/// Animation played from a [`Definition`].
+pub struct Scripted<const N: usize> {
+    definition: Definition,
+    /// Time since the start, in seconds.
+    time: f64,
+    /// Time at which the animation finishes, if any.
+    end: Option<f64>,
+    /// Last rendered frame, used for the transition to the next animation.
+    frame: [Argb; N],
+    transition: Option<Transition>,
+    transition_time: f64,
+    transition_frame: Option<[Argb; N]>,
+}
+
+impl<const N: usize> Scripted<N> {
+    /// Creates a new [`Scripted`] animation, after validating the `definition`.
+    pub fn new(definition: Definition) -> Result<Self> {
+        definition.validate()?;
+        Ok(Self {
+            time: 0.0,
+            end: definition
+                .repeat
+                .map(|repeat| f64::from(repeat) * definition.duration),
+            frame: [Argb::OFF; N],
+            transition: (definition.fade_in > 0.0)
+                .then_some(Transition::FadeIn(definition.fade_in)),
+            transition_time: 0.0,
+            transition_frame: None,
+            definition,
+        })
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn render(&self) -> [Argb; N] {
+        let Definition {
+            duration,
+            rotation,
+            background,
+            ref segments,
+            ..
+        } = self.definition;
+        let time = self.time.rem_euclid(duration) / duration;
+        let offset = rotation * self.time;
+        let mut frame = [background.0; N];
+        for (i, led) in frame.iter_mut().enumerate() {
+            let position = (i as f64 / N as f64 - offset).rem_euclid(1.0);
+            if let Some(segment) = segments
+                .iter()
+                .rev()
+                .find(|segment| segment.contains(position))
+            {
+                *led = segment.color(time);
+            }
+        }
+        frame
+    }
+}
+
+impl<const N: usize> Animation for Scripted<N> {
+    type Frame = [Argb; N];
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn animate(
+        &mut self,
+        frame: &mut [Argb; N],
+        dt: f64,
+        idle: bool,
+    ) -> AnimationState {
+        if self.end.is_some_and(|end| self.time >= end) {
+            return AnimationState::Finished;
+        }
+        let mut scaling_factor = 1.0;
+        let mut crossfade = None;
+        match self.transition {
+            Some(Transition::ForceStop) => return AnimationState::Finished,
+            Some(Transition::FadeOut(duration)) => {
+                self.transition_time += dt;
+                if self.transition_time >= duration {
+                    return AnimationState::Finished;
+                }
+                scaling_factor = (self.transition_time * PI / 2.0 / duration).cos();
+            }
+            Some(Transition::FadeIn(duration)) => {
+                self.transition_time += dt;
+                let progress = (self.transition_time / duration).min(1.0);
+                if let Some(transition_frame) = self.transition_frame {
+                    crossfade = Some((transition_frame, progress));
+                } else {
+                    scaling_factor = (progress * PI / 2.0).sin();
+                }
+                if self.transition_time >= duration {
+                    self.transition = None;
+                    self.transition_frame = None;
+                }
+            }
+            _ => {}
+        }
+
+        self.frame = self.render();
+        if let Some((transition_frame, progress)) = crossfade {
+            for (led, from) in self.frame.iter_mut().zip(transition_frame) {
+                *led = mix(from, *led, progress);
+            }
+        } else if scaling_factor < 1.0 {
+            for led in &mut self.frame {
+                *led *= scaling_factor;
+            }
+        }
+        if !idle {
+            *frame = self.frame;
+        }
+
+        self.time += dt;
+        AnimationState::Running
+    }
+
+    fn transition_from(&mut self, superseded: &dyn Any) -> TransitionStatus {
+        if self.definition.fade_in <= 0.0 {
+            return TransitionStatus::Sharp;
+        }
+        let transition_frame =
+            if let Some(scripted) = superseded.downcast_ref::<Scripted<N>>() {
+                scripted.frame
+            } else if let Some(r#static) = superseded.downcast_ref::<Static<N>>() {
+                [r#static.color(); N]
+            } else if let Some(wave) = superseded.downcast_ref::<Wave<N>>() {
+                [wave.color(); N]
+            } else if let Some(simple_spinner) =
+                superseded.downcast_ref::<SimpleSpinner<N>>()
+            {
+                [simple_spinner.background(); N]
+            } else {
+                return TransitionStatus::Sharp;
+            };
+        self.transition = Some(Transition::FadeIn(self.definition.fade_in));
+        self.transition_time = 0.0;
+        self.transition_frame = Some(transition_frame);
+        TransitionStatus::Smooth
+    }
+
+    fn stop(&mut self, transition: Transition) -> Result<()> {
+        match transition {
+            Transition::PlayOnce => {
+                let duration = self.definition.duration;
+                let iteration_end = ((self.time / duration).floor() + 1.0) * duration;
+                self.end =
+                    Some(self.end.map_or(iteration_end, |end| end.min(iteration_end)));
+            }
+            Transition::ForceStop | Transition::FadeOut(_) => {
+                self.transition = Some(transition);
+                self.transition_time = 0.0;
+            }
+            Transition::FadeIn(_) | Transition::StartDelay(_) | Transition::Shrink => {
+                return Err(eyre!(
+                    "Transition {:?} not supported for scripted animation",
+                    transition
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+        leds = "center"
+        level = "notice"
+        duration = 2.0
+        repeat = 3
+
+        [[segments]]
+        start = 0.75
+        end = 0.25
+        keyframes = [
+            { time = 0.0, color = [0, 0, 0] },
+            { time = 0.5, color = [31, 100, 0, 200], easing = "ease_in_out" },
+        ]
+    "#;
+
+    fn definition(keyframes: Vec<Keyframe>) -> Definition {
+        Definition {
+            leds: Target::Ring,
+            level: Level::Foreground,
+            duration: 1.0,
+            repeat: None,
+            rotation: 0.0,
+            fade_in: 0.0,
+            background: Color(Argb(None, 1, 1, 1)),
+            segments: vec![Segment {
+                start: 0.0,
+                end: 0.5,
+                keyframes,
+            }],
+        }
+    }
+
+    fn keyframe(time: f64, color: Argb, easing: Easing) -> Keyframe {
+        Keyframe {
+            time,
+            color: Color(color),
+            easing,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let definition: Definition = toml::from_str(TOML).unwrap();
+        definition.validate().unwrap();
+        assert_eq!(definition.leds, Target::Center);
+        assert_eq!(definition.level.value(), LEVEL_NOTICE);
+        assert_eq!(definition.repeat, Some(3));
+        assert_eq!(definition.background, Color(Argb::OFF));
+        let keyframes = &definition.segments[0].keyframes;
+        assert_eq!(keyframes[0].color, Color(Argb(None, 0, 0, 0)));
+        assert_eq!(keyframes[0].easing, Easing::Linear);
+        assert_eq!(keyframes[1].color, Color(Argb(Some(31), 100, 0, 200)));
+
+        let json = serde_json::to_string(&definition).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Definition>(&json).unwrap(),
+            definition
+        );
+        assert!(
+            toml::from_str::<Definition>("duration = 1.0\nbackground = [1, 2]")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = definition(vec![keyframe(0.0, Argb::OFF, Easing::Linear)]);
+        valid.validate().unwrap();
+        assert!(Definition {
+            duration: 0.0,
+            ..valid.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(Definition {
+            repeat: Some(0),
+            ..valid.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(definition(vec![]).validate().is_err());
+        assert!(definition(vec![
+            keyframe(0.5, Argb::OFF, Easing::Linear),
+            keyframe(0.5, Argb::OFF, Easing::Linear),
+        ])
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_keyframes() {
+        let segment = Segment {
+            start: 0.0,
+            end: 1.0,
+            keyframes: vec![
+                keyframe(0.2, Argb(None, 0, 0, 0), Easing::Linear),
+                keyframe(0.6, Argb(None, 200, 100, 0), Easing::Linear),
+                keyframe(0.8, Argb(None, 0, 0, 0), Easing::Step),
+            ],
+        };
+        // the color is held before the first and after the last keyframe
+        assert_eq!(segment.color(0.0), Argb(None, 0, 0, 0));
+        assert_eq!(segment.color(0.4), Argb(None, 100, 50, 0));
+        assert_eq!(segment.color(0.7), Argb(None, 200, 100, 0));
+        assert_eq!(segment.color(0.9), Argb(None, 0, 0, 0));
+
+        assert!((Easing::EaseInOut.apply(0.5) - 0.5).abs() < 1e-9);
+        assert!(Easing::EaseIn.apply(0.5) < 0.5);
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+        assert_eq!(
+            mix(Argb(Some(0), 0, 0, 0), Argb(Some(30), 10, 0, 0), 0.5),
+            Argb(Some(15), 5, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_animate() {
+        let mut animation = Scripted::<4>::new(Definition {
+            repeat: Some(1),
+            rotation: 0.25,
+            ..definition(vec![keyframe(0.0, Argb(None, 9, 9, 9), Easing::Linear)])
+        })
+        .unwrap();
+        let mut frame = [Argb::OFF; 4];
+        assert!(animation.animate(&mut frame, 0.5, false).is_running());
+        // the segment covers the first half of the LEDs
+        assert_eq!(frame[..2], [Argb(None, 9, 9, 9); 2]);
+        assert_eq!(frame[2..], [Argb(None, 1, 1, 1); 2]);
+        // rotated by half a LED after half a second, then by one LED
+        assert!(animation.animate(&mut frame, 0.5, false).is_running());
+        assert_eq!(frame[1..3], [Argb(None, 9, 9, 9); 2]);
+        assert!(!animation.animate(&mut frame, 0.5, false).is_running());
+    }
+
+    #[test]
+    fn test_transition() {
+        let mut previous = Scripted::<2>::new(definition(vec![keyframe(
+            0.0,
+            Argb(None, 100, 0, 0),
+            Easing::Linear,
+        )]))
+        .unwrap();
+        let mut frame = [Argb::OFF; 2];
+        previous.animate(&mut frame, 0.1, false);
+
+        let mut animation = Scripted::<2>::new(Definition {
+            fade_in: 1.0,
+            ..definition(vec![keyframe(0.0, Argb(None, 0, 0, 100), Easing::Linear)])
+        })
+        .unwrap();
+        assert_eq!(
+            animation.transition_from(previous.as_any()),
+            TransitionStatus::Smooth
+        );
+        animation.animate(&mut frame, 0.5, false);
+        assert_eq!(frame[0], Argb(None, 50, 0, 50));
+        animation.animate(&mut frame, 0.5, false);
+        assert_eq!(frame[0], Argb(None, 0, 0, 100));
+
+        // the second iteration is played to the end
+        animation.stop(Transition::PlayOnce).unwrap();
+        assert!(animation.stop(Transition::Shrink).is_err());
+        assert!(animation.animate(&mut frame, 0.5, false).is_running());
+        assert!(animation.animate(&mut frame, 0.5, false).is_running());
+        assert!(!animation.animate(&mut frame, 0.5, false).is_running());
+    }
+}
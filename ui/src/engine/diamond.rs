@@ -14,6 +14,7 @@ use tokio::time;
 use tokio_stream::wrappers::{IntervalStream, UnboundedReceiverStream};
 
 use crate::engine::animations::alert::BlinkDurations;
+use crate::engine::animations::scripted;
 use crate::engine::{
     animations, operator, Animation, AnimationsStack, CenterFrame, ConeFrame, Event,
     EventHandler, OperatingMode, OperatorFrame, OrbType, QrScanSchema,
@@ -23,7 +24,6 @@ use crate::engine::{
     LEVEL_NOTICE,
 };
 use crate::sound;
-use crate::sound::Player;
 
 struct WrappedCenterMessage(Message);
 
@@ -130,10 +130,11 @@ pub async fn event_loop(
     interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
     let mut interval = IntervalStream::new(interval);
     let mut rx = UnboundedReceiverStream::new(rx);
+    let mut timer = InstantTimer::default();
     let mut runner = match sound::Jetson::spawn().await {
-        Ok(sound) => {
-            Runner::<DIAMOND_RING_LED_COUNT, DIAMOND_CENTER_LED_COUNT>::new(sound)
-        }
+        Ok(sound) => Runner::<DIAMOND_RING_LED_COUNT, DIAMOND_CENTER_LED_COUNT>::new(
+            Box::new(sound),
+        ),
         Err(e) => {
             return Err(eyre::eyre!("Failed to initialize sound: {:?}", e));
         }
@@ -149,7 +150,8 @@ pub async fn event_loop(
                 }
             }
             Either::Right(_) => {
-                if let Err(e) = runner.run(&mut mcu_tx.clone()).await {
+                let dt = timer.get_dt().unwrap_or(0.0);
+                if let Err(e) = runner.run(&mut mcu_tx.clone(), dt).await {
                     tracing::error!("Error running UI: {:?}", e);
                 }
             }
@@ -159,9 +161,8 @@ pub async fn event_loop(
 }
 
 impl Runner<DIAMOND_RING_LED_COUNT, DIAMOND_CENTER_LED_COUNT> {
-    pub(crate) fn new(sound: sound::Jetson) -> Self {
+    pub(crate) fn new(sound: Box<dyn sound::Player>) -> Self {
         Self {
-            timer: InstantTimer::default(),
             ring_animations_stack: AnimationsStack::new(),
             center_animations_stack: AnimationsStack::new(),
             cone_animations_stack: Some(AnimationsStack::new()),
@@ -983,6 +984,23 @@ impl EventHandler for Runner<DIAMOND_RING_LED_COUNT, DIAMOND_CENTER_LED_COUNT> {
             Event::Gimbal { x, y } => {
                 self.gimbal = Some((*x, *y));
             }
+            Event::PlayAnimation { definition } => {
+                let level = definition.level.value();
+                match definition.leds {
+                    scripted::Target::Ring => self.set_ring(
+                        level,
+                        animations::Scripted::<DIAMOND_RING_LED_COUNT>::new(
+                            definition.clone(),
+                        )?,
+                    ),
+                    scripted::Target::Center => self.set_center(
+                        level,
+                        animations::Scripted::<DIAMOND_CENTER_LED_COUNT>::new(
+                            definition.clone(),
+                        )?,
+                    ),
+                }
+            }
             Event::Flow { mode } => {
                 self.operating_mode = *mode;
             }
@@ -990,8 +1008,7 @@ impl EventHandler for Runner<DIAMOND_RING_LED_COUNT, DIAMOND_CENTER_LED_COUNT> {
         Ok(())
     }
 
-    async fn run(&mut self, interface_tx: &mut Sender<Message>) -> Result<()> {
-        let dt = self.timer.get_dt().unwrap_or(0.0);
+    async fn run(&mut self, interface_tx: &mut Sender<Message>, dt: f64) -> Result<()> {
         self.center_animations_stack.run(&mut self.center_frame, dt);
         if !self.paused {
             interface_tx.try_send(WrappedCenterMessage::from(self.center_frame).0)?;
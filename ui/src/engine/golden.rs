@@ -0,0 +1,333 @@
+//! Golden-image tests of the LED engine.
+//!
+//! Each test feeds a scripted sequence of events to a [`Runner`], renders the frames
+//! at [`LED_ENGINE_FPS`] with a fixed time step, and compares them against a
+//! recording committed in `tests/golden/`.
+//!
+//! After an intended change of an animation, regenerate the recordings with
+//! `UPDATE_GOLDEN=1 cargo test -p orb-ui golden` and review the diff.
+
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::time::Duration;
+use std::{env, fs};
+
+use futures::channel::mpsc;
+use futures::{FutureExt, StreamExt};
+use orb_sound::SoundBuilder;
+
+use crate::engine::{
+    Event, EventHandler, OperatingMode, QrScanSchema, Runner, SignupFailReason,
+    DIAMOND_CENTER_LED_COUNT, DIAMOND_RING_LED_COUNT, LED_ENGINE_FPS,
+    PEARL_CENTER_LED_COUNT, PEARL_RING_LED_COUNT,
+};
+use crate::recorder::{DataFormat, Frame};
+use crate::sound::{self, Player, SoundConfig, SoundError};
+use crate::INPUT_CAPACITY;
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+
+/// Only every n-th rendered frame is compared, to keep the recordings small.
+const SAMPLE_INTERVAL: u64 = 10;
+
+/// Events to feed to the engine, with the time since the start in milliseconds.
+type Script = Vec<(u64, Event)>;
+
+/// Sound player which doesn't play anything.
+#[derive(Debug)]
+struct Silent;
+
+impl Player for Silent {
+    fn load_sound_files(
+        &self,
+        _config: SoundConfig,
+    ) -> Pin<Box<dyn Future<Output = Result<(), SoundError>> + Send + '_>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn build(&mut self, _sound_type: sound::Type) -> eyre::Result<SoundBuilder<'_>> {
+        eyre::bail!("no sound device")
+    }
+
+    fn clone(&self) -> Box<dyn Player> {
+        Box::new(Silent)
+    }
+
+    fn volume(&self) -> u64 {
+        0
+    }
+
+    fn set_master_volume(&mut self, _level: u64) {}
+
+    fn queue(
+        &mut self,
+        _sound_type: sound::Type,
+        _delay: Duration,
+    ) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    fn try_queue(&mut self, _sound_type: sound::Type) -> eyre::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Runs `script` for `duration_ms` and returns the recorded frames.
+async fn record(
+    runner: &mut impl EventHandler,
+    script: Script,
+    duration_ms: u64,
+) -> Vec<Frame> {
+    let interval = Duration::from_millis(1000 / LED_ENGINE_FPS);
+    let (mut interface_tx, mut interface_rx) = mpsc::channel(INPUT_CAPACITY);
+    let mut script = script.into_iter().peekable();
+    let mut frames = Vec::new();
+    for i in 0..duration_ms / interval.as_millis() as u64 {
+        let timestamp = interval * i as u32;
+        while let Some((_, event)) =
+            script.next_if(|&(at, _)| Duration::from_millis(at) <= timestamp)
+        {
+            runner.event(&event).unwrap();
+        }
+        runner
+            .run(&mut interface_tx, interval.as_secs_f64())
+            .await
+            .unwrap();
+        while let Some(Some(message)) = interface_rx.next().now_or_never() {
+            if i % SAMPLE_INTERVAL == 0 {
+                frames.extend(Frame::from_message(timestamp, &message));
+            }
+        }
+    }
+    assert!(
+        script.next().is_none(),
+        "script is longer than the recording"
+    );
+    frames
+}
+
+/// Compares `frames` against the golden recording `name`, or overwrites it if
+/// `UPDATE_GOLDEN` is set.
+fn assert_golden(name: &str, frames: &[Frame]) {
+    let path = Path::new(GOLDEN_DIR).join(format!("{name}.jsonl"));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        let mut recording = Vec::new();
+        for frame in frames {
+            frame.write(&mut recording).unwrap();
+        }
+        fs::create_dir_all(GOLDEN_DIR).unwrap();
+        fs::write(&path, recording).unwrap();
+        return;
+    }
+    let golden = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "failed to read {}: {e}, run with UPDATE_GOLDEN=1 to create it",
+            path.display()
+        )
+    });
+    let golden = golden
+        .lines()
+        .map(|line| serde_json::from_str::<Frame>(line).unwrap())
+        .collect::<Vec<_>>();
+    for (frame, expected) in frames.iter().zip(&golden) {
+        assert_eq!(
+            (frame.timestamp_ms, frame.leds),
+            (expected.timestamp_ms, expected.leds),
+            "{name}: frames are rendered in a different order"
+        );
+        if let Some(i) = frame
+            .data
+            .bytes()
+            .zip(expected.data.bytes())
+            .position(|(a, b)| a != b)
+        {
+            let led_size = match frame.format {
+                DataFormat::Rgb => 3,
+                DataFormat::Argb32 => 4,
+            };
+            panic!(
+                "{name}: {:?} frame at {} ms differs from the golden recording, \
+                 starting at LED {}",
+                frame.leds,
+                frame.timestamp_ms,
+                i / (2 * led_size)
+            );
+        }
+        assert_eq!(frame, expected, "{name}: frame differs in size or format");
+    }
+    assert_eq!(
+        frames.len(),
+        golden.len(),
+        "{name}: number of frames differs from the golden recording"
+    );
+}
+
+/// Operator-based signup, with operator and user QR codes, up to a successful
+/// signup.
+fn operator_signup() -> Script {
+    vec![
+        (0, Event::BatteryCapacity { percentage: 100 }),
+        (0, Event::GoodInternet),
+        (0, Event::GoodWlan),
+        (0, Event::Idle),
+        (500, Event::SignupStartOperator),
+        (
+            1000,
+            Event::QrScanStart {
+                schema: QrScanSchema::Operator,
+            },
+        ),
+        (2000, Event::QrScanCapture),
+        (
+            2500,
+            Event::QrScanCompleted {
+                schema: QrScanSchema::Operator,
+            },
+        ),
+        (
+            2500,
+            Event::QrScanSuccess {
+                schema: QrScanSchema::Operator,
+            },
+        ),
+        (
+            3000,
+            Event::QrScanStart {
+                schema: QrScanSchema::User,
+            },
+        ),
+        (4000, Event::QrScanCapture),
+        (
+            4500,
+            Event::QrScanCompleted {
+                schema: QrScanSchema::User,
+            },
+        ),
+        (
+            4500,
+            Event::QrScanSuccess {
+                schema: QrScanSchema::User,
+            },
+        ),
+        (5000, Event::SignupStart),
+        (5000, Event::BiometricCaptureDistance { in_range: true }),
+        (5500, Event::BiometricCaptureProgress { progress: 0.3 }),
+        (6000, Event::BiometricCaptureProgress { progress: 0.7 }),
+        (6500, Event::BiometricCaptureProgress { progress: 1.0 }),
+        (7000, Event::BiometricCaptureSuccess),
+        (7500, Event::BiometricPipelineProgress { progress: 0.5 }),
+        (8000, Event::BiometricPipelineSuccess),
+        (8500, Event::SignupSuccess),
+    ]
+}
+
+/// Self-serve signup, with the operator QR code shown to the orb and the signup
+/// started from the app, up to a failed signup.
+fn self_serve_signup() -> Script {
+    vec![
+        (0, Event::BatteryCapacity { percentage: 100 }),
+        (0, Event::GoodInternet),
+        (0, Event::GoodWlan),
+        (0, Event::Idle),
+        (
+            500,
+            Event::QrScanStart {
+                schema: QrScanSchema::OperatorSelfServe,
+            },
+        ),
+        (1500, Event::QrScanCapture),
+        (
+            2000,
+            Event::QrScanCompleted {
+                schema: QrScanSchema::OperatorSelfServe,
+            },
+        ),
+        (
+            2000,
+            Event::QrScanSuccess {
+                schema: QrScanSchema::OperatorSelfServe,
+            },
+        ),
+        (2500, Event::SignupStart),
+        (2500, Event::BiometricCaptureDistance { in_range: false }),
+        (3500, Event::BiometricCaptureDistance { in_range: true }),
+        (3500, Event::BiometricCaptureProgress { progress: 0.4 }),
+        (
+            4000,
+            Event::BiometricCaptureOcclusion {
+                occlusion_detected: true,
+            },
+        ),
+        (
+            4500,
+            Event::BiometricCaptureOcclusion {
+                occlusion_detected: false,
+            },
+        ),
+        (4500, Event::BiometricCaptureProgress { progress: 1.0 }),
+        (5000, Event::BiometricCaptureSuccess),
+        (5500, Event::BiometricPipelineProgress { progress: 0.4 }),
+        (6000, Event::StartingEnrollment),
+        (6500, Event::BiometricPipelineSuccess),
+        (
+            7000,
+            Event::SignupFail {
+                reason: SignupFailReason::Duplicate,
+            },
+        ),
+    ]
+}
+
+fn with_flow(mode: OperatingMode, mut script: Script) -> Script {
+    script.insert(0, (0, Event::Flow { mode }));
+    script
+}
+
+/// Diamond boot sequence, prepended to the Diamond signups.
+fn diamond_boot(script: Script) -> Script {
+    let mut boot = vec![
+        (0, Event::Bootup),
+        (1000, Event::BootComplete { api_mode: false }),
+    ];
+    boot.extend(script.into_iter().map(|(at, event)| (at + 1500, event)));
+    boot
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_pearl_operator_signup() {
+    let mut runner =
+        Runner::<PEARL_RING_LED_COUNT, PEARL_CENTER_LED_COUNT>::new(Box::new(Silent));
+    let script = with_flow(OperatingMode::Operator, operator_signup());
+    let frames = record(&mut runner, script, 10_000).await;
+    assert_golden("pearl_operator_signup", &frames);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_pearl_self_serve_signup() {
+    let mut runner =
+        Runner::<PEARL_RING_LED_COUNT, PEARL_CENTER_LED_COUNT>::new(Box::new(Silent));
+    let script = with_flow(OperatingMode::SelfServe, self_serve_signup());
+    let frames = record(&mut runner, script, 9_000).await;
+    assert_golden("pearl_self_serve_signup", &frames);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_diamond_operator_signup() {
+    let mut runner = Runner::<DIAMOND_RING_LED_COUNT, DIAMOND_CENTER_LED_COUNT>::new(
+        Box::new(Silent),
+    );
+    let script = with_flow(OperatingMode::Operator, diamond_boot(operator_signup()));
+    let frames = record(&mut runner, script, 11_500).await;
+    assert_golden("diamond_operator_signup", &frames);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_diamond_self_serve_signup() {
+    let mut runner = Runner::<DIAMOND_RING_LED_COUNT, DIAMOND_CENTER_LED_COUNT>::new(
+        Box::new(Silent),
+    );
+    let script = with_flow(OperatingMode::SelfServe, diamond_boot(self_serve_signup()));
+    let frames = record(&mut runner, script, 10_500).await;
+    assert_golden("diamond_self_serve_signup", &frames);
+}
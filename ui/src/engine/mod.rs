@@ -7,13 +7,14 @@ use eyre::Result;
 use futures::channel::mpsc::Sender;
 use orb_messages::mcu_main::mcu_message::Message;
 use orb_rgb::Argb;
-use pid::InstantTimer;
 use serde::{Deserialize, Serialize};
 use std::{any::Any, collections::BTreeMap};
 use tokio::sync::mpsc;
 
 pub mod animations;
 mod diamond;
+#[cfg(test)]
+mod golden;
 pub mod operator;
 mod pearl;
 
@@ -382,6 +383,12 @@ event_enum! {
         Gimbal {
             x: u32, y: u32
         },
+
+        /// Play an animation defined in data, see [`animations::scripted`].
+        #[event_enum(method = play_animation)]
+        PlayAnimation {
+            definition: animations::scripted::Definition,
+        },
     }
 }
 
@@ -501,7 +508,6 @@ pub type OperatorFrame = [Argb; 5];
 type DynamicAnimation<Frame> = Box<dyn Animation<Frame = Frame>>;
 
 struct Runner<const RING_LED_COUNT: usize, const CENTER_LED_COUNT: usize> {
-    timer: InstantTimer,
     ring_animations_stack: AnimationsStack<RingFrame<RING_LED_COUNT>>,
     center_animations_stack: AnimationsStack<CenterFrame<CENTER_LED_COUNT>>,
     cone_animations_stack: Option<AnimationsStack<RingFrame<DIAMOND_CONE_LED_COUNT>>>,
@@ -514,7 +520,7 @@ struct Runner<const RING_LED_COUNT: usize, const CENTER_LED_COUNT: usize> {
     operator_pulse: operator::Pulse,
     operator_action: operator::Bar,
     operator_signup_phase: operator::SignupPhase,
-    sound: sound::Jetson,
+    sound: Box<dyn sound::Player>,
     capture_sound: sound::capture::CaptureLoopSound,
     /// When set, update the UI one last time and then pause the engine, see `paused` below.
     is_api_mode: bool,
@@ -528,7 +534,9 @@ struct Runner<const RING_LED_COUNT: usize, const CENTER_LED_COUNT: usize> {
 trait EventHandler {
     fn event(&mut self, event: &Event) -> Result<()>;
 
-    async fn run(&mut self, interface_tx: &mut Sender<Message>) -> Result<()>;
+    /// Renders the frames `dt` seconds after the previous ones and sends them to
+    /// `interface_tx`.
+    async fn run(&mut self, interface_tx: &mut Sender<Message>, dt: f64) -> Result<()>;
 }
 
 struct AnimationsStack<Frame: 'static> {
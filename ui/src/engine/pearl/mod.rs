@@ -2,6 +2,7 @@ mod operator_based;
 mod self_serve;
 
 use crate::engine::animations::alert::BlinkDurations;
+use crate::engine::animations::scripted;
 use async_trait::async_trait;
 use eyre::Result;
 use futures::channel::mpsc;
@@ -23,7 +24,6 @@ use crate::engine::{
     PEARL_RING_LED_COUNT,
 };
 use crate::sound;
-use crate::sound::Player;
 use orb_rgb::Argb;
 
 struct WrappedMessage(Message);
@@ -109,8 +109,11 @@ pub async fn event_loop(
     interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
     let mut interval = IntervalStream::new(interval);
     let mut rx = UnboundedReceiverStream::new(rx);
+    let mut timer = InstantTimer::default();
     let mut runner = match sound::Jetson::spawn().await {
-        Ok(sound) => Runner::<PEARL_RING_LED_COUNT, PEARL_CENTER_LED_COUNT>::new(sound),
+        Ok(sound) => {
+            Runner::<PEARL_RING_LED_COUNT, PEARL_CENTER_LED_COUNT>::new(Box::new(sound))
+        }
         Err(e) => return Err(eyre::eyre!("Failed to initialize sound: {:?}", e)),
     };
     loop {
@@ -124,7 +127,8 @@ pub async fn event_loop(
                 }
             }
             Either::Right(_) => {
-                if let Err(e) = runner.run(&mut mcu_tx.clone()).await {
+                let dt = timer.get_dt().unwrap_or(0.0);
+                if let Err(e) = runner.run(&mut mcu_tx.clone(), dt).await {
                     tracing::error!("Error running UI: {:?}", e);
                 }
             }
@@ -134,9 +138,8 @@ pub async fn event_loop(
 }
 
 impl Runner<PEARL_RING_LED_COUNT, PEARL_CENTER_LED_COUNT> {
-    pub(crate) fn new(sound: sound::Jetson) -> Self {
+    pub(crate) fn new(sound: Box<dyn sound::Player>) -> Self {
         Self {
-            timer: InstantTimer::default(),
             ring_animations_stack: AnimationsStack::new(),
             center_animations_stack: AnimationsStack::new(),
             cone_animations_stack: None,
@@ -324,6 +327,23 @@ impl EventHandler for Runner<PEARL_RING_LED_COUNT, PEARL_CENTER_LED_COUNT> {
             Event::Gimbal { x, y } => {
                 self.gimbal = Some((*x, *y));
             }
+            Event::PlayAnimation { definition } => {
+                let level = definition.level.value();
+                match definition.leds {
+                    scripted::Target::Ring => self.set_ring(
+                        level,
+                        animations::Scripted::<PEARL_RING_LED_COUNT>::new(
+                            definition.clone(),
+                        )?,
+                    ),
+                    scripted::Target::Center => self.set_center(
+                        level,
+                        animations::Scripted::<PEARL_CENTER_LED_COUNT>::new(
+                            definition.clone(),
+                        )?,
+                    ),
+                }
+            }
 
             /* Events that are handled differently depending on the operating mode */
             event => {
@@ -337,8 +357,11 @@ impl EventHandler for Runner<PEARL_RING_LED_COUNT, PEARL_CENTER_LED_COUNT> {
         Ok(())
     }
 
-    async fn run(&mut self, interface_tx: &mut mpsc::Sender<Message>) -> Result<()> {
-        let dt = self.timer.get_dt().unwrap_or(0.0);
+    async fn run(
+        &mut self,
+        interface_tx: &mut mpsc::Sender<Message>,
+        dt: f64,
+    ) -> Result<()> {
         self.center_animations_stack.run(&mut self.center_frame, dt);
         if !self.paused {
             interface_tx.try_send(WrappedMessage::from(self.center_frame).0)?;
@@ -6,7 +6,6 @@ use crate::engine::{
     PEARL_RING_LED_COUNT,
 };
 use crate::sound;
-use crate::sound::Player;
 use eyre::Result;
 use orb_rgb::Argb;
 use std::f64::consts::PI;
@@ -4,7 +4,6 @@ use crate::engine::{
     LEVEL_NOTICE, PEARL_CENTER_LED_COUNT, PEARL_RING_LED_COUNT,
 };
 use crate::sound;
-use crate::sound::Player;
 use animations::alert::BlinkDurations;
 use eyre::Result;
 use orb_rgb::Argb;
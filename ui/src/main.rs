@@ -2,6 +2,7 @@
 
 use humantime::parse_duration;
 use std::env;
+use std::path::PathBuf;
 use std::sync::OnceLock;
 use std::time::Duration;
 use tokio::fs;
@@ -16,16 +17,20 @@ use tracing::debug;
 use crate::beacon::beacon;
 use crate::engine::{Engine, Event, EventChannel, OperatingMode};
 use crate::observer::listen;
+use crate::recorder::Recorder;
 use crate::serial::Serial;
-use crate::simulation::signup_simulation;
+use crate::simulation::{animation_simulation, signup_simulation};
+use crate::visualizer::Visualizer;
 
 mod beacon;
 mod dbus;
 mod engine;
 mod observer;
+mod recorder;
 mod serial;
 mod simulation;
 pub mod sound;
+mod visualizer;
 
 const INPUT_CAPACITY: usize = 100;
 const BUILD_INFO: BuildInfo = make_build_info!();
@@ -42,6 +47,14 @@ const SYSLOG_IDENTIFIER: &str = "worldcoin-ui";
 struct Args {
     #[clap(subcommand)]
     subcmd: SubCommand,
+
+    /// Record the LED frames to a file instead of sending them to the MCU
+    #[arg(long, global = true)]
+    record: Option<PathBuf>,
+
+    /// Draw the LED frames in the terminal instead of sending them to the MCU
+    #[arg(long, global = true, conflicts_with = "record")]
+    visualize: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -50,7 +63,7 @@ enum SubCommand {
     #[clap(action)]
     Daemon,
 
-    /// Signup simulation
+    /// Signup or animation simulation
     #[clap(subcommand)]
     Simulation(SimulationArgs),
 
@@ -76,6 +89,14 @@ enum SimulationArgs {
     /// show-car, infinite loop of signup
     #[clap(action)]
     ShowCar,
+
+    /// Play an animation from a TOML or JSON definition, reloaded when the file
+    /// changes
+    #[clap(action)]
+    Animation {
+        /// Path to the animation definition
+        path: PathBuf,
+    },
 }
 
 #[derive(Parser, Debug, Eq, PartialEq)]
@@ -132,7 +153,13 @@ async fn main() -> Result<()> {
     let args = Args::parse();
     let hw = get_hw_version().await?;
     let (mut serial_input_tx, serial_input_rx) = mpsc::channel(INPUT_CAPACITY);
-    Serial::spawn(serial_input_rx)?;
+    if let Some(path) = &args.record {
+        Recorder::spawn(serial_input_rx, path)?;
+    } else if args.visualize {
+        Visualizer::spawn(serial_input_rx)?;
+    } else {
+        Serial::spawn(serial_input_rx)?;
+    }
     match args.subcmd {
         SubCommand::Daemon => {
             if hw == Hardware::Diamond {
@@ -181,6 +208,9 @@ async fn main() -> Result<()> {
                 SimulationArgs::ShowCar => {
                     signup_simulation(ui.as_ref(), hw, true, true).await?
                 }
+                SimulationArgs::Animation { path } => {
+                    animation_simulation(ui.as_ref(), &path).await?
+                }
             }
         }
         SubCommand::Beacon(beacon_args) => {
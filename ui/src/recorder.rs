@@ -0,0 +1,211 @@
+//! Frame recorder, a headless replacement for the serial interface.
+//!
+//! Every LED frame sent by the engine is written to a file as a line of JSON, with
+//! the time since the start of the recording:
+//!
+//! ```json
+//! {"timestamp_ms":33,"leds":"center","format":"rgb","data":"ff0000ff0000..."}
+//! ```
+//!
+//! `data` holds the LED values exactly as they would be sent to the MCU, hex
+//! encoded. Messages that are not LED frames, e.g. gimbal angles, are not recorded.
+
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use eyre::{Result, WrapErr};
+use futures::{channel::mpsc, prelude::*};
+use orb_messages::mcu_main::mcu_message::Message;
+use orb_messages::mcu_main::{
+    cone_le_ds_sequence, distributor_le_ds_sequence, jetson_to_mcu,
+    user_center_le_ds_sequence, user_ring_le_ds_sequence, JetsonToMcu,
+};
+use serde::{Deserialize, Serialize};
+use tokio::runtime;
+
+/// LEDs a frame is rendered on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Leds {
+    Center,
+    Ring,
+    Cone,
+    Operator,
+}
+
+/// Encoding of the LED values of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataFormat {
+    /// 3 bytes per LED: red, green, blue.
+    Rgb,
+    /// 4 bytes per LED: dimming, red, green, blue.
+    Argb32,
+}
+
+/// A recorded frame.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Frame {
+    /// Time since the start of the recording, in milliseconds.
+    pub timestamp_ms: u64,
+    pub leds: Leds,
+    pub format: DataFormat,
+    /// Hex encoded LED values, in the order sent to the MCU.
+    pub data: String,
+}
+
+/// Returns the LEDs, the encoding and the LED values of a message to the MCU, if it is
+/// an LED sequence.
+pub fn led_sequence(message: &Message) -> Option<(Leds, DataFormat, &[u8])> {
+    let Message::JMessage(JetsonToMcu {
+        payload: Some(payload),
+        ..
+    }) = message
+    else {
+        return None;
+    };
+    let (leds, format, data) = match payload {
+        jetson_to_mcu::Payload::CenterLedsSequence(sequence) => {
+            match sequence.data_format.as_ref()? {
+                user_center_le_ds_sequence::DataFormat::RgbUncompressed(data) => {
+                    (Leds::Center, DataFormat::Rgb, data)
+                }
+                user_center_le_ds_sequence::DataFormat::Argb32Uncompressed(data) => {
+                    (Leds::Center, DataFormat::Argb32, data)
+                }
+            }
+        }
+        jetson_to_mcu::Payload::RingLedsSequence(sequence) => {
+            match sequence.data_format.as_ref()? {
+                user_ring_le_ds_sequence::DataFormat::RgbUncompressed(data) => {
+                    (Leds::Ring, DataFormat::Rgb, data)
+                }
+                user_ring_le_ds_sequence::DataFormat::Argb32Uncompressed(data) => {
+                    (Leds::Ring, DataFormat::Argb32, data)
+                }
+            }
+        }
+        jetson_to_mcu::Payload::ConeLedsSequence(sequence) => {
+            match sequence.data_format.as_ref()? {
+                cone_le_ds_sequence::DataFormat::RgbUncompressed(data) => {
+                    (Leds::Cone, DataFormat::Rgb, data)
+                }
+                cone_le_ds_sequence::DataFormat::Argb32Uncompressed(data) => {
+                    (Leds::Cone, DataFormat::Argb32, data)
+                }
+            }
+        }
+        jetson_to_mcu::Payload::DistributorLedsSequence(sequence) => {
+            match sequence.data_format.as_ref()? {
+                distributor_le_ds_sequence::DataFormat::RgbUncompressed(data) => {
+                    (Leds::Operator, DataFormat::Rgb, data)
+                }
+                distributor_le_ds_sequence::DataFormat::Argb32Uncompressed(data) => {
+                    (Leds::Operator, DataFormat::Argb32, data)
+                }
+            }
+        }
+        _ => return None,
+    };
+    Some((leds, format, data))
+}
+
+impl Frame {
+    /// Extracts the frame from a message to the MCU, if it is an LED sequence.
+    pub fn from_message(timestamp: Duration, message: &Message) -> Option<Self> {
+        let (leds, format, data) = led_sequence(message)?;
+        Some(Self {
+            timestamp_ms: timestamp.as_millis() as u64,
+            leds,
+            format,
+            data: data.iter().map(|byte| format!("{byte:02x}")).collect(),
+        })
+    }
+
+    /// Writes the frame as a line of JSON.
+    pub fn write(&self, w: &mut impl Write) -> Result<()> {
+        serde_json::to_writer(&mut *w, self)?;
+        writeln!(w)?;
+        Ok(())
+    }
+}
+
+pub struct Recorder {}
+
+/// Frame recorder.
+impl Recorder {
+    /// Spawns a new frame recorder, writing to the file at `path`.
+    pub fn spawn(mut input_rx: mpsc::Receiver<Message>, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .wrap_err_with(|| format!("failed to create {}", path.display()))?;
+        // flush every frame, so that the recording is complete when the daemon
+        // is interrupted
+        let mut file = LineWriter::new(file);
+        let name = "frame-recorder";
+        std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let rt = runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("failed to create a new tokio runtime");
+                let start = Instant::now();
+                while let Some(message) = rt.block_on(input_rx.next()) {
+                    if let Some(frame) = Frame::from_message(start.elapsed(), &message)
+                    {
+                        frame.write(&mut file).expect("failed to record a frame");
+                    }
+                }
+            })
+            .expect("failed to spawn thread");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use orb_messages::mcu_main::{MirrorAngle, UserRingLeDsSequence};
+
+    #[test]
+    fn test_write_frame() {
+        let message = Message::JMessage(JetsonToMcu {
+            ack_number: 0,
+            payload: Some(jetson_to_mcu::Payload::RingLedsSequence(
+                UserRingLeDsSequence {
+                    data_format: Some(
+                        user_ring_le_ds_sequence::DataFormat::Argb32Uncompressed(vec![
+                            0x0a, 0xff, 0x00, 0x80,
+                        ]),
+                    ),
+                },
+            )),
+        });
+        let frame = Frame::from_message(Duration::from_millis(66), &message).unwrap();
+        let mut line = Vec::new();
+        frame.write(&mut line).unwrap();
+        assert_eq!(
+            String::from_utf8(line).unwrap(),
+            "{\"timestamp_ms\":66,\"leds\":\"ring\",\"format\":\"argb32\",\
+             \"data\":\"0aff0080\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_skip_non_led_messages() {
+        let message = Message::JMessage(JetsonToMcu {
+            ack_number: 0,
+            payload: Some(jetson_to_mcu::Payload::MirrorAngle(MirrorAngle {
+                horizontal_angle: 0,
+                vertical_angle: 0,
+                phi_angle_millidegrees: 1,
+                theta_angle_millidegrees: 90000,
+                angle_type: 0,
+            })),
+        });
+        assert_eq!(Frame::from_message(Duration::ZERO, &message), None);
+    }
+}
@@ -1,8 +1,10 @@
+use crate::engine::animations::scripted::Definition;
 use crate::engine::{Engine, QrScanSchema, SignupFailReason};
 use crate::Hardware;
-use eyre::Result;
+use eyre::{Result, WrapErr};
 use rand::distributions::{Distribution, Standard};
 use rand::Rng;
+use std::path::Path;
 use std::time::Duration;
 use tokio::{fs, time};
 use tracing::{error, info};
@@ -261,6 +263,40 @@ pub async fn signup_simulation(
     Ok(())
 }
 
+/// Plays the animation defined in the file at `path`, and plays it again every time
+/// the file is modified.
+pub async fn animation_simulation(ui: &dyn Engine, path: &Path) -> Result<()> {
+    info!("🔹 Playing animation from {}", path.display());
+
+    let mut modified = None;
+    loop {
+        match fs::metadata(path)
+            .await
+            .and_then(|metadata| metadata.modified())
+        {
+            Ok(time) if modified != Some(time) => {
+                modified = Some(time);
+                match Definition::load(path) {
+                    Ok(definition) => {
+                        info!("🔹 Animation loaded");
+                        ui.play_animation(definition);
+                    }
+                    // keep the previous animation running until the file is fixed
+                    Err(e) => error!("Failed to load animation: {:?}", e),
+                }
+            }
+            Ok(_) => {}
+            Err(e) if modified.is_none() => {
+                return Err(e)
+                    .wrap_err_with(|| format!("failed to read {}", path.display()));
+            }
+            // the file may be replaced while being saved, try again later
+            Err(_) => {}
+        }
+        time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
 #[expect(dead_code)]
 pub async fn shutdown_simulation(ui: &dyn Engine) -> Result<()> {
     ui.shutdown(true);
@@ -0,0 +1,224 @@
+//! Terminal visualizer, a replacement for the serial interface on machines without
+//! an MCU.
+//!
+//! Renders the LED frames sent by the engine in a truecolor terminal. The ring and
+//! the center LEDs are drawn as concentric circles, LED 0 at the top and going
+//! clockwise, in the order sent to the MCU. The operator LEDs and, when used, the
+//! cone LEDs are drawn as rows below. The layout works for both Pearl and Diamond, it
+//! is not to scale.
+
+use std::f64::consts::PI;
+use std::fmt::Write as _;
+use std::io::{self, Write as _};
+
+use eyre::Result;
+use futures::{channel::mpsc, prelude::*};
+use orb_messages::mcu_main::mcu_message::Message;
+use orb_rgb::Argb;
+use tokio::{runtime, time};
+
+use crate::engine::LED_ENGINE_FPS;
+use crate::recorder::{led_sequence, DataFormat, Leds};
+
+/// Width and height of the drawing of the ring, in pixels. Every character of the
+/// terminal holds two pixels, one above the other.
+const CANVAS_SIZE: usize = 49;
+const RING_OUTER_RADIUS: f64 = 24.0;
+const RING_INNER_RADIUS: f64 = 21.5;
+const CENTER_RADIUS: f64 = 9.0;
+
+/// Gamma applied to the LED values for display. The LEDs are driven linearly, so low
+/// values look much brighter on the orb than on a screen.
+const DISPLAY_GAMMA: f64 = 2.5;
+
+type Rgb = (u8, u8, u8);
+
+/// The last frames received for all LEDs.
+#[derive(Debug, Default)]
+struct View {
+    ring: Vec<Rgb>,
+    center: Vec<Rgb>,
+    cone: Vec<Rgb>,
+    operator: Vec<Rgb>,
+}
+
+impl View {
+    /// Updates the LEDs of the message, returns `false` if it is not an LED frame.
+    fn update(&mut self, message: &Message) -> bool {
+        let Some((leds, format, data)) = led_sequence(message) else {
+            return false;
+        };
+        let colors = match format {
+            DataFormat::Rgb => data
+                .chunks_exact(3)
+                .map(|rgb| display_color(Argb(None, rgb[0], rgb[1], rgb[2])))
+                .collect(),
+            DataFormat::Argb32 => data
+                .chunks_exact(4)
+                .map(|argb| {
+                    display_color(Argb(Some(argb[0]), argb[1], argb[2], argb[3]))
+                })
+                .collect(),
+        };
+        match leds {
+            Leds::Ring => self.ring = colors,
+            Leds::Center => self.center = colors,
+            Leds::Cone => self.cone = colors,
+            Leds::Operator => self.operator = colors,
+        }
+        true
+    }
+
+    /// Returns the color of the pixel at `x`, `y` of the ring drawing.
+    fn pixel(&self, x: usize, y: usize) -> Rgb {
+        let center = (CANVAS_SIZE / 2) as f64;
+        let dx = x as f64 - center;
+        let dy = y as f64 - center;
+        let radius = dx.hypot(dy);
+        let leds = if (RING_INNER_RADIUS..=RING_OUTER_RADIUS).contains(&radius) {
+            &self.ring
+        } else if radius <= CENTER_RADIUS {
+            &self.center
+        } else {
+            return (0, 0, 0);
+        };
+        if leds.is_empty() {
+            return (0, 0, 0);
+        }
+        // clockwise from the top, the y axis of the terminal points down
+        let angle = dx.atan2(-dy).rem_euclid(2.0 * PI);
+        let i = (angle / (2.0 * PI) * leds.len() as f64) as usize;
+        leds[i.min(leds.len() - 1)]
+    }
+
+    /// Renders the view, to be printed from the top left corner of the terminal.
+    fn render(&self) -> String {
+        let mut out = String::from("\x1b[H");
+        let mut colors = None;
+        for row in 0..CANVAS_SIZE.div_ceil(2) {
+            for x in 0..CANVAS_SIZE {
+                let top = self.pixel(x, 2 * row);
+                let bottom = if 2 * row + 1 < CANVAS_SIZE {
+                    self.pixel(x, 2 * row + 1)
+                } else {
+                    (0, 0, 0)
+                };
+                // only change the colors when needed, to keep the output small
+                if colors != Some((top, bottom)) {
+                    colors = Some((top, bottom));
+                    let _ = write!(
+                        out,
+                        "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                        top.0, top.1, top.2, bottom.0, bottom.1, bottom.2
+                    );
+                }
+                out.push('▀');
+            }
+            colors = None;
+            out.push_str("\x1b[0m\x1b[K\n");
+        }
+        for (name, leds) in [("operator", &self.operator), ("cone", &self.cone)] {
+            if leds.is_empty() {
+                continue;
+            }
+            let _ = write!(out, "{name:<9}");
+            for &(r, g, b) in leds {
+                let _ = write!(out, "\x1b[38;2;{r};{g};{b}m█");
+            }
+            out.push_str("\x1b[0m\x1b[K\n");
+        }
+        out
+    }
+}
+
+/// Converts an LED value to the color to display.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn display_color(Argb(dimming, r, g, b): Argb) -> Rgb {
+    let dimming = dimming.map_or(1.0, |dimming| {
+        f64::from(dimming.min(Argb::DIMMING_MAX_VALUE))
+            / f64::from(Argb::DIMMING_MAX_VALUE)
+    });
+    let display = |value: u8| {
+        (255.0 * (f64::from(value) / 255.0 * dimming).powf(1.0 / DISPLAY_GAMMA)).round()
+            as u8
+    };
+    (display(r), display(g), display(b))
+}
+
+pub struct Visualizer {}
+
+/// Terminal visualizer.
+impl Visualizer {
+    /// Spawns a new terminal visualizer, drawing on stdout.
+    pub fn spawn(mut input_rx: mpsc::Receiver<Message>) -> Result<()> {
+        let name = "visualizer";
+        std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let rt = runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("failed to create a new tokio runtime");
+                rt.block_on(async move {
+                    let mut view = View::default();
+                    let mut updated = false;
+                    let mut interval = time::interval(time::Duration::from_millis(
+                        1000 / LED_ENGINE_FPS,
+                    ));
+                    print!("\x1b[2J");
+                    loop {
+                        tokio::select! {
+                            message = input_rx.next() => match message {
+                                Some(message) => updated |= view.update(&message),
+                                None => break,
+                            },
+                            _ = interval.tick(), if updated => {
+                                updated = false;
+                                let mut stdout = io::stdout().lock();
+                                stdout
+                                    .write_all(view.render().as_bytes())
+                                    .and_then(|()| stdout.flush())
+                                    .expect("failed to write to stdout");
+                            }
+                        }
+                    }
+                });
+            })
+            .expect("failed to spawn thread");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_color() {
+        assert_eq!(display_color(Argb(None, 255, 0, 0)), (255, 0, 0));
+        assert_eq!(display_color(Argb(Some(31), 0, 255, 0)), (0, 255, 0));
+        assert_eq!(display_color(Argb(Some(0), 255, 255, 255)), (0, 0, 0));
+        // low values are brightened, the LEDs are driven linearly
+        let (r, _, _) = display_color(Argb(None, 20, 0, 0));
+        assert!(r > 80, "{r}");
+    }
+
+    #[test]
+    fn test_ring_layout() {
+        let view = View {
+            ring: vec![(1, 0, 0), (2, 0, 0), (3, 0, 0), (4, 0, 0)],
+            center: vec![(0, 0, 9)],
+            ..View::default()
+        };
+        let middle = CANVAS_SIZE / 2;
+        let edge = CANVAS_SIZE - 1;
+        // LED 0 is at the top, and the LEDs go clockwise
+        assert_eq!(view.pixel(middle + 1, 1), (1, 0, 0));
+        assert_eq!(view.pixel(edge - 1, middle + 1), (2, 0, 0));
+        assert_eq!(view.pixel(middle - 1, edge - 1), (3, 0, 0));
+        assert_eq!(view.pixel(1, middle - 1), (4, 0, 0));
+        assert_eq!(view.pixel(middle, middle), (0, 0, 9));
+        assert_eq!(view.pixel(middle, middle - 15), (0, 0, 0));
+    }
+}
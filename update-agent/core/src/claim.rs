@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
 use ed25519_dalek::VerifyingKey;
 use serde::{Deserialize, Serialize};
@@ -27,6 +27,21 @@ pub enum MimeType {
     OctetStream,
     #[serde(rename = "application/x-xz")]
     XZ,
+    /// A zstd compressed component. Decompresses considerably faster than xz at a comparable
+    /// ratio.
+    #[serde(rename = "application/zstd")]
+    Zstd,
+    /// An xz compressed binary patch in bsdiff format. It is applied against the component's
+    /// contents on the currently active slot to produce the component listed in the manifest.
+    #[serde(rename = "application/x-bsdiff+xz")]
+    BsdiffXz,
+}
+
+impl MimeType {
+    /// Whether the source has to be applied against the currently installed component.
+    pub fn is_delta(&self) -> bool {
+        matches!(self, Self::BsdiffXz)
+    }
 }
 
 /// The source of a component.
@@ -257,6 +272,36 @@ impl Claim {
         &self.sources
     }
 
+    /// Resolves sources given as relative paths against `dir`.
+    ///
+    /// Claims shipped on removable media list their components relative to the claim itself,
+    /// since the mount point is only known on the orb. Sources are not covered by the manifest
+    /// signature; the components made from them are checked against the manifest hashes.
+    pub fn resolve_local_sources(&mut self, dir: &Path) {
+        for source in self.sources.values_mut() {
+            source.url.resolve_relative_to(dir);
+        }
+    }
+
+    /// Replaces the system components listed in the claim.
+    ///
+    /// System components are not covered by the manifest signature, so claims from untrusted
+    /// locations take them from the orb instead.
+    pub fn replace_system_components(
+        &mut self,
+        system_components: crate::Components,
+    ) -> Result<(), Error> {
+        let components_not_in_system =
+            find_components_not_in_system(&self.manifest, &system_components);
+        if !components_not_in_system.is_empty() {
+            return Err(Error::ManifestComponentsNotInSystemComponents(
+                components_not_in_system,
+            ));
+        }
+        self.system_components = system_components;
+        Ok(())
+    }
+
     pub fn full_update_size(&self) -> u64 {
         let sources_size = self.sources.iter().fold(0, |acc, (_, s)| acc + s.size);
         let components_size = self
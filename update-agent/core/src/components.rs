@@ -143,7 +143,9 @@ pub enum Error {
 }
 
 impl Gpt {
-    fn get_partition_name(&self, slot: Slot) -> String {
+    /// Name of the partition holding the component on `slot`. Redundant components carry the
+    /// slot as a suffix, as in `<label>_b`.
+    pub fn get_partition_name(&self, slot: Slot) -> String {
         match self.redundancy {
             Redundancy::Redundant => format!("{}_{}", self.label.clone(), slot),
             Redundancy::Single => self.label.clone(),
@@ -180,6 +182,15 @@ impl Gpt {
 }
 
 impl Raw {
+    /// Byte offset of the component on `slot`. Redundant components store slot B directly after
+    /// slot A.
+    pub fn get_offset(&self, slot: Slot) -> u64 {
+        match (slot, self.redundancy) {
+            (Slot::B, Redundancy::Redundant) => self.offset + self.size,
+            _ => self.offset,
+        }
+    }
+
     pub fn get_file(&self) -> Result<File, Error> {
         File::options()
             .read(true)
@@ -1,4 +1,8 @@
-use std::{borrow::Cow, path::PathBuf, str::FromStr};
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use url::{ParseError, Url};
 
@@ -52,6 +56,15 @@ impl LocalOrRemote {
         matches!(self, Self::Remote(..))
     }
 
+    /// Joins a relative local path onto `base`. Absolute paths and remote URLs are left as is.
+    pub fn resolve_relative_to(&mut self, base: &Path) {
+        if let Self::Local(path) = self {
+            if path.is_relative() {
+                *path = base.join(&*path);
+            }
+        }
+    }
+
     pub fn parse(s: &str) -> Result<Self, Error> {
         let location = match Url::parse(s) {
             Ok(url) if url.scheme() == "https" => url.into(),
@@ -160,6 +173,27 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn relative_path_is_resolved_against_base() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let mut relative = LocalOrRemote::parse("components/foo.xz")?;
+        relative.resolve_relative_to("/media/usb".as_ref());
+        assert_eq!(
+            LocalOrRemote::Local("/media/usb/components/foo.xz".into()),
+            relative
+        );
+
+        let mut absolute = LocalOrRemote::parse("file:///foo/bar/baz")?;
+        absolute.resolve_relative_to("/media/usb".as_ref());
+        assert_eq!(LocalOrRemote::Local("/foo/bar/baz".into()), absolute);
+
+        let url = "https://foo.bar/baz";
+        let mut remote = LocalOrRemote::parse(url)?;
+        remote.resolve_relative_to("/media/usb".as_ref());
+        assert_eq!(LocalOrRemote::Remote(Url::parse(url)?), remote);
+        Ok(())
+    }
+
     #[test]
     fn http_remote_is_not_supported() -> Result<(), Box<dyn std::error::Error>> {
         let url = "http://foo.bar?baz=True";
@@ -8,8 +8,8 @@ use std::{
 
 use eyre::{ensure, WrapErr as _};
 use orb_update_agent_core::{
-    reexports::ed25519_dalek::VerifyingKey, Claim, ClaimVerificationContext,
-    LocalOrRemote, Slot, Source, VersionMap,
+    reexports::ed25519_dalek::VerifyingKey, version_map::SlotVersion, Claim,
+    ClaimVerificationContext, Components, LocalOrRemote, Slot, Source, VersionMap,
 };
 use reqwest::{StatusCode, Url};
 use tracing::{debug, info, warn};
@@ -85,6 +85,9 @@ impl Error {
     }
 }
 
+/// Location of the claim on a removable drive, relative to the drive's mount point.
+const USB_CLAIM_PATH: &str = "orb-update/claim.json";
+
 fn make_claim_destination(settings: &Settings) -> PathBuf {
     settings.workspace.join("claim.json")
 }
@@ -159,6 +162,131 @@ fn from_remote(
     }
 }
 
+/// Lists the claims on drives mounted directly below `mount_root`, ordered by mount point.
+fn find_usb_claims(mount_root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut claims = Vec::new();
+    for entry in fs::read_dir(mount_root)? {
+        let path = entry?.path().join(USB_CLAIM_PATH);
+        if path.is_file() {
+            claims.push(path);
+        }
+    }
+    claims.sort();
+    Ok(claims)
+}
+
+/// Reads the first claim on a removable drive that passes verification and does not downgrade
+/// any component, unless version asserts are skipped.
+///
+/// The claim's system components are replaced by `system_components`, since only its manifest
+/// is signed. Sources with relative paths are resolved against the directory holding the claim,
+/// so that components can be shipped next to it.
+fn from_usb(
+    mount_root: &Path,
+    system_components: &Components,
+    settings: &Settings,
+    version_map: &VersionMap,
+) -> Option<Claim> {
+    let claim_paths = match find_usb_claims(mount_root) {
+        Ok(claim_paths) => claim_paths,
+        Err(e) => {
+            warn!(
+                "failed searching removable drives below `{}`: {e:?}",
+                mount_root.display()
+            );
+            return None;
+        }
+    };
+    for path in claim_paths {
+        info!(
+            "reading update claim on removable drive at {}",
+            path.display()
+        );
+        let claim = from_path(&path, settings.verify_manifest_signature_against)
+            .map_err(eyre::Report::new)
+            .and_then(|mut claim| {
+                claim.replace_system_components(system_components.clone())?;
+                if !settings.skip_version_asserts {
+                    ensure_not_older(&claim, version_map, settings.active_slot)?;
+                }
+                Ok(claim)
+            });
+        match claim {
+            Ok(mut claim) => {
+                let dir = path.parent().expect("claim paths are joined onto a drive");
+                claim.resolve_local_sources(dir);
+                return Some(claim);
+            }
+            Err(e) => warn!("ignoring update claim at `{}`: {e:?}", path.display()),
+        }
+    }
+    None
+}
+
+/// Ensures that `claim` does not install any component older than the one installed, so that a
+/// stale drive cannot roll the orb back.
+fn ensure_not_older(
+    claim: &Claim,
+    version_map: &VersionMap,
+    active_slot: Slot,
+) -> eyre::Result<()> {
+    for component in claim.manifest_components() {
+        let installed = match version_map.slot_version(component.name()) {
+            Some(SlotVersion::Single { version }) => version,
+            Some(SlotVersion::Redundant {
+                version_a,
+                version_b,
+            }) => {
+                let version = match active_slot {
+                    Slot::A => version_a,
+                    Slot::B => version_b,
+                };
+                let Some(version) = version else {
+                    continue;
+                };
+                version
+            }
+            None => continue,
+        };
+        ensure_version_not_older(
+            component.name(),
+            component.version_upgrade(),
+            installed,
+        )?;
+    }
+    Ok(())
+}
+
+/// Reads the component definitions at `path`, in the format of `components.json`.
+fn read_components(path: &Path) -> eyre::Result<Components> {
+    let reader = File::open(path)
+        .map(io::BufReader::new)
+        .wrap_err_with(|| format!("failed opening `{}`", path.display()))?;
+    crate::json::deserialize(reader).wrap_err_with(|| {
+        format!("failed reading components from `{}`", path.display())
+    })
+}
+
+fn ensure_version_not_older(
+    name: &str,
+    claimed: &str,
+    installed: &str,
+) -> eyre::Result<()> {
+    let parse = |version: &str| {
+        semver::Version::parse(version).wrap_err_with(|| {
+            format!(
+                "cannot tell whether version `{claimed}` of component `{name}` is older than \
+                 installed version `{installed}`"
+            )
+        })
+    };
+    ensure!(
+        parse(claimed)? >= parse(installed)?,
+        "version `{claimed}` of component `{name}` is older than installed version `{installed}`",
+    );
+    Ok(())
+}
+
 fn to_disk(path: &Path, raw_claim: &str) -> eyre::Result<()> {
     fs::write(path, raw_claim).wrap_err("failed writing raw claim to disk")
 }
@@ -232,6 +360,28 @@ fn ensure_sources_match_claim(
 }
 
 pub fn get(settings: &Settings, version_map: &VersionMap) -> Result<Claim, Error> {
+    match (&settings.usb_mount_root, &settings.usb_components) {
+        (Some(mount_root), Some(components_path)) => {
+            let usb_claim = read_components(components_path)
+                .map_err(|e| warn!("not searching removable drives for claims: {e:?}"))
+                .ok()
+                .and_then(|components| {
+                    from_usb(mount_root, &components, settings, version_map)
+                });
+            if let Some(claim) = usb_claim {
+                return Ok(claim);
+            }
+            debug!(
+                "no usable update claim found on removable drives below `{}`",
+                mount_root.display()
+            );
+        }
+        (Some(_), None) => warn!(
+            "not searching removable drives for claims because no component definitions are \
+             configured for them"
+        ),
+        (None, _) => {}
+    }
     match &settings.update_location {
         LocalOrRemote::Remote(url) => {
             let path = make_claim_destination(settings);
@@ -271,3 +421,43 @@ fn pubkey_from_backend_type(backend: Backend) -> &'static VerifyingKey {
         Backend::Stage => &pubkeys.stage,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{ensure_version_not_older, find_usb_claims, USB_CLAIM_PATH};
+
+    #[test]
+    fn usb_claims_are_found_on_mounted_drives() {
+        let mount_root = tempfile::tempdir().unwrap();
+        for drive in ["b", "a", "empty"] {
+            fs::create_dir_all(mount_root.path().join(drive).join("orb-update"))
+                .unwrap();
+        }
+        for drive in ["b", "a"] {
+            fs::write(mount_root.path().join(drive).join(USB_CLAIM_PATH), "{}")
+                .unwrap();
+        }
+
+        let claims = find_usb_claims(mount_root.path()).unwrap();
+        assert_eq!(
+            claims,
+            [
+                mount_root.path().join("a").join(USB_CLAIM_PATH),
+                mount_root.path().join("b").join(USB_CLAIM_PATH),
+            ]
+        );
+    }
+
+    #[test]
+    fn usb_claims_must_not_downgrade_components() {
+        ensure_version_not_older("main", "1.2.0", "1.1.9").unwrap();
+        ensure_version_not_older("main", "1.2.0", "1.2.0").unwrap();
+        ensure_version_not_older("main", "32.6.1+0.2.0", "32.6.1+0.1.0").unwrap();
+        assert!(ensure_version_not_older("main", "1.1.9", "1.2.0").is_err());
+        assert!(
+            ensure_version_not_older("main", "1.2.0", "amarous-albatross").is_err()
+        );
+    }
+}
@@ -1,7 +1,11 @@
-use std::time::Duration;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use once_cell::sync::OnceCell;
-use reqwest::blocking::Client;
+use reqwest::{blocking::Client, Certificate};
 
 const APP_USER_AGENT: &str =
     concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
@@ -13,6 +17,10 @@ static INSTANCE: OnceCell<Client> = OnceCell::new();
 pub enum Error {
     #[error("failed initializing HTTP client")]
     BuildClient(#[source] reqwest::Error),
+    #[error("failed reading pinned certificate at `{}`", .0.display())]
+    ReadCertificate(PathBuf, #[source] io::Error),
+    #[error("failed parsing pinned certificate at `{}` as PEM", .0.display())]
+    ParseCertificate(PathBuf, #[source] reqwest::Error),
 }
 
 // Return a HTTPS client with explicit reasonable defaults
@@ -37,3 +45,24 @@ fn initialize() -> Result<Client, Error> {
         .build()
         .map_err(Error::BuildClient)
 }
+
+/// Returns a HTTPS client that trusts only the PEM encoded `certificate`.
+///
+/// Used to talk to peers on the local network, which have no publicly trusted certificate. The
+/// peer must present `certificate` itself or a certificate issued by it.
+pub fn pinned(certificate: &Path) -> Result<Client, Error> {
+    let pem = std::fs::read(certificate)
+        .map_err(|e| Error::ReadCertificate(certificate.to_path_buf(), e))?;
+    let certificate = Certificate::from_pem(&pem)
+        .map_err(|e| Error::ParseCertificate(certificate.to_path_buf(), e))?;
+    Client::builder()
+        .tls_built_in_root_certs(false)
+        .add_root_certificate(certificate)
+        .min_tls_version(reqwest::tls::Version::TLS_1_3)
+        .redirect(reqwest::redirect::Policy::none())
+        .https_only(true)
+        .user_agent(APP_USER_AGENT)
+        .timeout(Duration::from_secs(120))
+        .build()
+        .map_err(Error::BuildClient)
+}
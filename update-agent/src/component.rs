@@ -4,13 +4,18 @@
 //! defined here also includes its source and location on disk.
 use std::{
     fs::{metadata, remove_file, File, OpenOptions},
-    io::{self, copy},
+    io::{self, copy, BufRead, BufReader, BufWriter, Read, Write},
     num::ParseIntError,
+    os::unix::fs::FileExt as _,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
     time::Duration,
 };
 
-use eyre::{ensure, WrapErr as _};
+use eyre::{bail, ensure, WrapErr as _};
 use orb_update_agent_core::{
     components, manifest::InstallationPhase, Claim, LocalOrRemote, ManifestComponent,
     MimeType, Slot, Source,
@@ -19,11 +24,20 @@ use reqwest::{
     header::{ToStrError, CONTENT_LENGTH, RANGE},
     Url,
 };
-use tracing::{info, warn};
+use sha2::{Digest as _, Sha256};
+use tracing::{debug, info, warn};
 
-use crate::{dbus, update::Update as _, util};
+use crate::{
+    dbus, delta,
+    journal::{self, Journal},
+    peer_mirror::PeerMirror,
+    progress::{Counted, Counter, PROGRESS},
+    update::{ReadInstalled as _, Update as _, WriteTarget as _},
+    util, Settings,
+};
 
 const CHUNK_SIZE: u32 = 4 * 1024 * 1024;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -49,12 +63,60 @@ pub enum Error {
         "request for range `{0}` {0:#} returned status code `{1}`, expected range 200-299: {2}"
     )]
     ResponseStatus(util::Range, reqwest::StatusCode, Url),
-    #[error("failed retrieving the response body for range `{0}` {0:#} as bytes: {1}")]
-    GetBytes(util::Range, Url, #[source] reqwest::Error),
+    #[error("failed reading the response body for range `{0}` {0:#}: {1}")]
+    ReadRange(util::Range, Url, #[source] io::Error),
+    #[error(
+        "response body for range `{range}` {range:#} ended after {received} of {expected} bytes: \
+         {url}"
+    )]
+    ShortRange {
+        range: util::Range,
+        expected: u64,
+        received: u64,
+        url: Url,
+    },
     #[error("failed copying retrieved chunk `{0}` {0:#} to target `{target}`: {2}", target = .1.display())]
     MergeChunk(util::Range, PathBuf, Url, #[source] io::Error),
+    #[error("failed persisting the download journal")]
+    Journal(#[from] journal::Error),
+    #[error("failed verifying ranges recorded in the download journal against `{}`", .0.display())]
+    VerifyJournal(PathBuf, #[source] io::Error),
+    #[error("failed sending the request to stream the component: `{0}`")]
+    StreamRequest(Url, #[source] reqwest::Error),
+    #[error("request to stream the component returned status code `{0}`, expected range 200-299: {1}")]
+    StreamStatus(reqwest::StatusCode, Url),
+    #[error(
+        "component `{name}` written to slot {slot} does not match the manifest; expected \
+         {expected_size} bytes with hash `{expected_hash}`, wrote {actual_size} bytes with hash \
+         `{actual_hash}`"
+    )]
+    InstalledHashMismatch {
+        name: String,
+        slot: Slot,
+        expected_size: u64,
+        expected_hash: String,
+        actual_size: u64,
+        actual_hash: String,
+    },
+    #[error("streaming component `{name}` to slot {slot} failed after writing to it")]
+    StreamInterrupted {
+        name: String,
+        slot: Slot,
+        source: io::Error,
+    },
+    #[error("failed resolving component on peer mirror")]
+    PeerMirror(#[from] crate::peer_mirror::Error),
     #[error("failed verifying source component `{name}` against claim")]
     HashMismatch { name: String, source: eyre::Report },
+    #[error(
+        "uncompressed source of component `{name}` has hash `{source_hash}` instead of the hash \
+         `{manifest_hash}` signed in the manifest"
+    )]
+    UnsignedSourceHash {
+        name: String,
+        source_hash: String,
+        manifest_hash: String,
+    },
     #[error(
         "MIME type of component `{name}` was set to `{actual_type}`; only `application/x-xz` MIME \
          types are supported"
@@ -82,13 +144,23 @@ impl Component {
         self.manifest_component.name()
     }
 
-    fn process_compressed(&mut self, dst: &Path) -> eyre::Result<()> {
+    fn process_compressed(
+        &mut self,
+        dst: &Path,
+        active_slot: Slot,
+    ) -> eyre::Result<()> {
         let uncompressed_path = dst.with_extension("uncompressed");
         let uncompressed_path_verified =
             get_verified_component_path(&uncompressed_path);
 
-        match check_existing_component(&uncompressed_path, self.manifest_component.size)
-        {
+        // Components made from local sources, which may live on removable drives, are always
+        // processed and hashed again.
+        let existing = if self.source.is_remote() {
+            check_existing_component(&uncompressed_path, self.manifest_component.size)
+        } else {
+            Err(eyre::eyre!("source is local"))
+        };
+        match existing {
             Ok(()) => {
                 info!(
                     "found verification file at `{}`, skipping hash verification of decompressed \
@@ -108,13 +180,33 @@ impl Component {
             }
         }
 
-        info!("extracting {}", self.manifest_component.name());
-        extract(&self.on_disk, &uncompressed_path).wrap_err_with(|| {
-            format!(
-                "failed decompressing component at `{}`",
-                self.on_disk.display()
+        if self.source.mime_type.is_delta() {
+            info!(
+                "applying delta {} against slot {active_slot}",
+                self.manifest_component.name()
+            );
+            apply_delta(
+                &self.system_component,
+                active_slot,
+                &self.on_disk,
+                &uncompressed_path,
             )
-        })?;
+            .wrap_err_with(|| {
+                format!(
+                    "failed applying delta component at `{}`",
+                    self.on_disk.display()
+                )
+            })?;
+        } else {
+            info!("extracting {}", self.manifest_component.name());
+            extract(&self.source.mime_type, &self.on_disk, &uncompressed_path)
+                .wrap_err_with(|| {
+                    format!(
+                        "failed decompressing component at `{}`",
+                        self.on_disk.display()
+                    )
+                })?;
+        }
         info!(
             "checking sha256 hash of extracted {}",
             self.manifest_component.name()
@@ -156,15 +248,20 @@ impl Component {
         Ok(())
     }
 
-    pub fn process(&mut self, dst: &Path) -> eyre::Result<()> {
+    /// Turns the fetched source into the component listed in the manifest.
+    ///
+    /// Delta sources are applied against the component installed on `active_slot`.
+    pub fn process(&mut self, dst: &Path, active_slot: Slot) -> eyre::Result<()> {
         match self.source.mime_type {
-            MimeType::XZ => self.process_compressed(dst),
+            MimeType::XZ | MimeType::Zstd | MimeType::BsdiffXz => {
+                self.process_compressed(dst, active_slot)
+            }
             MimeType::OctetStream => Ok(()),
         }
     }
 
     fn do_install(&self, slot: Slot, claim: &Claim) -> eyre::Result<()> {
-        let mut component_file = File::options()
+        let component_file = File::options()
             .read(true)
             .create(false)
             .open(&self.on_disk)
@@ -174,6 +271,7 @@ impl Component {
                     self.on_disk.display(),
                 )
             })?;
+        let mut component_file = Counted::new(component_file, Counter::Written);
         // FIXME: Panic has some surprising behaviour pre-2021; update rust edition to 2021
         //        and fix the format string.
         claim
@@ -258,70 +356,305 @@ fn get_verified_component_path(component_path: &Path) -> PathBuf {
     component_path.with_extension("verified")
 }
 
-fn extract<P: AsRef<Path>>(path: P, uncompressed_download_path: P) -> eyre::Result<()> {
+/// Wraps `reader` in the streaming decoder matching `mime_type`.
+fn decoder<'a, R: BufRead + 'a>(
+    mime_type: &MimeType,
+    reader: R,
+) -> eyre::Result<Box<dyn Read + 'a>> {
+    let decoder: Box<dyn Read + 'a> = match mime_type {
+        MimeType::XZ | MimeType::BsdiffXz => {
+            Box::new(xz2::read::XzDecoder::new(reader))
+        }
+        MimeType::Zstd => Box::new(
+            zstd::stream::read::Decoder::with_buffer(reader)
+                .wrap_err("failed to initialize zstd decoder")?,
+        ),
+        MimeType::OctetStream => Box::new(reader),
+    };
+    Ok(decoder)
+}
+
+fn extract(
+    mime_type: &MimeType,
+    path: &Path,
+    uncompressed_download_path: &Path,
+) -> eyre::Result<()> {
     let compressed_download = File::options()
         .read(true)
         .write(false)
         .create(false)
-        .open(&path)
+        .open(path)
         .wrap_err_with(|| {
             format!(
                 "failed to open component for decompression at `{}`",
-                path.as_ref().display()
+                path.display()
             )
         })?;
 
-    let mut decoder = xz2::read::XzDecoder::new(compressed_download);
+    let mut decoder = decoder(mime_type, BufReader::new(compressed_download))?;
     let mut uncompressed_download = File::options()
         .write(true)
         .truncate(true)
         .create(true)
-        .open(&uncompressed_download_path)
+        .open(uncompressed_download_path)
         .wrap_err_with(|| {
             format!(
                 "failed to open target to store decompressed component at `{}`",
-                path.as_ref().display()
+                path.display()
             )
         })?;
     copy(&mut decoder, &mut uncompressed_download).wrap_err_with(|| {
-        format!("failed to decompress file at `{}`", path.as_ref().display())
+        format!("failed to decompress file at `{}`", path.display())
     })?;
     Ok(())
 }
 
+fn apply_delta(
+    system_component: &components::Component,
+    active_slot: Slot,
+    patch_path: &Path,
+    dst: &Path,
+) -> eyre::Result<()> {
+    let mut base = system_component
+        .read_installed(active_slot)
+        .wrap_err_with(|| {
+            format!("failed to open installed component on slot {active_slot} as delta base")
+        })?;
+    let patch = File::open(patch_path).wrap_err_with(|| {
+        format!(
+            "failed to open delta component at `{}`",
+            patch_path.display()
+        )
+    })?;
+    let mut patch = xz2::read::XzDecoder::new(BufReader::new(patch));
+    let mut patched = File::options()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(dst)
+        .map(BufWriter::new)
+        .wrap_err_with(|| {
+            format!(
+                "failed to open target to store patched component at `{}`",
+                dst.display()
+            )
+        })?;
+    let written = delta::apply_patch(&mut base, &mut patch, &mut patched)
+        .wrap_err("failed to apply patch")?;
+    patched
+        .flush()
+        .wrap_err("failed to flush patched component")?;
+    debug!("-- wrote {written} patched bytes to `{}`", dst.display());
+    Ok(())
+}
+
+/// Tuning knobs for downloading remote components.
+#[derive(Clone, Copy, Debug)]
+pub struct DownloadOptions<'a> {
+    /// Delay between dispatching two ranges while the orb is in use.
+    pub delay: Duration,
+    /// Number of ranges that are downloaded concurrently.
+    pub parallelism: usize,
+    /// Upper bound on the combined throughput of all ranges in bytes per second.
+    pub bandwidth_limit: Option<u64>,
+    /// Peer that remote components are fetched from before trying their original location.
+    pub peer_mirror: Option<&'a PeerMirror>,
+}
+
+impl<'a> DownloadOptions<'a> {
+    pub fn from_settings(
+        settings: &Settings,
+        peer_mirror: Option<&'a PeerMirror>,
+    ) -> Self {
+        Self {
+            delay: settings.download_delay,
+            parallelism: settings.download_parallelism,
+            bandwidth_limit: settings.download_bandwidth_limit,
+            peer_mirror,
+        }
+    }
+}
+
+/// Opens the component blob and its journal, restoring as much of a previous download as can be
+/// verified.
 #[expect(clippy::result_large_err)]
-pub fn download<P: AsRef<Path>>(
-    url: &Url,
-    name: &str,
-    unique_name: &str,
+fn prepare_download(
+    component_path: &Path,
+    journal_path: &Path,
     size: u64,
-    dst_dir: P,
-    supervisor_proxy: Option<&dbus::SupervisorProxyBlocking<'static>>,
-    download_delay: Duration,
-) -> Result<PathBuf, Error> {
-    let component_path = util::make_component_path(dst_dir, unique_name);
-    let component_file_len = match metadata(&component_path)
-        .map(|metadata| metadata.len())
-    {
-        Ok(len) if len == size => {
-            info!(
-                "component with matching size form claim found on disk, skipping download of \
-                 `{name}`"
+    name: &str,
+) -> Result<(File, Journal), Error> {
+    let blob = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(component_path)
+        .map_err(|e| Error::OpenWriteTarget(component_path.to_path_buf(), e))?;
+    let blob_len = blob
+        .metadata()
+        .map_err(|e| Error::OpenWriteTarget(component_path.to_path_buf(), e))?
+        .len();
+
+    let journal = match Journal::load(journal_path) {
+        Ok(Some(journal)) if journal.matches(size, CHUNK_SIZE) => Some(journal),
+        Ok(Some(_)) => {
+            warn!(
+                "download journal at `{}` was recorded for a different blob; discarding it",
+                journal_path.display()
             );
-            return Ok(component_path);
+            None
         }
-        Ok(len) => Some(len),
-        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Ok(None) => None,
         Err(e) => {
-            warn!(
-                "failed to query metadata of `{}`: {e:?}",
-                component_path.display()
-            );
+            warn!("failed loading download journal; discarding it: {e:?}");
             None
         }
     };
 
-    let client = crate::client::normal()?;
+    let journal = match journal {
+        Some(mut journal) if blob_len == size => {
+            let forgotten = journal
+                .retain_verified(&blob)
+                .map_err(|e| Error::VerifyJournal(component_path.to_path_buf(), e))?;
+            if forgotten > 0 {
+                warn!(
+                    "{forgotten} ranges of `{name}` recorded in the download journal did not \
+                     match the blob on disk; downloading them again"
+                );
+            }
+            info!(
+                "resuming download of `{name}` with {}/{} ranges already done",
+                journal.num_done(),
+                journal.num_chunks()
+            );
+            journal
+        }
+        _ if blob_len < size && blob_len % (CHUNK_SIZE as u64) == 0 && blob_len > 0 => {
+            info!(
+                "taking over partial download of `{name}` without journal; treating the first \
+                 {blob_len} bytes as done"
+            );
+            let mut journal = Journal::new(size, CHUNK_SIZE);
+            journal
+                .adopt_prefix(&blob, blob_len / CHUNK_SIZE as u64)
+                .map_err(|e| Error::VerifyJournal(component_path.to_path_buf(), e))?;
+            journal
+        }
+        _ => {
+            if blob_len > 0 {
+                warn!(
+                    "blob on disk for `{name}` cannot be resumed; removing its contents and \
+                     restarting download"
+                );
+            }
+            blob.set_len(0)
+                .map_err(|e| Error::OpenWriteTarget(component_path.to_path_buf(), e))?;
+            Journal::new(size, CHUNK_SIZE)
+        }
+    };
+
+    // The journal must exist before the blob is extended to its full size, or else an
+    // interrupted download could be mistaken for a finished one.
+    journal.store(journal_path)?;
+    blob.set_len(size)
+        .map_err(|e| Error::OpenWriteTarget(component_path.to_path_buf(), e))?;
+    Ok((blob, journal))
+}
+
+/// Downloads a single range and writes it to its offset in `blob`, returning the hex encoded
+/// sha256 hash of the range.
+#[expect(clippy::result_large_err)]
+fn download_range(
+    client: &reqwest::blocking::Client,
+    url: &Url,
+    range: util::Range,
+    blob: &File,
+    component_path: &Path,
+    rate_limiter: Option<&util::RateLimiter>,
+) -> Result<String, Error> {
+    let response = client
+        .get(url.clone())
+        .header(RANGE, range.to_string())
+        .send()
+        .map_err(|e| Error::RangeRequest(range, url.clone(), e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(Error::ResponseStatus(range, status, url.clone()));
+    }
+
+    // Never read past the requested range, in case the server ignored the range header.
+    let mut body = response.take(range.num_bytes());
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    let mut offset = range.start();
+    loop {
+        let n = body
+            .read(&mut buf)
+            .map_err(|e| Error::ReadRange(range, url.clone(), e))?;
+        if n == 0 {
+            break;
+        }
+        if let Some(rate_limiter) = rate_limiter {
+            rate_limiter.acquire(n as u64);
+        }
+        hasher.update(&buf[..n]);
+        blob.write_all_at(&buf[..n], offset).map_err(|e| {
+            Error::MergeChunk(range, component_path.to_path_buf(), url.clone(), e)
+        })?;
+        offset += n as u64;
+    }
+    let received = offset - range.start();
+    if received != range.num_bytes() {
+        return Err(Error::ShortRange {
+            range,
+            expected: range.num_bytes(),
+            received,
+            url: url.clone(),
+        });
+    }
+    // Only ranges that made it to disk may be recorded in the journal.
+    blob.sync_data().map_err(|e| {
+        Error::MergeChunk(range, component_path.to_path_buf(), url.clone(), e)
+    })?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[expect(clippy::result_large_err)]
+pub fn download<P: AsRef<Path>>(
+    client: &reqwest::blocking::Client,
+    url: &Url,
+    source: &Source,
+    dst_dir: P,
+    supervisor_proxy: Option<&dbus::SupervisorProxyBlocking<'static>>,
+    options: DownloadOptions<'_>,
+) -> Result<PathBuf, Error> {
+    let (name, size) = (source.name.as_str(), source.size);
+    let component_path = util::make_component_path(dst_dir, &source.unique_name());
+    let journal_path = journal::make_journal_path(&component_path);
+    let component_file_len =
+        match metadata(&component_path).map(|metadata| metadata.len()) {
+            Ok(len) => Some(len),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!(
+                    "failed to query metadata of `{}`: {e:?}",
+                    component_path.display()
+                );
+                None
+            }
+        };
+    // Blobs are extended to their full size as soon as a download starts, so a matching size
+    // alone only means the download is finished if no journal is left behind.
+    if component_file_len == Some(size) && !journal_path.exists() {
+        info!(
+            "component with matching size form claim found on disk, skipping download of \
+             `{name}`"
+        );
+        PROGRESS.add_downloaded(size);
+        return Ok(component_path);
+    }
 
     // We issue a GET request and ignore the response body instead of a HEAD request because AWS S3
     // pre-signed URLs include the HTTP action in the URL signature. Otherwise the server would
@@ -351,136 +684,197 @@ pub fn download<P: AsRef<Path>>(
         ));
     }
 
-    let mut open_options = File::options();
-    open_options.write(true).create(true);
-
-    let start_bytes = match component_file_len {
-        Some(len) if len == size => {
-            info!(
-                "file on disk for component `{}` matches size recorded in claim; skipping download",
-                name,
-            );
-            return Ok(component_path);
-        }
-        Some(len) if len > size => {
-            warn!(
-                "length of file on disk exceeds Content-Length header; removing file and \
-                 restarting download"
-            );
-            if let Err(e) = remove_file(&component_path) {
-                warn!(
-                    "failed removing components blob at `{}`: {e:?}",
-                    component_path.display()
-                );
-            }
-            open_options.truncate(true);
-            0
-        }
-        Some(len) if len % (CHUNK_SIZE as u64) != 0 => {
-            warn!(
-                "length of file on disk is not a multiple of hard coded chunk size; removing file \
-                 and restarting download"
-            );
-            if let Err(e) = remove_file(&component_path) {
-                warn!(
-                    "failed removing components file at `{}`: {e:?}",
-                    component_path.display()
-                );
-            }
-            open_options.truncate(true);
-            0
-        }
-        Some(len) => {
-            open_options.append(true);
-            len
-        }
-        None => {
-            open_options.truncate(true);
-            0
-        }
-    };
-
-    if start_bytes == 0 {
+    let (blob, journal) = prepare_download(&component_path, &journal_path, size, name)?;
+    PROGRESS.add_downloaded(journal.num_done_bytes());
+    if journal.num_done() == 0 {
         info!("starting download to: {}", component_path.display());
     } else {
         info!("resuming download to: {}", component_path.display());
     }
 
-    let dst = open_options
-        .open(&component_path)
-        .map_err(|e| Error::OpenWriteTarget(component_path.clone(), e))?;
+    let pending_ranges: Vec<_> = if size == 0 {
+        Vec::new()
+    } else {
+        util::HttpRangeIter::try_new(0, size - 1, CHUNK_SIZE)?
+            .enumerate()
+            .map(|(i, range)| (i as u64, range))
+            .filter(|(i, _)| !journal.is_done(*i))
+            .collect()
+    };
 
-    let mut current_delay = download_delay;
-    let mut allowed_before = true;
+    let num_chunks = journal.num_chunks();
+    let journal = Mutex::new(journal);
+    let failure: Mutex<Option<Error>> = Mutex::new(None);
+    let rate_limiter = options.bandwidth_limit.map(util::RateLimiter::new);
+    let progress_percent = AtomicU64::new(0);
+
+    std::thread::scope(|scope| {
+        // A rendezvous channel: ranges are only handed out once a worker is ready for them, so
+        // that the delay between dispatches below throttles the download as a whole.
+        let (range_tx, range_rx) = flume::bounded::<(u64, util::Range)>(0);
+        for _ in 0..options.parallelism.max(1) {
+            let range_rx = range_rx.clone();
+            let (blob, journal, failure, component_path, journal_path) =
+                (&blob, &journal, &failure, &component_path, &journal_path);
+            let (rate_limiter, progress_percent) =
+                (rate_limiter.as_ref(), &progress_percent);
+            scope.spawn(move || {
+                for (index, range) in range_rx.iter() {
+                    let hash = match download_range(
+                        client,
+                        url,
+                        range,
+                        blob,
+                        component_path,
+                        rate_limiter,
+                    ) {
+                        Ok(hash) => hash,
+                        Err(e) => {
+                            lock(failure).get_or_insert(e);
+                            return;
+                        }
+                    };
+                    PROGRESS.add_downloaded(range.num_bytes());
+                    let mut journal = lock(journal);
+                    journal.mark_done(index, hash);
+                    if let Err(e) = journal.store(journal_path) {
+                        warn!("failed updating download journal; continuing anyway: {e:?}");
+                    }
+                    let current_percent = journal.num_done() * 100 / num_chunks;
+                    if progress_percent.swap(current_percent, Ordering::Relaxed)
+                        != current_percent
+                    {
+                        info!("downloading component `{name}`: {current_percent}%");
+                    }
+                }
+            });
+        }
+        drop(range_rx);
 
-    let remaining_chunks =
-        ((component_remote_len - start_bytes) / CHUNK_SIZE as u64) as usize;
+        let mut throttle = SupervisorThrottle::new(supervisor_proxy, options.delay);
+        for (index, range) in pending_ranges {
+            if lock(&failure).is_some() {
+                break;
+            }
+            let delay = throttle.current_delay();
 
-    let mut progress_percent = 0;
-    for (i, range) in
-        util::HttpRangeIter::try_new(start_bytes, component_remote_len - 1, CHUNK_SIZE)?
-            .enumerate()
-    {
-        if let Some(current_progress_percent) = (i * 100).checked_div(remaining_chunks)
-        {
-            if progress_percent != current_progress_percent {
-                progress_percent = current_progress_percent;
-                info!("downloading component `{name}`: {progress_percent}%");
+            // Sending only fails if all workers bailed out because of an error.
+            if range_tx.send((index, range)).is_err() {
+                break;
             }
-        } else {
-            info!("downloading component `{name}`: 100%");
+            std::thread::sleep(delay);
         }
+    });
 
+    if let Some(e) = failure.into_inner().unwrap_or_else(|e| e.into_inner()) {
+        return Err(e);
+    }
+    let journal = journal.into_inner().unwrap_or_else(|e| e.into_inner());
+    assert!(
+        journal.is_complete(),
+        "all ranges must be downloaded if no worker failed"
+    );
+    if let Err(e) = Journal::remove(&journal_path) {
+        warn!("failed removing download journal of finished download: {e:?}");
+    }
+    Ok(component_path)
+}
+
+/// Delay between downloading two chunks, depending on whether the supervisor allows background
+/// downloads.
+struct SupervisorThrottle<'a> {
+    proxy: Option<&'a dbus::SupervisorProxyBlocking<'static>>,
+    delay: Duration,
+    current_delay: Duration,
+    allowed_before: bool,
+}
+
+impl<'a> SupervisorThrottle<'a> {
+    fn new(
+        proxy: Option<&'a dbus::SupervisorProxyBlocking<'static>>,
+        delay: Duration,
+    ) -> Self {
+        Self {
+            proxy,
+            delay,
+            current_delay: delay,
+            allowed_before: true,
+        }
+    }
+
+    /// Asks the supervisor whether the orb is in use and returns the delay to wait after the next
+    /// chunk.
+    fn current_delay(&mut self) -> Duration {
         // We are using `downloads_allowed` as a proxy to set/unset the sleep duration for now.
         // Downloads are no longer blocked entirely. Dbus error when communicating are reported
         // but leave the currently set duration unchanged.
-        if let Some(proxy) = supervisor_proxy {
-            match proxy.background_downloads_allowed() {
-                Ok(allowed_now) => {
-                    match (allowed_now, allowed_before) {
-                        (true, false) => info!("orb no longer in use; stop throttling downloads"),
-                        (false, true) => info!("orb in use again; throttling downloads"),
-                        _ => {}
-                    }
-                    if allowed_now {
-                        if current_delay != Duration::ZERO {
-                            info!("stop throttling downloads");
-                        }
-                        current_delay = Duration::ZERO;
-                    } else if current_delay != download_delay {
-                        current_delay = download_delay;
-                        info!("throttling downloads: new delay {}ms", download_delay.as_millis());
+        let Some(proxy) = self.proxy else {
+            return self.current_delay;
+        };
+        match proxy.background_downloads_allowed() {
+            Ok(allowed_now) => {
+                match (allowed_now, self.allowed_before) {
+                    (true, false) => info!("orb no longer in use; stop throttling downloads"),
+                    (false, true) => info!("orb in use again; throttling downloads"),
+                    _ => {}
+                }
+                if allowed_now {
+                    if self.current_delay != Duration::ZERO {
+                        info!("stop throttling downloads");
                     }
-                    allowed_before = allowed_now;
+                    self.current_delay = Duration::ZERO;
+                } else if self.current_delay != self.delay {
+                    self.current_delay = self.delay;
+                    info!("throttling downloads: new delay {}ms", self.delay.as_millis());
                 }
-                Err(e) => warn!(
-                    "checking supervisor for download restrictions failed; leaving download delay \
-                     unchanged: {e:?}"
-                ),
+                self.allowed_before = allowed_now;
             }
+            Err(e) => warn!(
+                "checking supervisor for download restrictions failed; leaving download delay \
+                 unchanged: {e:?}"
+            ),
         }
+        self.current_delay
+    }
+}
 
-        let response = client
-            .get(url.clone())
-            .header(RANGE, range.to_string())
-            .send()
-            .map_err(|e| Error::RangeRequest(range, url.clone(), e))?;
+/// Reader pausing after every [`CHUNK_SIZE`] bytes as long as the supervisor throttles downloads,
+/// like [`download`] does between two ranges.
+struct SupervisorThrottled<'a, R> {
+    inner: R,
+    throttle: SupervisorThrottle<'a>,
+    until_pause: u64,
+}
 
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Error::ResponseStatus(range, status, url.clone()));
+impl<'a, R> SupervisorThrottled<'a, R> {
+    fn new(inner: R, throttle: SupervisorThrottle<'a>) -> Self {
+        Self {
+            inner,
+            throttle,
+            until_pause: CHUNK_SIZE.into(),
         }
+    }
+}
 
-        let response = response
-            .bytes()
-            .map_err(|e| Error::GetBytes(range, url.clone(), e))?;
-        copy(&mut response.as_ref(), &mut &dst).map_err(|e| {
-            Error::MergeChunk(range, component_path.clone(), url.clone(), e)
-        })?;
-
-        std::thread::sleep(current_delay);
+impl<R: Read> Read for SupervisorThrottled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.until_pause == 0 {
+            std::thread::sleep(self.throttle.current_delay());
+            self.until_pause = CHUNK_SIZE.into();
+        }
+        let len = buf
+            .len()
+            .min(self.until_pause.try_into().unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..len])?;
+        self.until_pause -= n as u64;
+        Ok(n)
     }
-    Ok(component_path)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
 }
 
 // Fetches a component by finding it on disk or downloading it from remote.
@@ -491,72 +885,440 @@ pub fn fetch<P: AsRef<Path>>(
     source: &Source,
     dst_dir: P,
     supervisor: Option<&dbus::SupervisorProxyBlocking<'static>>,
-    download_delay: Duration,
+    download_options: DownloadOptions<'_>,
 ) -> Result<Component, Error> {
+    ensure_source_hash_signed(manifest_component, source)?;
     let path = match &source.url {
         LocalOrRemote::Local(path) => path.clone(),
-        LocalOrRemote::Remote(url) => download(
-            url,
-            &source.name,
-            &source.unique_name(),
-            source.size,
-            dst_dir,
-            supervisor,
-            download_delay,
-        )?,
+        LocalOrRemote::Remote(url) => {
+            let mirrored = download_options.peer_mirror.and_then(|mirror| {
+                fetch_from_peer(
+                    mirror,
+                    source,
+                    dst_dir.as_ref(),
+                    supervisor,
+                    download_options,
+                )
+                .map_err(|e| {
+                    warn!(
+                        "failed fetching `{}` from peer mirror at {}; falling back to its \
+                         original location: {:?}",
+                        source.name,
+                        mirror.base(),
+                        eyre::Report::new(e),
+                    )
+                })
+                .ok()
+            });
+            match mirrored {
+                Some(path) => path,
+                None => download(
+                    crate::client::normal()?,
+                    url,
+                    source,
+                    dst_dir,
+                    supervisor,
+                    download_options,
+                )?,
+            }
+        }
     };
     info!(
         "checking sha256 hash of downloaded `{}`",
         manifest_component.name()
     );
-    let path_verified = get_verified_component_path(&path);
+    verify_source(&path, source)?;
+
+    Ok(Component {
+        manifest_component: manifest_component.clone(),
+        system_component: system_component.clone(),
+        source: source.clone(),
+        on_disk: path,
+    })
+}
+
+/// Downloads `source` from `mirror` and verifies it.
+#[expect(clippy::result_large_err)]
+fn fetch_from_peer(
+    mirror: &PeerMirror,
+    source: &Source,
+    dst_dir: &Path,
+    supervisor: Option<&dbus::SupervisorProxyBlocking<'static>>,
+    download_options: DownloadOptions<'_>,
+) -> Result<PathBuf, Error> {
+    let url = mirror.url_for(source)?;
+    info!("fetching `{}` from peer mirror at {url}", source.name);
+    let path = download(
+        mirror.client(),
+        &url,
+        source,
+        dst_dir,
+        supervisor,
+        download_options,
+    )?;
+    verify_source(&path, source)?;
+    Ok(path)
+}
+
+/// Ensures that the hash of an uncompressed `source`, which is not signed itself, is the one
+/// signed in the manifest. Other sources are checked against the manifest once processed.
+#[expect(clippy::result_large_err)]
+fn ensure_source_hash_signed(
+    manifest_component: &ManifestComponent,
+    source: &Source,
+) -> Result<(), Error> {
+    match source.mime_type {
+        MimeType::OctetStream
+            if !source.hash.eq_ignore_ascii_case(manifest_component.hash()) =>
+        {
+            Err(Error::UnsignedSourceHash {
+                name: source.name.clone(),
+                source_hash: source.hash.clone(),
+                manifest_hash: manifest_component.hash().to_owned(),
+            })
+        }
+        MimeType::OctetStream | MimeType::XZ | MimeType::Zstd | MimeType::BsdiffXz => {
+            Ok(())
+        }
+    }
+}
 
-    if path_verified.exists() {
+/// Checks the blob at `path` against the hash of `source`, deleting downloaded blobs that don't
+/// match. Successfully verified downloads are marked so that they are not hashed again; local
+/// blobs are always hashed, since they may live on removable drives.
+#[expect(clippy::result_large_err)]
+fn verify_source(path: &Path, source: &Source) -> Result<(), Error> {
+    let path_verified = get_verified_component_path(path);
+
+    if source.is_remote() && path_verified.exists() {
         info!(
             "found verification file at `{}`, skipping hash verification of `{}`",
             path_verified.display(),
             source.name,
         );
-    } else {
-        if let Err(e) =
-            util::check_hash(&path, &source.hash).map_err(|e| Error::HashMismatch {
-                name: source.name.clone(),
-                source: e,
-            })
-        {
-            if source.url.is_remote() {
+        return Ok(());
+    }
+    if let Err(e) =
+        util::check_hash(path, &source.hash).map_err(|e| Error::HashMismatch {
+            name: source.name.clone(),
+            source: e,
+        })
+    {
+        if source.url.is_remote() {
+            warn!(
+                "deleting downloaded source blob of component `{}` because hash verification \
+                 failed; see logs for more info",
+                source.name
+            );
+            if let Err(rm_err) = remove_file(path) {
                 warn!(
-                    "deleting downloaded source blob of component `{}` because hash verification \
-                     failed; see logs for more info",
-                    source.name
+                    "failed deleting source blob of component `{}` at `{}`: {rm_err:?}",
+                    source.name,
+                    path.display(),
                 );
-                if let Err(rm_err) = remove_file(&path) {
-                    warn!(
-                        "failed deleting source blob of component `{}` at `{}`: {rm_err:?}",
-                        source.name,
-                        path.display(),
-                    );
-                }
             }
-            return Err(e);
         }
-        if let Err(e) = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(path_verified)
-        {
-            warn!(
-                "failed marking component `{}` as verified: {e:?}",
-                source.name
+        return Err(e);
+    }
+    if source.is_local() {
+        return Ok(());
+    }
+    if let Err(e) = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path_verified)
+    {
+        warn!(
+            "failed marking component `{}` as verified: {e:?}",
+            source.name
+        )
+    }
+    Ok(())
+}
+
+/// Whether a component of `phase` is installed when running with `recovery`.
+pub fn is_installed_in(phase: InstallationPhase, recovery: bool) -> bool {
+    matches!(
+        (phase, recovery),
+        (InstallationPhase::Normal, false) | (InstallationPhase::Recovery, true)
+    )
+}
+
+/// Whether a component can be installed with [`stream_install`] instead of being staged with
+/// [`fetch`] first.
+///
+/// Only remote, non-delta sources for GPT and raw components qualify. Components that would be
+/// skipped in the current installation phase are staged as before.
+pub fn is_streamable(
+    manifest_component: &ManifestComponent,
+    system_component: &components::Component,
+    source: &Source,
+    recovery: bool,
+) -> bool {
+    source.is_remote()
+        && !source.mime_type.is_delta()
+        && matches!(
+            system_component,
+            components::Component::Gpt(_) | components::Component::Raw(_)
+        )
+        && is_installed_in(manifest_component.installation_phase(), recovery)
+}
+
+/// Downloads `source` and writes it to where `system_component` is installed on `slot` in a
+/// single pass, decompressing and hashing it on the way.
+///
+/// Nothing is staged on disk, so an interrupted install has to start over. If the written
+/// component does not match the manifest, [`Error::InstalledHashMismatch`] is returned, and if
+/// streaming fails after the first byte was written, [`Error::StreamInterrupted`]. In both cases
+/// the contents of `slot` must not be booted. Streamed components are always fetched from their
+/// original location and never from a peer mirror, and are throttled like [`download`] while the
+/// orb is in use.
+pub fn stream_install(
+    manifest_component: &ManifestComponent,
+    system_component: &components::Component,
+    source: &Source,
+    slot: Slot,
+    supervisor_proxy: Option<&dbus::SupervisorProxyBlocking<'static>>,
+    download_options: DownloadOptions<'_>,
+) -> eyre::Result<()> {
+    let LocalOrRemote::Remote(url) = &source.url else {
+        bail!("source of component `{}` is not remote", source.name);
+    };
+    let expected_hash = hex::decode(manifest_component.hash()).wrap_err_with(|| {
+        format!(
+            "failed to decode hex string as hash: {}",
+            manifest_component.hash()
+        )
+    })?;
+    let response = crate::client::normal()
+        .map_err(Error::InitClient)?
+        .get(url.clone())
+        .send()
+        .map_err(|e| Error::StreamRequest(url.clone(), e))?;
+    if !response.status().is_success() {
+        return Err(Error::StreamStatus(response.status(), url.clone()).into());
+    }
+    let response = SupervisorThrottled::new(
+        response,
+        SupervisorThrottle::new(supervisor_proxy, download_options.delay),
+    );
+    let response = Counted::new(
+        util::Throttled::new(response, download_options.bandwidth_limit),
+        Counter::Downloaded,
+    );
+    let mut decoder = Counted::new(
+        decoder(
+            &source.mime_type,
+            BufReader::with_capacity(READ_BUFFER_SIZE, response),
+        )?,
+        Counter::Written,
+    );
+
+    let target = system_component
+        .open_target(slot, manifest_component.size)
+        .wrap_err_with(|| {
+            format!(
+                "failed to open target of component `{}` on slot {slot}",
+                manifest_component.name()
             )
+        })?;
+    let mut target = Touched::new(target);
+    info!(
+        "streaming {} from `{url}` to slot {slot}",
+        manifest_component.name()
+    );
+    let copied = copy_hashed(&mut decoder, &mut target, manifest_component.size)
+        .and_then(|copied| target.flush().map(|()| copied));
+    let (written, hash) = match copied {
+        Ok(copied) => copied,
+        Err(e) if target.touched => {
+            return Err(Error::StreamInterrupted {
+                name: manifest_component.name().to_string(),
+                slot,
+                source: e,
+            }
+            .into());
         }
+        Err(e) => {
+            return Err(e).wrap_err_with(|| {
+                format!(
+                    "failed streaming component `{}` to slot {slot}",
+                    manifest_component.name()
+                )
+            });
+        }
+    };
+    debug!("-- streamed {written} bytes to slot {slot}");
+
+    if written != manifest_component.size || hash[..] != expected_hash[..] {
+        return Err(Error::InstalledHashMismatch {
+            name: manifest_component.name().to_string(),
+            slot,
+            expected_size: manifest_component.size,
+            expected_hash: manifest_component.hash().to_string(),
+            actual_size: written,
+            actual_hash: hex::encode(hash),
+        }
+        .into());
     }
+    Ok(())
+}
 
-    Ok(Component {
-        manifest_component: manifest_component.clone(),
-        system_component: system_component.clone(),
-        source: source.clone(),
-        on_disk: path,
-    })
+/// Writer remembering whether anything was written through it.
+struct Touched<W> {
+    inner: W,
+    touched: bool,
+}
+
+impl<W> Touched<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            touched: false,
+        }
+    }
+}
+
+impl<W: Write> Write for Touched<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Set before writing, a failing write may still have written part of `buf`.
+        self.touched |= !buf.is_empty();
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Copies `src` to `dst` while hashing it, failing instead of writing more than `limit` bytes.
+///
+/// Returns the number of bytes copied and their sha256 hash.
+fn copy_hashed<R, W>(
+    src: &mut R,
+    dst: &mut W,
+    limit: u64,
+) -> io::Result<(u64, [u8; 32])>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+{
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    let mut written = 0u64;
+    loop {
+        let n = match src.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if written + n as u64 > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("source is larger than the expected {limit} bytes"),
+            ));
+        }
+        hasher.update(&buf[..n]);
+        dst.write_all(&buf[..n])?;
+        written += n as u64;
+    }
+    Ok((written, hasher.finalize().into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use orb_update_agent_core::{
+        manifest::InstallationPhase, LocalOrRemote, ManifestComponent, MimeType, Source,
+    };
+    use sha2::{Digest as _, Sha256};
+
+    use super::{
+        copy_hashed, ensure_source_hash_signed, get_verified_component_path,
+        verify_source, Error,
+    };
+
+    fn manifest_component(hash: &str) -> ManifestComponent {
+        ManifestComponent {
+            name: "rootfs".to_owned(),
+            version_assert: "1.0.0".to_owned(),
+            version_upgrade: "1.1.0".to_owned(),
+            size: 4,
+            hash: hash.to_owned(),
+            installation_phase: InstallationPhase::Normal,
+        }
+    }
+
+    fn source(mime_type: MimeType, hash: &str, url: LocalOrRemote) -> Source {
+        Source {
+            hash: hash.to_owned(),
+            mime_type,
+            name: "rootfs".to_owned(),
+            size: 4,
+            url,
+        }
+    }
+
+    #[test]
+    fn copy_hashed_hashes_what_it_writes() {
+        let src = vec![7u8; 200_000];
+        let mut dst = Vec::new();
+        let (written, hash) =
+            copy_hashed(&mut src.as_slice(), &mut dst, src.len() as u64).unwrap();
+        assert_eq!(written, src.len() as u64);
+        assert_eq!(dst, src);
+        assert_eq!(hash[..], Sha256::digest(&src)[..]);
+    }
+
+    #[test]
+    fn copy_hashed_refuses_to_write_past_limit() {
+        let src = vec![7u8; 100];
+        let mut dst = Vec::new();
+        let err = copy_hashed(&mut src.as_slice(), &mut dst, 99).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(dst.is_empty());
+    }
+
+    #[test]
+    fn uncompressed_sources_must_have_the_manifest_hash() {
+        let signed = hex::encode(Sha256::digest(b"good"));
+        let unsigned = hex::encode(Sha256::digest(b"evil"));
+        let url = LocalOrRemote::Local("/media/usb/rootfs".into());
+        let component = manifest_component(&signed);
+
+        let matching =
+            source(MimeType::OctetStream, &signed.to_uppercase(), url.clone());
+        ensure_source_hash_signed(&component, &matching).unwrap();
+        let mismatching = source(MimeType::OctetStream, &unsigned, url.clone());
+        assert!(matches!(
+            ensure_source_hash_signed(&component, &mismatching),
+            Err(Error::UnsignedSourceHash { .. })
+        ));
+        // Compressed sources are checked against the manifest once decompressed.
+        let compressed = source(MimeType::Zstd, &unsigned, url);
+        ensure_source_hash_signed(&component, &compressed).unwrap();
+    }
+
+    #[test]
+    fn local_sources_are_hashed_despite_verification_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rootfs");
+        fs::write(&path, b"evil").unwrap();
+        fs::write(get_verified_component_path(&path), b"").unwrap();
+        let hash = hex::encode(Sha256::digest(b"good"));
+
+        let local = source(
+            MimeType::OctetStream,
+            &hash,
+            LocalOrRemote::Local(path.clone()),
+        );
+        assert!(matches!(
+            verify_source(&path, &local),
+            Err(Error::HashMismatch { .. })
+        ));
+        assert!(path.exists(), "local sources must not be deleted");
+    }
 }
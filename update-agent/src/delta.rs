@@ -0,0 +1,233 @@
+//! Application of binary patches for delta update components.
+//!
+//! Patches use the format produced by the [`bsdiff`](https://crates.io/crates/bsdiff) crate:
+//! a sequence of control triples, each made up of three sign-magnitude little endian 64 bit
+//! integers:
+//!
+//! 1. `mix_len`: number of bytes that follow the triple and are added bytewise to the base;
+//! 2. `copy_len`: number of bytes that follow the mix bytes and are copied verbatim;
+//! 3. `seek_len`: signed number of bytes the cursor in the base is moved after the mix step.
+//!
+//! The patch ends with the last complete triple. Unlike the reference implementation, the base
+//! is not loaded into memory but read on demand, so that patches can be applied against whole
+//! partitions.
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed reading patch")]
+    ReadPatch(#[source] io::Error),
+    #[error("patch ended in the middle of a control block or its data")]
+    TruncatedPatch,
+    #[error("patch contained negative length `{0}` in control block")]
+    NegativeLength(i64),
+    #[error("patch referenced offset `{0}` outside of the base")]
+    BaseOutOfBounds(i64),
+    #[error("failed reading base")]
+    ReadBase(#[source] io::Error),
+    #[error("failed writing patched output")]
+    WriteOutput(#[source] io::Error),
+}
+
+/// Decodes a sign-magnitude little endian integer as used by bsdiff.
+fn offtin(buf: [u8; 8]) -> i64 {
+    let magnitude = i64::from_le_bytes(buf) & i64::MAX;
+    if buf[7] & 0x80 == 0 {
+        magnitude
+    } else {
+        -magnitude
+    }
+}
+
+/// Fills `buf` completely, returning `false` if `reader` was already exhausted.
+fn read_control<R: Read + ?Sized>(
+    reader: &mut R,
+    buf: &mut [u8; 24],
+) -> Result<bool, Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(Error::TruncatedPatch),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(Error::ReadPatch(e)),
+        }
+    }
+    Ok(true)
+}
+
+fn read_patch_exact<R: Read + ?Sized>(
+    patch: &mut R,
+    buf: &mut [u8],
+) -> Result<(), Error> {
+    patch.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => Error::TruncatedPatch,
+        _ => Error::ReadPatch(e),
+    })
+}
+
+fn non_negative(len: i64) -> Result<u64, Error> {
+    u64::try_from(len).map_err(|_| Error::NegativeLength(len))
+}
+
+/// Applies `patch` to `base`, writing the result to `output`.
+///
+/// Returns the number of bytes written.
+pub fn apply_patch<B, P, W>(
+    base: &mut B,
+    patch: &mut P,
+    output: &mut W,
+) -> Result<u64, Error>
+where
+    B: Read + Seek + ?Sized,
+    P: Read + ?Sized,
+    W: Write + ?Sized,
+{
+    let mut control = [0u8; 24];
+    let mut patch_buf = vec![0u8; BUFFER_SIZE];
+    let mut base_buf = vec![0u8; BUFFER_SIZE];
+    let mut base_pos: i64 = 0;
+    let mut written = 0;
+
+    while read_control(patch, &mut control)? {
+        let [mix_len, copy_len, seek_len] = [0, 8, 16]
+            .map(|i| offtin(control[i..i + 8].try_into().expect("slice has length 8")));
+        let mut mix_len = non_negative(mix_len)?;
+        let mut copy_len = non_negative(copy_len)?;
+
+        if mix_len > 0 {
+            let start = u64::try_from(base_pos)
+                .map_err(|_| Error::BaseOutOfBounds(base_pos))?;
+            base.seek(SeekFrom::Start(start)).map_err(Error::ReadBase)?;
+        }
+        while mix_len > 0 {
+            let n = mix_len.min(BUFFER_SIZE as u64) as usize;
+            read_patch_exact(patch, &mut patch_buf[..n])?;
+            base.read_exact(&mut base_buf[..n])
+                .map_err(|e| match e.kind() {
+                    io::ErrorKind::UnexpectedEof => Error::BaseOutOfBounds(base_pos),
+                    _ => Error::ReadBase(e),
+                })?;
+            for (new, old) in patch_buf[..n].iter_mut().zip(&base_buf[..n]) {
+                *new = new.wrapping_add(*old);
+            }
+            output
+                .write_all(&patch_buf[..n])
+                .map_err(Error::WriteOutput)?;
+            mix_len -= n as u64;
+            base_pos += n as i64;
+            written += n as u64;
+        }
+
+        while copy_len > 0 {
+            let n = copy_len.min(BUFFER_SIZE as u64) as usize;
+            read_patch_exact(patch, &mut patch_buf[..n])?;
+            output
+                .write_all(&patch_buf[..n])
+                .map_err(Error::WriteOutput)?;
+            copy_len -= n as u64;
+            written += n as u64;
+        }
+
+        base_pos = base_pos
+            .checked_add(seek_len)
+            .ok_or(Error::BaseOutOfBounds(base_pos))?;
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{apply_patch, Error};
+
+    fn intout(x: i64) -> [u8; 8] {
+        let mut buf = x.unsigned_abs().to_le_bytes();
+        if x < 0 {
+            buf[7] |= 0x80;
+        }
+        buf
+    }
+
+    fn control(mix_len: i64, copy_len: i64, seek_len: i64) -> Vec<u8> {
+        [intout(mix_len), intout(copy_len), intout(seek_len)].concat()
+    }
+
+    fn apply(base: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::new();
+        let written =
+            apply_patch(&mut Cursor::new(base), &mut Cursor::new(patch), &mut output)?;
+        assert_eq!(written, output.len() as u64);
+        Ok(output)
+    }
+
+    #[test]
+    fn empty_patch_yields_empty_output() {
+        assert_eq!(apply(b"base", b"").unwrap(), b"");
+    }
+
+    #[test]
+    fn mix_copy_and_seek_are_applied() {
+        let base = b"hello world";
+        // "hello" + [0, 0, 0, 0, 2] = "hellq", then skip the space in the base
+        let mut patch = control(5, 1, 1);
+        patch.extend_from_slice(&[0, 0, 0, 0, 2]);
+        patch.extend_from_slice(b",");
+        // "world" unchanged, then seek back to the second byte of the base
+        patch.extend(control(5, 1, -10));
+        patch.extend_from_slice(&[0; 5]);
+        patch.extend_from_slice(b"!");
+        patch.extend(control(4, 0, 0));
+        patch.extend_from_slice(&[0; 4]);
+
+        assert_eq!(apply(base, &patch).unwrap(), b"hellq,world!ello");
+    }
+
+    #[test]
+    fn mix_wraps_around() {
+        let mut patch = control(1, 0, 0);
+        patch.push(0xff);
+        assert_eq!(apply(&[2], &patch).unwrap(), [1]);
+    }
+
+    #[test]
+    fn truncated_patch_is_rejected() {
+        let mut patch = control(0, 4, 0);
+        patch.extend_from_slice(b"ab");
+        assert!(matches!(apply(b"", &patch), Err(Error::TruncatedPatch)));
+        assert!(matches!(
+            apply(b"", &control(0, 0, 0)[..10]),
+            Err(Error::TruncatedPatch)
+        ));
+    }
+
+    #[test]
+    fn reading_past_base_is_rejected() {
+        let mut patch = control(4, 0, 0);
+        patch.extend_from_slice(&[0; 4]);
+        assert!(matches!(
+            apply(b"abc", &patch),
+            Err(Error::BaseOutOfBounds(_))
+        ));
+
+        let mut patch = control(0, 0, -1);
+        patch.extend(control(1, 0, 0));
+        patch.push(0);
+        assert!(matches!(
+            apply(b"abc", &patch),
+            Err(Error::BaseOutOfBounds(-1))
+        ));
+    }
+
+    #[test]
+    fn negative_lengths_are_rejected() {
+        assert!(matches!(
+            apply(b"", &control(-1, 0, 0)),
+            Err(Error::NegativeLength(-1))
+        ));
+    }
+}
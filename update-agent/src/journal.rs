@@ -0,0 +1,278 @@
+//! Persistent record of the byte ranges of a remote component that were already downloaded.
+//!
+//! The journal lives next to the component blob (see [`make_journal_path`]) and is rewritten
+//! after every completed range. Because each entry also stores the sha256 hash of the range,
+//! a download can be resumed exactly where it stopped after a reboot or a dropped connection,
+//! even if the ranges were fetched out of order by several workers.
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read as _},
+    os::unix::fs::FileExt as _,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+const JOURNAL_EXTENSION: &str = "journal";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed reading download journal at `{}`", .0.display())]
+    Read(PathBuf, #[source] io::Error),
+    #[error("failed parsing download journal at `{}`", .0.display())]
+    Parse(PathBuf, #[source] serde_json::Error),
+    #[error("failed writing download journal to `{}`", .0.display())]
+    Write(PathBuf, #[source] io::Error),
+    #[error("failed removing download journal at `{}`", .0.display())]
+    Remove(PathBuf, #[source] io::Error),
+}
+
+/// Journal file path in form of "{component_path}.journal"
+pub fn make_journal_path(component_path: &Path) -> PathBuf {
+    component_path.with_extension(JOURNAL_EXTENSION)
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Journal {
+    size: u64,
+    chunk_size: u32,
+    /// Completed chunks, keyed by chunk index, with the hex encoded sha256 hash of their contents.
+    chunks: BTreeMap<u64, String>,
+}
+
+impl Journal {
+    pub fn new(size: u64, chunk_size: u32) -> Self {
+        assert!(chunk_size > 0, "chunk size must be non-zero");
+        Self {
+            size,
+            chunk_size,
+            chunks: BTreeMap::new(),
+        }
+    }
+
+    /// Reads the journal at `path`, returning `None` if it does not exist.
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Read(path.to_path_buf(), e)),
+        };
+        let journal: Self = serde_json::from_slice(&contents)
+            .map_err(|e| Error::Parse(path.to_path_buf(), e))?;
+        Ok(Some(journal))
+    }
+
+    /// Atomically replaces the journal at `path` with the contents of `self`.
+    pub fn store(&self, path: &Path) -> Result<(), Error> {
+        let tmp_path = path.with_extension(format!("{JOURNAL_EXTENSION}.tmp"));
+        let contents =
+            serde_json::to_vec(self).expect("journal only contains json-safe types");
+        fs::write(&tmp_path, contents)
+            .and_then(|()| fs::rename(&tmp_path, path))
+            .map_err(|e| Error::Write(path.to_path_buf(), e))
+    }
+
+    /// Removes the journal at `path`. A missing journal is not treated as an error.
+    pub fn remove(path: &Path) -> Result<(), Error> {
+        match fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::Remove(path.to_path_buf(), e)),
+        }
+    }
+
+    /// Whether the journal was recorded for a blob with the given size and chunking.
+    pub fn matches(&self, size: u64, chunk_size: u32) -> bool {
+        self.size == size && self.chunk_size == chunk_size
+    }
+
+    pub fn num_chunks(&self) -> u64 {
+        self.size.div_ceil(u64::from(self.chunk_size))
+    }
+
+    pub fn num_done(&self) -> u64 {
+        self.chunks.len() as u64
+    }
+
+    /// Number of bytes covered by the chunks that are done.
+    pub fn num_done_bytes(&self) -> u64 {
+        self.chunks
+            .keys()
+            .map(|&index| self.chunk_bounds(index).1)
+            .sum()
+    }
+
+    pub fn is_done(&self, index: u64) -> bool {
+        self.chunks.contains_key(&index)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        (0..self.num_chunks()).all(|index| self.is_done(index))
+    }
+
+    pub fn mark_done(&mut self, index: u64, hex_hash: String) {
+        debug_assert!(index < self.num_chunks(), "chunk index out of bounds");
+        self.chunks.insert(index, hex_hash);
+    }
+
+    /// Returns the byte offset and length of chunk `index`.
+    pub fn chunk_bounds(&self, index: u64) -> (u64, u64) {
+        let start = index * u64::from(self.chunk_size);
+        let len = std::cmp::min(u64::from(self.chunk_size), self.size - start);
+        (start, len)
+    }
+
+    /// Marks the first `num_chunks` chunks of `blob` as done, hashing their current contents.
+    ///
+    /// Used to take over partial downloads that were written sequentially without a journal.
+    pub fn adopt_prefix(&mut self, blob: &File, num_chunks: u64) -> io::Result<()> {
+        for index in 0..num_chunks.min(self.num_chunks()) {
+            let hash = self.hash_chunk(blob, index)?;
+            self.mark_done(index, hash);
+        }
+        Ok(())
+    }
+
+    /// Re-hashes all chunks recorded as done and forgets those whose contents in `blob` no longer
+    /// match, for example because they were not flushed to disk before a power loss.
+    ///
+    /// Returns the number of forgotten chunks.
+    pub fn retain_verified(&mut self, blob: &File) -> io::Result<u64> {
+        let mut mismatched = Vec::new();
+        for (&index, expected) in &self.chunks {
+            let verified = index < self.num_chunks()
+                && match self.hash_chunk(blob, index) {
+                    Ok(actual) => actual == *expected,
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+                    Err(e) => return Err(e),
+                };
+            if !verified {
+                mismatched.push(index);
+            }
+        }
+        for index in &mismatched {
+            self.chunks.remove(index);
+        }
+        Ok(mismatched.len() as u64)
+    }
+
+    fn hash_chunk(&self, blob: &File, index: u64) -> io::Result<String> {
+        let (start, len) = self.chunk_bounds(index);
+        let mut hasher = Sha256::new();
+        let mut reader = ReadAt {
+            blob,
+            offset: start,
+        }
+        .take(len);
+        let copied = io::copy(&mut reader, &mut hasher)?;
+        if copied != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "chunk {index} is truncated: expected {len} bytes, read {copied}"
+                ),
+            ));
+        }
+        Ok(hex::encode(hasher.finalize()))
+    }
+}
+
+/// Adapter to read a file from a fixed offset without moving its shared cursor.
+struct ReadAt<'a> {
+    blob: &'a File,
+    offset: u64,
+}
+
+impl io::Read for ReadAt<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.blob.read_at(buf, self.offset)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write as _, os::unix::fs::FileExt as _};
+
+    use super::{make_journal_path, Journal};
+
+    const CHUNK_SIZE: u32 = 4;
+
+    fn blob_with(contents: &[u8]) -> std::fs::File {
+        let mut blob = tempfile::tempfile().unwrap();
+        blob.write_all(contents).unwrap();
+        blob
+    }
+
+    #[test]
+    fn last_chunk_is_shortened_to_size() {
+        let journal = Journal::new(10, CHUNK_SIZE);
+        assert_eq!(journal.num_chunks(), 3);
+        assert_eq!(journal.chunk_bounds(0), (0, 4));
+        assert_eq!(journal.chunk_bounds(2), (8, 2));
+    }
+
+    #[test]
+    fn done_bytes_account_for_short_last_chunk() {
+        let mut journal = Journal::new(10, CHUNK_SIZE);
+        journal.mark_done(2, "c".into());
+        assert_eq!(journal.num_done_bytes(), 2);
+        journal.mark_done(0, "a".into());
+        assert_eq!(journal.num_done_bytes(), 6);
+    }
+
+    #[test]
+    fn empty_blob_is_complete() {
+        let journal = Journal::new(0, CHUNK_SIZE);
+        assert_eq!(journal.num_chunks(), 0);
+        assert!(journal.is_complete());
+    }
+
+    #[test]
+    fn journal_survives_store_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = make_journal_path(&dir.path().join("rootfs-abcd"));
+        assert_eq!(Journal::load(&path).unwrap(), None);
+
+        let mut journal = Journal::new(10, CHUNK_SIZE);
+        journal.mark_done(1, "00".into());
+        journal.store(&path).unwrap();
+
+        let loaded = Journal::load(&path).unwrap().unwrap();
+        assert_eq!(loaded, journal);
+        assert!(loaded.matches(10, CHUNK_SIZE));
+        assert!(!loaded.matches(10, CHUNK_SIZE * 2));
+        assert!(loaded.is_done(1));
+        assert!(!loaded.is_complete());
+
+        Journal::remove(&path).unwrap();
+        assert_eq!(Journal::load(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn adopted_prefix_is_verified_until_modified() {
+        let blob = blob_with(b"0123456789");
+        let mut journal = Journal::new(10, CHUNK_SIZE);
+        journal.adopt_prefix(&blob, 2).unwrap();
+        assert_eq!(journal.num_done(), 2);
+        assert_eq!(journal.retain_verified(&blob).unwrap(), 0);
+
+        blob.write_all_at(b"x", 0).unwrap();
+        assert_eq!(journal.retain_verified(&blob).unwrap(), 1);
+        assert!(!journal.is_done(0));
+        assert!(journal.is_done(1));
+    }
+
+    #[test]
+    fn chunks_beyond_truncated_blob_are_forgotten() {
+        let blob = blob_with(b"01234567");
+        let mut journal = Journal::new(10, CHUNK_SIZE);
+        journal.adopt_prefix(&blob, 2).unwrap();
+        journal.mark_done(2, "89".into());
+        assert_eq!(journal.retain_verified(&blob).unwrap(), 1);
+        assert_eq!(journal.num_done(), 2);
+    }
+}
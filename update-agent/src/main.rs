@@ -17,12 +17,14 @@
 //!    manifest;
 //! 8. actually perform the update by copying the component to its respective position on the
 //!    currently inactive slot.
+//!
+//! If `streaming_install` is set, steps 6 to 8 are done in a single pass for remote GPT and raw
+//! components, writing them to the inactive slot while they are being downloaded.
 use std::{
     borrow::Cow,
     collections::HashSet,
     fs::{self, File},
     path::{Path, PathBuf},
-    time::Duration,
 };
 
 use crate::update::capsule::{EFI_OS_INDICATIONS, EFI_OS_REQUEST_CAPSULE_UPDATE};
@@ -30,11 +32,17 @@ use clap::Parser as _;
 use eyre::{bail, ensure, WrapErr};
 use nix::sys::statvfs;
 use orb_update_agent::{
-    component, component::Component, dbus, update, update_component_version_on_disk,
-    Args, Settings,
+    component,
+    component::{Component, DownloadOptions},
+    dbus,
+    peer_mirror::PeerMirror,
+    plan::{Plan, PlanFormat},
+    progress::{self, Phase, PROGRESS},
+    update, update_component_version_on_disk, Args, Settings,
 };
 use orb_update_agent_core::{
-    version_map::SlotVersion, Claim, Slot, VersionMap, Versions,
+    components, version_map::SlotVersion, Claim, ManifestComponent, Slot, Source,
+    VersionMap, Versions,
 };
 use orb_zbus_proxies::login1;
 use slot_ctrl::EfiVar;
@@ -55,10 +63,13 @@ fn main() -> UpdateAgentResult {
 
     let args = Args::parse();
 
-    match run(&args) {
+    // Owned here so that a failure of `run` is still published before it shuts down.
+    let mut progress_server = None;
+    match run(&args, &mut progress_server) {
         Ok(_) => UpdateAgentResult::Success,
         Err(err) => {
             error!("{err:?}");
+            PROGRESS.fail(&err);
             err.into()
         }
     }
@@ -77,7 +88,10 @@ fn get_config_source(args: &Args) -> Cow<'_, Path> {
     }
 }
 
-fn run(args: &Args) -> eyre::Result<()> {
+fn run(
+    args: &Args,
+    progress_server: &mut Option<progress::Server>,
+) -> eyre::Result<()> {
     // TODO: In the event of a corrupt EFIVAR slot, we would be put into an unrecoverable state
     let active_slot =
         slot_ctrl::get_current_slot().wrap_err("failed getting current slot")?;
@@ -99,8 +113,23 @@ fn run(args: &Args) -> eyre::Result<()> {
 
     prepare_environment(&settings).wrap_err("failed preparing environment to run")?;
 
-    let supervisor_proxy = if settings.nodbus || settings.recovery {
-        debug!("nodbus flag set or in recovery; not connecting to dbus");
+    *progress_server = if settings.nodbus || settings.recovery || args.plan.is_some() {
+        None
+    } else {
+        match progress::serve() {
+            Ok(server) => Some(server),
+            Err(e) => {
+                warn!("failed serving update progress over dbus; continuing anyway: {e:?}");
+                None
+            }
+        }
+    };
+
+    let supervisor_proxy = if settings.nodbus
+        || settings.recovery
+        || args.plan.is_some()
+    {
+        debug!("nodbus flag set, in recovery, or planning; not connecting to dbus");
         None
     } else {
         match zbus::blocking::Connection::session()
@@ -183,6 +212,7 @@ fn run(args: &Args) -> eyre::Result<()> {
         }
     }
 
+    PROGRESS.start_phase(Phase::FetchingClaim, 0);
     let claim = orb_update_agent::claim::get(&settings, &version_map)
         .wrap_err("unable to get update claim")?;
 
@@ -202,20 +232,81 @@ fn run(args: &Args) -> eyre::Result<()> {
             .wrap_err("failed validating update claim against on-disk versions")?;
     }
 
+    if let Some(format) = args.plan {
+        info!("plan was requested; printing planned update and bailing");
+        let plan = Plan::new(
+            &claim,
+            &version_map,
+            settings.active_slot,
+            settings.recovery,
+            settings.streaming_install && !settings.noupdate,
+        );
+        match format {
+            PlanFormat::Table => print!("{plan}"),
+            PlanFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&plan)
+                    .wrap_err("failed serializing plan")?
+            ),
+        }
+        return Ok(());
+    }
+
+    let streamed_components = if settings.streaming_install && !settings.noupdate {
+        find_streamed_components(&claim, settings.recovery)
+    } else {
+        HashSet::new()
+    };
+    if !streamed_components.is_empty() {
+        info!(
+            "streaming install requested; these components will be written to the target slot \
+             while downloading: `{streamed_components:?}`"
+        );
+    }
+    let peer_mirror = match (&settings.peer_mirror, &settings.peer_mirror_cert) {
+        (Some(url), Some(cert)) => match PeerMirror::new(url.clone(), cert) {
+            Ok(peer_mirror) => Some(peer_mirror),
+            Err(e) => {
+                warn!(
+                    "failed setting up peer mirror at {url}; downloading from original \
+                     locations only: {e:?}"
+                );
+                None
+            }
+        },
+        (Some(url), None) => {
+            warn!("peer mirror at {url} set without a certificate to pin; ignoring it");
+            None
+        }
+        (None, _) => None,
+    };
+    let download_options =
+        DownloadOptions::from_settings(&settings, peer_mirror.as_ref());
+
     info!("cleanup old updates");
     cleanup_old_updates(&settings.downloads, &claim)
         .wrap_err("failed to cleaning up old updates")?;
     info!("check if free space is enough for new update");
-    check_for_available_space(&settings.downloads, &claim)
+    check_for_available_space(&settings.downloads, &claim, &streamed_components)
         .wrap_err("failed to check for free space")?;
 
     info!("fetching and validating components listed in manifest");
+    let download_size = claim
+        .iter_components_with_location()
+        .filter(|(component, source)| {
+            source.is_remote() && !streamed_components.contains(component.name())
+        })
+        .map(|(_, source)| source.size)
+        .sum();
+    PROGRESS.start_phase(Phase::Downloading, download_size);
     let update_components = fetch_update_components(
         &claim,
         &settings.workspace,
         &settings.downloads,
+        settings.active_slot,
         supervisor_proxy.as_ref(),
-        settings.download_delay,
+        download_options,
+        &streamed_components,
     )
     .wrap_err("failed fetching update components")?;
 
@@ -226,6 +317,7 @@ fn run(args: &Args) -> eyre::Result<()> {
     debug!("active slot: {}", settings.active_slot);
     debug!("target slot: {}", target_slot);
 
+    PROGRESS.start_phase(Phase::AwaitingPermission, 0);
     if settings.nodbus || settings.recovery {
         debug!(
             "nodbus option set or in recovery mode; not requesting update permission and \
@@ -248,8 +340,22 @@ fn run(args: &Args) -> eyre::Result<()> {
         format!("failed to set the rootfs status for the target slot {target_slot}")
     })?;
 
+    let install_size = claim
+        .manifest()
+        .components()
+        .iter()
+        .filter(|component| {
+            component::is_installed_in(
+                component.installation_phase(),
+                settings.recovery,
+            )
+        })
+        .map(|component| component.size)
+        .sum();
+    PROGRESS.start_phase(Phase::Installing, install_size);
     for component in &update_components {
         info!("running update for component `{}`", component.name());
+        PROGRESS.set_component(component.name());
         component
             .run_update(target_slot, &claim, settings.recovery)
             .wrap_err_with(|| {
@@ -261,7 +367,8 @@ fn run(args: &Args) -> eyre::Result<()> {
 
         update_component_version_on_disk(
             target_slot,
-            component,
+            component.manifest_component(),
+            component.system_component(),
             &mut version_map,
             &version_map_dst,
         )
@@ -274,10 +381,57 @@ fn run(args: &Args) -> eyre::Result<()> {
         })?;
     }
 
+    for (manifest_component, source) in claim
+        .iter_components_with_location()
+        .filter(|(component, _)| streamed_components.contains(component.name()))
+    {
+        info!(
+            "running streaming update for component `{}`",
+            manifest_component.name()
+        );
+        PROGRESS.set_component(manifest_component.name());
+        let system_component = &claim.system_components()[manifest_component.name()];
+        stream_component(
+            manifest_component,
+            system_component,
+            source,
+            target_slot,
+            supervisor_proxy.as_ref(),
+            download_options,
+        )
+        .wrap_err_with(|| {
+            format!(
+                "failed executing streaming update for component `{}`",
+                manifest_component.name()
+            )
+        })?;
+
+        update_component_version_on_disk(
+            target_slot,
+            manifest_component,
+            system_component,
+            &mut version_map,
+            &version_map_dst,
+        )
+        .wrap_err_with(|| {
+            format!(
+                "failed updating version for component `{}` at `{}`",
+                manifest_component.name(),
+                version_map_dst.display(),
+            )
+        })?;
+    }
+
     if claim.manifest().is_normal_update() && !settings.recovery {
+        let updated_components: Vec<&str> = claim
+            .manifest()
+            .components()
+            .iter()
+            .map(|component| component.name())
+            .collect();
         update::gpt::copy_not_updated_redundant_components(
             &claim,
-            &update_components,
+            &updated_components,
             settings.active_slot,
             &mut version_map,
             &version_map_dst,
@@ -286,6 +440,7 @@ fn run(args: &Args) -> eyre::Result<()> {
     }
 
     info!("Executing post update logic");
+    PROGRESS.start_phase(Phase::Finalizing, 0);
     finalize(&settings, &claim, version_map, version_map_dst)
         .wrap_err("failed to finalize update")
 }
@@ -353,33 +508,97 @@ pub fn validate_claim(
     Ok(())
 }
 
+/// Names of the components that are written to the target slot while they are downloaded.
+fn find_streamed_components(claim: &Claim, recovery: bool) -> HashSet<&str> {
+    claim
+        .iter_components_with_location()
+        .filter(|(component, source)| {
+            component::is_streamable(
+                component,
+                &claim.system_components()[component.name()],
+                source,
+                recovery,
+            )
+        })
+        .map(|(component, _)| component.name())
+        .collect()
+}
+
+/// Streams a component to `target_slot`, marking the slot unbootable if streaming failed after
+/// writing to it or if what was written does not match the manifest.
+fn stream_component(
+    manifest_component: &ManifestComponent,
+    system_component: &components::Component,
+    source: &Source,
+    target_slot: Slot,
+    supervisor_proxy: Option<&dbus::SupervisorProxyBlocking<'static>>,
+    download_options: DownloadOptions<'_>,
+) -> eyre::Result<()> {
+    let result = component::stream_install(
+        manifest_component,
+        system_component,
+        source,
+        target_slot,
+        supervisor_proxy,
+        download_options,
+    );
+    if let Err(e) = &result {
+        if let Some(
+            component::Error::InstalledHashMismatch { .. }
+            | component::Error::StreamInterrupted { .. },
+        ) = e.downcast_ref()
+        {
+            error!(
+                "streaming component `{}` left slot {target_slot} in an unknown state; \
+                 marking it as unbootable",
+                manifest_component.name()
+            );
+            if let Err(e) = slot_ctrl::set_rootfs_status(
+                slot_ctrl::RootFsStatus::Unbootable,
+                target_slot.into(),
+            ) {
+                error!("failed to mark slot {target_slot} as unbootable: {e:?}");
+            }
+        }
+    }
+    result
+}
+
 fn fetch_update_components(
     claim: &Claim,
     manifest_dst: &Path,
     dst: &Path,
+    active_slot: Slot,
     supervisor_proxy: Option<&dbus::SupervisorProxyBlocking<'static>>,
-    download_delay: Duration,
+    download_options: DownloadOptions<'_>,
+    streamed_components: &HashSet<&str>,
 ) -> eyre::Result<Vec<Component>> {
     orb_update_agent::manifest::compare_to_disk(claim.manifest(), manifest_dst)?;
     let mut components = Vec::with_capacity(claim.num_components());
-    for (component, source) in claim.iter_components_with_location() {
+    for (component, source) in claim
+        .iter_components_with_location()
+        .filter(|(component, _)| !streamed_components.contains(component.name()))
+    {
+        PROGRESS.set_component(component.name());
         let component = component::fetch(
             component,
             &claim.system_components()[component.name()],
             source,
             dst,
             supervisor_proxy,
-            download_delay,
+            download_options,
         )
         .wrap_err_with(|| {
             format!("failed fetching source for component `{}`", source.name)
         })?;
         components.push(component);
     }
+    PROGRESS.start_phase(Phase::Processing, 0);
     components
         .iter_mut()
         .try_for_each(|comp| {
-            comp.process(dst).wrap_err_with(|| {
+            PROGRESS.set_component(comp.name());
+            comp.process(dst, active_slot).wrap_err_with(|| {
                 format!(
                     "failed to process update file for component `{}`",
                     comp.name(),
@@ -415,6 +634,7 @@ fn cleanup_old_updates(dst: &Path, claim: &Claim) -> eyre::Result<()> {
                 // TODO(andronat): I would like to have a "safer" way to create this names. What if
                 // we change these conventions later one?
                 format!("{}.{}", s.unique_name(), "verified"),
+                format!("{}.{}", s.unique_name(), "journal"),
                 format!("{}.{}", s.unique_name(), "uncompressed"),
                 format!("{}.{}", s.unique_name(), "uncompressed.verified"),
             ]
@@ -440,6 +660,7 @@ fn cleanup_old_updates(dst: &Path, claim: &Claim) -> eyre::Result<()> {
 fn check_for_available_space<P: AsRef<Path>>(
     dst: &P,
     claim: &Claim,
+    streamed_components: &HashSet<&str>,
 ) -> eyre::Result<()> {
     let stats = match statvfs::statvfs(dst.as_ref()) {
         Ok(stats) => stats,
@@ -484,6 +705,7 @@ fn check_for_available_space<P: AsRef<Path>>(
                 // TODO(andronat): I would like to have a "safer" way to create this names. What if
                 // we change these conventions later one?
                 format!("{}.{}", s.unique_name(), "verified"),
+                format!("{}.{}", s.unique_name(), "journal"),
                 format!("{}.{}", s.unique_name(), "uncompressed"),
                 format!("{}.{}", s.unique_name(), "uncompressed.verified"),
             ]
@@ -507,12 +729,19 @@ fn check_for_available_space<P: AsRef<Path>>(
             acc + size
         });
 
-    if available_space < (claim.full_update_size() - existing_claim_entries_size) {
+    // Streamed components are never staged in `dst`.
+    let streamed_size: u64 = claim
+        .iter_components_with_location()
+        .filter(|(component, _)| streamed_components.contains(component.name()))
+        .map(|(component, source)| component.size + source.size)
+        .sum();
+    let required_space = claim.full_update_size() - streamed_size;
+    if available_space < required_space.saturating_sub(existing_claim_entries_size) {
         warn!(
             "not enough space on disk at `{}`; available space: {}, required space: {}",
             dst.as_ref().display(),
             available_space,
-            claim.full_update_size(),
+            required_space,
         );
         bail!(
             "something is very wrong here. We can't continue. There is not enough space on disk!"
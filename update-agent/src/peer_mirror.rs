@@ -0,0 +1,102 @@
+//! Fetching components from a peer orb on the local network.
+//!
+//! A peer mirror is any HTTPS server that serves the download directory of an orb which already
+//! fetched a release, so that components are found under their [`Source::unique_name`]. Peers
+//! have no publicly trusted certificate, so the mirror's certificate is pinned instead of being
+//! validated against the system's root CAs. Components fetched from a mirror are checked against
+//! the hashes in the signed claim like any other download.
+use std::path::Path;
+
+use orb_update_agent_core::Source;
+use reqwest::{blocking::Client, Url};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("peer mirror must be served over HTTPS; URL provided: `{0}`")]
+    NotHttps(Url),
+    #[error("failed initializing client for peer mirror")]
+    InitClient(#[from] crate::client::Error),
+    #[error("failed joining `{name}` onto peer mirror URL `{base}`")]
+    Join {
+        base: Url,
+        name: String,
+        source: url::ParseError,
+    },
+}
+
+#[derive(Debug)]
+pub struct PeerMirror {
+    base: Url,
+    client: Client,
+}
+
+impl PeerMirror {
+    /// Creates a mirror rooted at `base`, trusting only the PEM encoded `certificate`.
+    pub fn new(base: Url, certificate: &Path) -> Result<Self, Error> {
+        Ok(Self {
+            base: normalize_base(base)?,
+            client: crate::client::pinned(certificate)?,
+        })
+    }
+
+    pub fn base(&self) -> &Url {
+        &self.base
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// The URL under which the mirror serves `source`.
+    pub fn url_for(&self, source: &Source) -> Result<Url, Error> {
+        join(&self.base, &source.unique_name())
+    }
+}
+
+/// Ensures `base` is HTTPS and ends in a slash, so that joining a file name appends to its path
+/// instead of replacing the last segment.
+fn normalize_base(mut base: Url) -> Result<Url, Error> {
+    if base.scheme() != "https" {
+        return Err(Error::NotHttps(base));
+    }
+    if !base.path().ends_with('/') {
+        let path = format!("{}/", base.path());
+        base.set_path(&path);
+    }
+    Ok(base)
+}
+
+fn join(base: &Url, name: &str) -> Result<Url, Error> {
+    base.join(name).map_err(|source| Error::Join {
+        base: base.clone(),
+        name: name.to_string(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+
+    use super::{join, normalize_base, Error};
+
+    #[test]
+    fn components_are_served_below_base() {
+        for base in [
+            "https://10.0.0.2:8443/downloads",
+            "https://10.0.0.2:8443/downloads/",
+        ] {
+            let base = normalize_base(Url::parse(base).unwrap()).unwrap();
+            assert_eq!(
+                join(&base, "rootfs-abcd").unwrap().as_str(),
+                "https://10.0.0.2:8443/downloads/rootfs-abcd"
+            );
+        }
+    }
+
+    #[test]
+    fn plain_http_is_rejected() {
+        let base = Url::parse("http://10.0.0.2/downloads").unwrap();
+        assert!(matches!(normalize_base(base), Err(Error::NotHttps(_))));
+    }
+}
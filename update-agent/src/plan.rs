@@ -0,0 +1,622 @@
+//! Dry run of an update.
+//!
+//! A [`Plan`] resolves every component of a claim against the system components and records what
+//! the agent would write where, without opening any device.
+use std::fmt::{self, Display};
+
+use orb_update_agent_core::{
+    components::{self, Component},
+    manifest::UpdateKind,
+    version_map::SlotVersion,
+    Claim, MimeType, Slot, VersionMap,
+};
+use serde::Serialize;
+
+use crate::{component, update::gpt::find_not_updated_redundant_gpt_components};
+
+/// The format in which a [`Plan`] is printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PlanFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Plan {
+    pub version: String,
+    pub kind: UpdateKind,
+    pub active_slot: Slot,
+    pub target_slot: Slot,
+    pub recovery: bool,
+    pub components: Vec<PlannedComponent>,
+    pub redundant_copies: Vec<RedundantCopy>,
+    pub version_map: VersionMap,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlannedComponent {
+    pub name: String,
+    pub version: String,
+    /// Whether the component is written in the current installation phase.
+    pub install: bool,
+    /// Whether the component is written to the target while it is downloaded.
+    pub streamed: bool,
+    pub target: Target,
+    pub mime_type: MimeType,
+    pub source_size: u64,
+    pub size: u64,
+}
+
+/// Where a component is written to.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Target {
+    Gpt {
+        device: String,
+        partition: String,
+    },
+    Raw {
+        device: String,
+        offset: u64,
+        size: u64,
+    },
+    Can {
+        bus: String,
+        address: u32,
+    },
+    Capsule,
+}
+
+/// A redundant GPT partition that is not part of the manifest and is copied from the active to
+/// the target slot.
+#[derive(Debug, Serialize)]
+pub struct RedundantCopy {
+    pub name: String,
+    pub device: String,
+    pub from_partition: String,
+    pub to_partition: String,
+}
+
+impl Target {
+    fn new(component: &Component, slot: Slot) -> Self {
+        match component {
+            Component::Gpt(gpt) => Self::Gpt {
+                device: gpt.device.to_string(),
+                partition: gpt.get_partition_name(slot),
+            },
+            Component::Raw(raw) => Self::Raw {
+                device: raw.device.to_string(),
+                offset: raw.get_offset(slot),
+                size: raw.size,
+            },
+            Component::Can(can) => Self::Can {
+                bus: can.bus.clone(),
+                address: can.address,
+            },
+            Component::Capsule(_) => Self::Capsule,
+        }
+    }
+}
+
+impl Plan {
+    /// Plans the update described by `claim` from `active_slot`, starting from `version_map`.
+    ///
+    /// Mirrors the decisions `main` takes when running the update: components are skipped if they
+    /// don't belong to the installation phase selected by `recovery`, redundant GPT components
+    /// missing from a normal update are copied, and the release version is recorded as in
+    /// `finalize`.
+    pub fn new(
+        claim: &Claim,
+        version_map: &VersionMap,
+        active_slot: Slot,
+        recovery: bool,
+        streaming_install: bool,
+    ) -> Self {
+        let target_slot = active_slot.opposite();
+        let mut version_map = version_map.clone();
+
+        let components: Vec<_> = claim
+            .iter_components_with_location()
+            .map(|(manifest_component, source)| {
+                let system_component =
+                    &claim.system_components()[manifest_component.name()];
+                let install = component::is_installed_in(
+                    manifest_component.installation_phase(),
+                    recovery,
+                );
+                if install {
+                    version_map.set_component(
+                        target_slot,
+                        manifest_component,
+                        system_component,
+                    );
+                }
+                PlannedComponent {
+                    name: manifest_component.name().to_string(),
+                    version: manifest_component.version_upgrade().to_string(),
+                    install,
+                    streamed: streaming_install
+                        && component::is_streamable(
+                            manifest_component,
+                            system_component,
+                            source,
+                            recovery,
+                        ),
+                    target: Target::new(system_component, target_slot),
+                    mime_type: source.mime_type.clone(),
+                    source_size: source.size,
+                    size: manifest_component.size,
+                }
+            })
+            .collect();
+
+        let mut redundant_copies = Vec::new();
+        if claim.manifest().is_normal_update() && !recovery {
+            let updated_components: Vec<&str> =
+                components.iter().map(|c| c.name.as_str()).collect();
+            for (name, gpt) in find_not_updated_redundant_gpt_components(
+                claim.system_components(),
+                &updated_components,
+            ) {
+                redundant_copies.push(RedundantCopy::new(name, gpt, active_slot));
+                version_map.mirror_redundant_component_version(name, active_slot);
+            }
+            redundant_copies.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+
+        match claim.manifest().kind() {
+            UpdateKind::Full => version_map.set_recovery_version(claim.version()),
+            UpdateKind::Normal => {
+                version_map.set_slot_version(claim.version(), target_slot)
+            }
+        }
+
+        Self {
+            version: claim.version().to_string(),
+            kind: claim.manifest().kind(),
+            active_slot,
+            target_slot,
+            recovery,
+            components,
+            redundant_copies,
+            version_map,
+        }
+    }
+}
+
+impl RedundantCopy {
+    fn new(name: &str, gpt: &components::Gpt, active_slot: Slot) -> Self {
+        Self {
+            name: name.to_string(),
+            device: gpt.device.to_string(),
+            from_partition: gpt.get_partition_name(active_slot),
+            to_partition: gpt.get_partition_name(active_slot.opposite()),
+        }
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gpt { device, partition } => write!(f, "gpt {device} {partition}"),
+            Self::Raw {
+                device,
+                offset,
+                size,
+            } => write!(f, "raw {device} @ {offset:#x} (+{size:#x})"),
+            Self::Can { bus, address } => write!(f, "can {bus} {address:#x}"),
+            Self::Capsule => f.write_str("capsule"),
+        }
+    }
+}
+
+/// Writes `rows` as left aligned columns separated by two spaces.
+fn write_table<const N: usize>(
+    f: &mut fmt::Formatter<'_>,
+    header: [&str; N],
+    rows: &[[String; N]],
+) -> fmt::Result {
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let header = header.map(str::to_string);
+    for row in std::iter::once(&header).chain(rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(f, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+fn format_slot_version(version: &SlotVersion) -> [String; 2] {
+    match version {
+        SlotVersion::Single { version } => [version.clone(), version.clone()],
+        SlotVersion::Redundant {
+            version_a,
+            version_b,
+        } => [version_a, version_b].map(|v| v.clone().unwrap_or_else(|| "-".into())),
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            UpdateKind::Full => "full",
+            UpdateKind::Normal => "normal",
+        };
+        writeln!(
+            f,
+            "{kind} update to `{}` from slot {} to slot {}{}",
+            self.version,
+            self.active_slot,
+            self.target_slot,
+            if self.recovery { " (recovery)" } else { "" },
+        )?;
+        writeln!(f)?;
+
+        let rows: Vec<_> = self
+            .components
+            .iter()
+            .map(|c| {
+                let action = match (c.install, c.streamed) {
+                    (false, _) => "skip",
+                    (true, false) => "install",
+                    (true, true) => "stream",
+                };
+                [
+                    c.name.clone(),
+                    c.version.clone(),
+                    action.to_string(),
+                    c.target.to_string(),
+                    c.source_size.to_string(),
+                    c.size.to_string(),
+                ]
+            })
+            .collect();
+        write_table(
+            f,
+            [
+                "COMPONENT",
+                "VERSION",
+                "ACTION",
+                "TARGET",
+                "SOURCE SIZE",
+                "SIZE",
+            ],
+            &rows,
+        )?;
+
+        if !self.redundant_copies.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "redundant GPT partitions copied from the active slot:")?;
+            let rows: Vec<_> = self
+                .redundant_copies
+                .iter()
+                .map(|c| {
+                    [
+                        c.name.clone(),
+                        c.device.clone(),
+                        c.from_partition.clone(),
+                        c.to_partition.clone(),
+                    ]
+                })
+                .collect();
+            write_table(f, ["COMPONENT", "DEVICE", "FROM", "TO"], &rows)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "resulting versions:")?;
+        let mut rows: Vec<_> = self
+            .version_map
+            .components()
+            .map(|(name, version)| {
+                let [a, b] = format_slot_version(version);
+                [name.to_string(), a, b]
+            })
+            .collect();
+        rows.sort();
+        let [release_a, release_b] =
+            [self.version_map.get_slot_a(), self.version_map.get_slot_b()]
+                .map(|v| v.unwrap_or("-").to_string());
+        rows.insert(0, ["release".to_string(), release_a, release_b]);
+        write_table(f, ["COMPONENT", "SLOT A", "SLOT B"], &rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use base64::Engine as _;
+    use ed25519_dalek::{Signer as _, SigningKey};
+    use orb_update_agent_core::{
+        manifest::{InstallationPhase, UpdateKind},
+        Claim, LocalOrRemote, Manifest, ManifestComponent, MimeType, Slot, Source,
+        VersionMap,
+    };
+    use serde_json::json;
+
+    use super::{Plan, Target};
+
+    fn manifest_component(
+        name: &str,
+        version: &str,
+        installation_phase: InstallationPhase,
+    ) -> ManifestComponent {
+        ManifestComponent {
+            name: name.to_owned(),
+            version_assert: "1.0.0".to_owned(),
+            version_upgrade: version.to_owned(),
+            size: 4096,
+            hash: "00".repeat(32),
+            installation_phase,
+        }
+    }
+
+    fn source(name: &str, mime_type: MimeType) -> Source {
+        Source {
+            hash: "00".repeat(32),
+            mime_type,
+            name: name.to_owned(),
+            size: 1024,
+            url: LocalOrRemote::Remote(
+                format!("https://example.com/{name}").parse().unwrap(),
+            ),
+        }
+    }
+
+    /// A claim for `rootfs`, `bootloader`, `mainboard` and the recovery-only `recovery`,
+    /// leaving the redundant `var` and the single `cuda` partitions untouched.
+    fn claim(kind: UpdateKind) -> Claim {
+        let manifest = Manifest::builder()
+            .magic("some magic")
+            .kind(kind)
+            .components(vec![
+                manifest_component("rootfs", "1.1.0", InstallationPhase::Normal),
+                manifest_component("bootloader", "2.0.0", InstallationPhase::Normal),
+                manifest_component("mainboard", "0.3.0", InstallationPhase::Normal),
+                manifest_component("recovery", "1.1.0", InstallationPhase::Recovery),
+            ])
+            .build()
+            .unwrap();
+        let sources: HashMap<_, _> = [
+            source("rootfs", MimeType::XZ),
+            source("bootloader", MimeType::OctetStream),
+            source("mainboard", MimeType::OctetStream),
+            source("recovery", MimeType::BsdiffXz),
+        ]
+        .into_iter()
+        .map(|source| (source.name.clone(), source))
+        .collect();
+        let system_components = serde_json::from_value(json!({
+            "rootfs": {
+                "type": "gpt",
+                "value": { "redundancy": "redundant", "device": "emmc", "label": "APP" }
+            },
+            "var": {
+                "type": "gpt",
+                "value": { "redundancy": "redundant", "device": "emmc", "label": "VAR" }
+            },
+            "cuda": {
+                "type": "gpt",
+                "value": { "redundancy": "single", "device": "emmc", "label": "CUDA" }
+            },
+            "recovery": {
+                "type": "gpt",
+                "value": { "redundancy": "single", "device": "emmc", "label": "RCV" }
+            },
+            "bootloader": {
+                "type": "raw",
+                "value": {
+                    "redundancy": "redundant",
+                    "device": "qspi",
+                    "offset": 4096,
+                    "size": 8192
+                }
+            },
+            "mainboard": {
+                "type": "can",
+                "value": { "redundancy": "redundant", "bus": "can0", "address": 1 }
+            }
+        }))
+        .unwrap();
+
+        let manifest_raw = serde_json::to_string(&manifest).unwrap();
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let signature = base64::prelude::BASE64_STANDARD
+            .encode(signing_key.sign(manifest_raw.as_bytes()).to_bytes());
+        Claim::builder()
+            .manifest(manifest)
+            .manifest_raw(manifest_raw)
+            .signature(signature)
+            .sources(sources)
+            .system_components(system_components)
+            .version("blissful-bumblebee")
+            .build(&signing_key.verifying_key())
+            .unwrap()
+    }
+
+    fn version_map() -> VersionMap {
+        serde_json::from_value(json!({
+            "releases": {
+                "slot_a": "amarous-albatross",
+                "slot_b": "amarous-albatross",
+                "recovery": null
+            },
+            "components": {
+                "rootfs": {
+                    "Redundant": { "version_a": "1.0.0", "version_b": "1.0.0" }
+                },
+                "var": {
+                    "Redundant": { "version_a": "0.9.0", "version_b": "0.9.0" }
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn normal_update_resolves_targets_on_the_opposite_slot() {
+        let plan = Plan::new(
+            &claim(UpdateKind::Normal),
+            &version_map(),
+            Slot::A,
+            false,
+            true,
+        );
+        assert_eq!(plan.target_slot, Slot::B);
+
+        let components: HashMap<_, _> =
+            plan.components.iter().map(|c| (c.name.as_str(), c)).collect();
+        assert_eq!(components.len(), 4);
+        assert!(matches!(
+            &components["rootfs"].target,
+            Target::Gpt { device, partition }
+                if device == "/dev/mmcblk0" && partition == "APP_b"
+        ));
+        assert!(matches!(
+            components["bootloader"].target,
+            Target::Raw {
+                offset: 12288,
+                size: 8192,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &components["mainboard"].target,
+            Target::Can { bus, address: 1 } if bus == "can0"
+        ));
+        assert!(components["rootfs"].install && components["rootfs"].streamed);
+        assert!(components["bootloader"].install && components["bootloader"].streamed);
+        assert!(components["mainboard"].install && !components["mainboard"].streamed);
+        assert!(!components["recovery"].install && !components["recovery"].streamed);
+
+        // `cuda` is not redundant, so only `var` is copied from the active slot.
+        assert_eq!(plan.redundant_copies.len(), 1);
+        let copy = &plan.redundant_copies[0];
+        assert_eq!(copy.name, "var");
+        assert_eq!(copy.from_partition, "VAR_a");
+        assert_eq!(copy.to_partition, "VAR_b");
+
+        assert_eq!(plan.version_map.get_slot_a(), Some("amarous-albatross"));
+        assert_eq!(plan.version_map.get_slot_b(), Some("blissful-bumblebee"));
+        assert!(plan.version_map.slot_version("recovery").is_none());
+    }
+
+    #[test]
+    fn recovery_and_full_updates_copy_no_redundant_partitions() {
+        let plan = Plan::new(
+            &claim(UpdateKind::Normal),
+            &version_map(),
+            Slot::B,
+            true,
+            false,
+        );
+        assert_eq!(plan.target_slot, Slot::A);
+        assert!(plan.redundant_copies.is_empty());
+        let installed: Vec<_> = plan
+            .components
+            .iter()
+            .filter(|c| c.install)
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(installed, ["recovery"]);
+
+        let plan = Plan::new(
+            &claim(UpdateKind::Full),
+            &version_map(),
+            Slot::A,
+            false,
+            false,
+        );
+        assert!(plan.redundant_copies.is_empty());
+        assert!(plan.components.iter().all(|c| !c.streamed));
+        assert_eq!(plan.version_map.get_slot_b(), Some("amarous-albatross"));
+    }
+
+    #[test]
+    fn plan_is_displayed_as_tables() {
+        let plan = Plan::new(
+            &claim(UpdateKind::Normal),
+            &version_map(),
+            Slot::A,
+            false,
+            false,
+        );
+        assert_eq!(plan.to_string(), TABLE);
+    }
+
+    const TABLE: &str = "\
+normal update to `blissful-bumblebee` from slot a to slot b
+
+COMPONENT   VERSION  ACTION   TARGET                                 SOURCE SIZE  SIZE
+rootfs      1.1.0    install  gpt /dev/mmcblk0 APP_b                 1024         4096
+bootloader  2.0.0    install  raw /dev/mtdblock0 @ 0x3000 (+0x2000)  1024         4096
+mainboard   0.3.0    install  can can0 0x1                           1024         4096
+recovery    1.1.0    skip     gpt /dev/mmcblk0 RCV                   1024         4096
+
+redundant GPT partitions copied from the active slot:
+COMPONENT  DEVICE        FROM   TO
+var        /dev/mmcblk0  VAR_a  VAR_b
+
+resulting versions:
+COMPONENT   SLOT A             SLOT B
+release     amarous-albatross  blissful-bumblebee
+bootloader  -                  2.0.0
+mainboard   -                  0.3.0
+rootfs      1.0.0              1.1.0
+var         0.9.0              0.9.0
+";
+
+    #[test]
+    fn plan_is_serialized_as_json() {
+        let plan = Plan::new(
+            &claim(UpdateKind::Normal),
+            &version_map(),
+            Slot::A,
+            false,
+            true,
+        );
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["version"], "blissful-bumblebee");
+        assert_eq!(json["kind"], "normal");
+        assert_eq!(json["target_slot"], "b");
+        assert_eq!(
+            json["components"][0],
+            json!({
+                "name": "rootfs",
+                "version": "1.1.0",
+                "install": true,
+                "streamed": true,
+                "target": {
+                    "type": "gpt",
+                    "device": "/dev/mmcblk0",
+                    "partition": "APP_b"
+                },
+                "mime_type": "application/x-xz",
+                "source_size": 1024,
+                "size": 4096
+            })
+        );
+        assert_eq!(json["components"][2]["target"]["type"], "can");
+        assert_eq!(
+            json["redundant_copies"],
+            json!([{
+                "name": "var",
+                "device": "/dev/mmcblk0",
+                "from_partition": "VAR_a",
+                "to_partition": "VAR_b"
+            }])
+        );
+        assert_eq!(json["version_map"]["releases"]["slot_b"], "blissful-bumblebee");
+    }
+}
+
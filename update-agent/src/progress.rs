@@ -0,0 +1,360 @@
+//! Progress reporting over the `org.worldcoin.UpdateAgent1` dbus interface.
+//!
+//! The agent records its progress in the global [`PROGRESS`], similar to how metrics are sent
+//! through [`orb_update_agent_core::telemetry::DATADOG`]. Recording is cheap and works whether or
+//! not the interface is served; [`serve`] publishes it on the session bus.
+use std::{
+    future::Future,
+    io::{self, Read, Seek, SeekFrom},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use eyre::{ensure, WrapErr as _};
+use tokio::runtime::{Handle, Runtime};
+use tracing::warn;
+use zbus::{interface, object_server::InterfaceRef, SignalContext};
+
+pub const SERVICE_NAME: &str = "org.worldcoin.UpdateAgent1";
+pub const OBJECT_PATH: &str = "/org/worldcoin/UpdateAgent1";
+
+/// Byte counters are announced at most this often to not flood the bus.
+const COUNTER_NOTIFY_INTERVAL: Duration = Duration::from_secs(1);
+
+pub static PROGRESS: Progress = Progress::new();
+
+/// The steps the agent goes through when running an update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Idle,
+    FetchingClaim,
+    Downloading,
+    Processing,
+    AwaitingPermission,
+    Installing,
+    Finalizing,
+    Failed,
+}
+
+impl Phase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Phase::Idle => "idle",
+            Phase::FetchingClaim => "fetching_claim",
+            Phase::Downloading => "downloading",
+            Phase::Processing => "processing",
+            Phase::AwaitingPermission => "awaiting_permission",
+            Phase::Installing => "installing",
+            Phase::Finalizing => "finalizing",
+            Phase::Failed => "failed",
+        }
+    }
+}
+
+struct Status {
+    phase: Phase,
+    component: String,
+    last_error: String,
+}
+
+pub struct Progress {
+    status: Mutex<Status>,
+    downloaded: AtomicU64,
+    written: AtomicU64,
+    total: AtomicU64,
+    last_counter_notify: Mutex<Option<Instant>>,
+    served: Mutex<Option<Served>>,
+}
+
+/// The served interface and the runtime driving its connection.
+#[derive(Clone)]
+struct Served {
+    runtime: Handle,
+    interface: InterfaceRef<Interface>,
+}
+
+impl Progress {
+    const fn new() -> Self {
+        Self {
+            status: Mutex::new(Status {
+                phase: Phase::Idle,
+                component: String::new(),
+                last_error: String::new(),
+            }),
+            downloaded: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            last_counter_notify: Mutex::new(None),
+            served: Mutex::new(None),
+        }
+    }
+
+    fn served(&self) -> MutexGuard<'_, Option<Served>> {
+        self.served
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn status(&self) -> MutexGuard<'_, Status> {
+        self.status
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Enters `phase`, which is expected to transfer `total_bytes` in total.
+    pub fn start_phase(&self, phase: Phase, total_bytes: u64) {
+        self.total.store(total_bytes, Ordering::Relaxed);
+        self.status().phase = phase;
+        self.notify(|iface_ref| async move {
+            let ctx = iface_ref.signal_context();
+            let iface = iface_ref.get().await;
+            iface.phase_changed(ctx).await?;
+            iface.total_bytes_changed(ctx).await?;
+            Interface::phase_transition(ctx, phase.as_str()).await
+        });
+    }
+
+    pub fn set_component(&self, name: &str) {
+        name.clone_into(&mut self.status().component);
+        self.notify(|iface_ref| async move {
+            let ctx = iface_ref.signal_context();
+            iface_ref.get().await.current_component_changed(ctx).await
+        });
+    }
+
+    pub fn add_downloaded(&self, bytes: u64) {
+        self.downloaded.fetch_add(bytes, Ordering::Relaxed);
+        self.notify_counters();
+    }
+
+    pub fn add_written(&self, bytes: u64) {
+        self.written.fetch_add(bytes, Ordering::Relaxed);
+        self.notify_counters();
+    }
+
+    /// Records `error` and enters [`Phase::Failed`].
+    pub fn fail(&self, error: &eyre::Report) {
+        self.status().last_error = format!("{error:#}");
+        self.notify(|iface_ref| async move {
+            let ctx = iface_ref.signal_context();
+            iface_ref.get().await.last_error_changed(ctx).await
+        });
+        self.start_phase(Phase::Failed, 0);
+    }
+
+    fn notify_counters(&self) {
+        {
+            let mut last = self
+                .last_counter_notify
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if last.is_some_and(|last| last.elapsed() < COUNTER_NOTIFY_INTERVAL) {
+                return;
+            }
+            *last = Some(Instant::now());
+        }
+        self.notify(|iface_ref| async move {
+            let ctx = iface_ref.signal_context();
+            let iface = iface_ref.get().await;
+            iface.bytes_downloaded_changed(ctx).await?;
+            iface.bytes_written_changed(ctx).await
+        });
+    }
+
+    /// Runs the notification `f` to completion if the interface is served.
+    fn notify<F, Fut>(&self, f: F)
+    where
+        F: FnOnce(InterfaceRef<Interface>) -> Fut,
+        Fut: Future<Output = zbus::Result<()>>,
+    {
+        let Some(served) = self.served().clone() else {
+            return;
+        };
+        if let Err(e) = served.runtime.block_on(f(served.interface.clone())) {
+            warn!("failed notifying update progress over dbus: {e:?}");
+        }
+    }
+}
+
+/// Connection serving [`PROGRESS`] on the session bus.
+pub struct Server {
+    _conn: zbus::Connection,
+    _runtime: Runtime,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        // Progress is only recorded from now on, as the runtime is about to shut down.
+        PROGRESS.served().take();
+    }
+}
+
+/// Publishes [`PROGRESS`] on the session bus.
+///
+/// The returned server must be kept alive for as long as the interface should be served.
+pub fn serve() -> eyre::Result<Server> {
+    ensure!(
+        PROGRESS.served().is_none(),
+        "update progress is already served over dbus"
+    );
+    // The agent is synchronous, so the connection gets its own runtime whose worker keeps
+    // answering property requests while the agent is busy.
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("dbus-progress")
+        .enable_all()
+        .build()
+        .wrap_err("failed building the runtime to serve dbus")?;
+    let conn = runtime.block_on(async {
+        zbus::connection::Builder::session()
+            .wrap_err("failed creating a `session` dbus connection builder")?
+            .name(SERVICE_NAME)
+            .wrap_err_with(|| format!("failed requesting dbus name `{SERVICE_NAME}`"))?
+            .serve_at(OBJECT_PATH, Interface)
+            .wrap_err_with(|| format!("failed serving interface at `{OBJECT_PATH}`"))?
+            .build()
+            .await
+            .wrap_err("failed establishing a `session` dbus connection")
+    })?;
+    let interface = runtime
+        .block_on(conn.object_server().interface::<_, Interface>(OBJECT_PATH))
+        .wrap_err("failed retrieving served progress interface")?;
+    *PROGRESS.served() = Some(Served {
+        runtime: runtime.handle().clone(),
+        interface,
+    });
+    Ok(Server {
+        _conn: conn,
+        _runtime: runtime,
+    })
+}
+
+/// The dbus object exposing [`PROGRESS`].
+pub struct Interface;
+
+#[interface(name = "org.worldcoin.UpdateAgent1")]
+impl Interface {
+    #[zbus(property)]
+    fn phase(&self) -> String {
+        PROGRESS.status().phase.as_str().to_string()
+    }
+
+    /// The component that is currently downloaded, processed or installed.
+    #[zbus(property)]
+    fn current_component(&self) -> String {
+        PROGRESS.status().component.clone()
+    }
+
+    #[zbus(property)]
+    fn bytes_downloaded(&self) -> u64 {
+        PROGRESS.downloaded.load(Ordering::Relaxed)
+    }
+
+    #[zbus(property)]
+    fn bytes_written(&self) -> u64 {
+        PROGRESS.written.load(Ordering::Relaxed)
+    }
+
+    /// Bytes to be downloaded or written in the current phase.
+    #[zbus(property)]
+    fn total_bytes(&self) -> u64 {
+        PROGRESS.total.load(Ordering::Relaxed)
+    }
+
+    /// The error that made the last update fail; empty if there was none.
+    #[zbus(property)]
+    fn last_error(&self) -> String {
+        PROGRESS.status().last_error.clone()
+    }
+
+    /// Emitted whenever the agent enters a new phase.
+    #[zbus(signal, name = "PhaseChanged")]
+    async fn phase_transition(ctx: &SignalContext<'_>, phase: &str)
+        -> zbus::Result<()>;
+}
+
+/// Whether a [`Counted`] reader counts towards downloaded or written bytes.
+#[derive(Clone, Copy, Debug)]
+pub enum Counter {
+    Downloaded,
+    Written,
+}
+
+/// Reader that reports every byte read from `inner` to [`PROGRESS`].
+pub struct Counted<R> {
+    inner: R,
+    counter: Counter,
+}
+
+impl<R> Counted<R> {
+    pub fn new(inner: R, counter: Counter) -> Self {
+        Self { inner, counter }
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        match self.counter {
+            Counter::Downloaded => PROGRESS.add_downloaded(n as u64),
+            Counter::Written => PROGRESS.add_written(n as u64),
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Counted<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use eyre::eyre;
+
+    use super::{Phase, Progress};
+
+    #[test]
+    fn phases_and_counters_are_recorded_without_being_served() {
+        let progress = Progress::new();
+        assert_eq!(progress.status().phase, Phase::Idle);
+
+        progress.start_phase(Phase::Downloading, 100);
+        progress.set_component("rootfs");
+        progress.add_downloaded(30);
+        progress.add_downloaded(20);
+        progress.add_written(10);
+        assert_eq!(progress.status().phase, Phase::Downloading);
+        assert_eq!(progress.status().component, "rootfs");
+        assert_eq!(progress.total.load(Ordering::Relaxed), 100);
+        assert_eq!(progress.downloaded.load(Ordering::Relaxed), 50);
+        assert_eq!(progress.written.load(Ordering::Relaxed), 10);
+
+        progress.start_phase(Phase::Installing, 40);
+        assert_eq!(progress.status().phase, Phase::Installing);
+        assert_eq!(progress.total.load(Ordering::Relaxed), 40);
+        assert_eq!(progress.downloaded.load(Ordering::Relaxed), 50);
+    }
+
+    #[test]
+    fn failing_records_the_error_chain() {
+        let progress = Progress::new();
+        progress.start_phase(Phase::Downloading, 100);
+        progress.fail(&eyre!("connection reset").wrap_err("failed fetching rootfs"));
+
+        let status = progress.status();
+        assert_eq!(status.phase, Phase::Failed);
+        assert_eq!(
+            status.last_error,
+            "failed fetching rootfs: connection reset"
+        );
+        drop(status);
+        assert_eq!(progress.total.load(Ordering::Relaxed), 0);
+    }
+}
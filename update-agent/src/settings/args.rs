@@ -88,6 +88,51 @@ pub struct Args {
     #[arg(long)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub download_delay: Option<u64>,
+    /// Number of byte ranges of a component that are downloaded concurrently. Defaults to 4.
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_parallelism: Option<usize>,
+    /// Caps the combined throughput of all concurrently downloaded ranges, in bytes per second.
+    /// Downloads are not capped if unset.
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_bandwidth_limit: Option<u64>,
+    /// Writes remote GPT and raw components to the target slot while they are downloaded,
+    /// decompressing and hashing them on the fly, instead of staging them in the download
+    /// directory first. The target slot is marked unbootable if streaming fails after writing
+    /// to it or if a written component does not match its hash.
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub streaming_install: bool,
+    /// Directory under which removable drives are mounted, for example `/media`. Each drive is
+    /// searched for a signed `orb-update/claim.json`, which is used instead of the update location
+    /// if found. Components listed with relative paths are read from the drive. Claims that would
+    /// install an older version of a component are ignored unless `--skip-version-asserts` is
+    /// given. Requires `--usb-components`.
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usb_mount_root: Option<String>,
+    /// Path to the orb's component definitions, in the format of `components.json`. Claims on
+    /// removable drives are ignored unless it is given, and the devices and partitions they write
+    /// to are taken from it instead of from the claim.
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usb_components: Option<String>,
+    /// HTTPS URL of a peer orb on the local network that serves its download directory. Remote
+    /// components are fetched from the peer first and from their original location if that fails.
+    /// Requires `--peer-mirror-cert`.
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_mirror: Option<String>,
+    /// Path to the PEM certificate that the peer mirror must present or be issued by.
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_mirror_cert: Option<String>,
+    /// Validates the update claim and prints what would be written where, without touching any
+    /// device. Prints tables for review, or JSON for tools if `json` is given.
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "table")]
+    #[serde(skip)]
+    pub plan: Option<crate::plan::PlanFormat>,
     #[clap(long)]
     pub(super) token: Option<String>,
 }
@@ -7,6 +7,7 @@ use figment::providers::Format as _;
 use orb_update_agent_core::{LocalOrRemote, Slot};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DurationMilliSeconds};
+use url::Url;
 
 mod args;
 pub use args::Args;
@@ -45,9 +46,37 @@ pub struct Settings {
     pub recovery: bool,
     #[serde_as(as = "DurationMilliSeconds")]
     pub download_delay: Duration,
+    #[serde(default = "default_download_parallelism")]
+    pub download_parallelism: usize,
+    /// Bytes per second; downloads are not capped if unset.
+    #[serde(default)]
+    pub download_bandwidth_limit: Option<u64>,
+    /// Install remote components while they are downloaded instead of staging them on disk.
+    #[serde(default)]
+    pub streaming_install: bool,
+    /// Directory under which removable drives are mounted. Drives below it are searched for a
+    /// claim, which takes precedence over `update_location`.
+    #[serde(default)]
+    pub usb_mount_root: Option<PathBuf>,
+    /// The orb's component definitions, in the format of `components.json`. Claims on removable
+    /// drives are only used if this is set, and write to the components defined here instead of
+    /// those listed in the claim, which are not covered by the manifest signature.
+    #[serde(default)]
+    pub usb_components: Option<PathBuf>,
+    /// HTTPS URL of a peer orb serving its download directory. Remote components are fetched from
+    /// it first, falling back to their original location.
+    #[serde(default)]
+    pub peer_mirror: Option<Url>,
+    /// PEM certificate that the peer mirror is pinned to.
+    #[serde(default)]
+    pub peer_mirror_cert: Option<PathBuf>,
     pub token: Option<String>,
 }
 
+fn default_download_parallelism() -> usize {
+    4
+}
+
 impl Settings {
     /// Constructs `Settings` from a config file, environment variables, and command line
     /// arguments. Command line arguments always take precedence over environment variables, which
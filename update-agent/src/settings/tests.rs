@@ -8,6 +8,7 @@ use std::{path::Path, time::Duration};
 use clap::Parser as _;
 use figment::Jail;
 use orb_update_agent_core::{file_location::LocalOrRemote, Slot};
+use url::Url;
 
 use crate::settings::{Backend, Settings};
 
@@ -22,7 +23,14 @@ const CFG_FILE_CONTENTS_TRUTHY: &str = r#"
     nodbus = true
     skip_version_asserts = true
     noupdate = true
+    streaming_install = true
+    usb_mount_root = "/config/media"
+    usb_components = "/config/components.json"
+    peer_mirror = "https://config.peer/downloads/"
+    peer_mirror_cert = "/config/peer.pem"
     download_delay = 3000
+    download_parallelism = 2
+    download_bandwidth_limit = 1000000
     recovery = true
 "#;
 
@@ -37,7 +45,14 @@ const CFG_FILE_CONTENTS_FALSY: &str = r#"
     nodbus = false
     skip_version_asserts = false
     noupdate = false
+    streaming_install = false
+    usb_mount_root = "/config/media"
+    usb_components = "/config/components.json"
+    peer_mirror = "https://config.peer/downloads/"
+    peer_mirror_cert = "/config/peer.pem"
     download_delay = 3000
+    download_parallelism = 2
+    download_bandwidth_limit = 1000000
     recovery = false
 "#;
 
@@ -61,7 +76,14 @@ fn set_env(jail: &mut Jail, set_bools_to: bool) {
     jail.set_env("update_agent_skip_version_asserts", bool_str);
     jail.set_env("update_agent_noupdate", bool_str);
     jail.set_env("update_agent_recovery", bool_str);
+    jail.set_env("update_agent_streaming_install", bool_str);
     jail.set_env("update_agent_download_delay", "4000");
+    jail.set_env("update_agent_download_parallelism", "3");
+    jail.set_env("update_agent_download_bandwidth_limit", "2000000");
+    jail.set_env("update_agent_usb_mount_root", "/env/media");
+    jail.set_env("update_agent_usb_components", "/env/components.json");
+    jail.set_env("update_agent_peer_mirror", "https://env.peer/downloads/");
+    jail.set_env("update_agent_peer_mirror_cert", "/env/peer.pem");
 }
 
 #[test]
@@ -79,6 +101,13 @@ fn test_cli_args_override_config_file_and_env_vars() {
         --skip-version-asserts
         --noupdate
         --download-delay 5000
+        --download-parallelism 8
+        --download-bandwidth-limit 3000000
+        --streaming-install
+        --usb-mount-root /args/media
+        --usb-components /args/components.json
+        --peer-mirror https://args.peer/downloads/
+        --peer-mirror-cert /args/peer.pem
         --recovery
     "#;
 
@@ -102,6 +131,13 @@ fn test_cli_args_override_config_file_and_env_vars() {
             noupdate,
             recovery,
             download_delay,
+            download_parallelism,
+            download_bandwidth_limit,
+            streaming_install,
+            usb_mount_root,
+            usb_components,
+            peer_mirror,
+            peer_mirror_cert,
             token,
         } = Settings::get(&args, "config.toml", "update_agent_", current_slot)?;
         assert_eq!(active_slot, current_slot);
@@ -126,6 +162,25 @@ fn test_cli_args_override_config_file_and_env_vars() {
             download_delay.as_millis(),
             args.download_delay.map(u128::from).unwrap()
         );
+        assert_eq!(download_parallelism, args.download_parallelism.unwrap());
+        assert_eq!(download_bandwidth_limit, args.download_bandwidth_limit);
+        assert_eq!(streaming_install, args.streaming_install);
+        assert_eq!(
+            usb_mount_root.as_deref(),
+            args.usb_mount_root.as_deref().map(Path::new)
+        );
+        assert_eq!(
+            usb_components.as_deref(),
+            args.usb_components.as_deref().map(Path::new)
+        );
+        assert_eq!(
+            peer_mirror.as_ref().map(Url::as_str),
+            args.peer_mirror.as_deref()
+        );
+        assert_eq!(
+            peer_mirror_cert.as_deref(),
+            args.peer_mirror_cert.as_deref().map(Path::new)
+        );
         assert_eq!(token, args.token);
         Ok(())
     })
@@ -146,6 +201,13 @@ fn test_cli_args_override_config_file() {
         --skip-version-asserts
         --noupdate
         --download-delay 5000
+        --download-parallelism 8
+        --download-bandwidth-limit 3000000
+        --streaming-install
+        --usb-mount-root /args/media
+        --usb-components /args/components.json
+        --peer-mirror https://args.peer/downloads/
+        --peer-mirror-cert /args/peer.pem
         --recovery
     "#;
 
@@ -168,6 +230,13 @@ fn test_cli_args_override_config_file() {
             noupdate,
             recovery,
             download_delay,
+            download_parallelism,
+            download_bandwidth_limit,
+            streaming_install,
+            usb_mount_root,
+            usb_components,
+            peer_mirror,
+            peer_mirror_cert,
             token,
         } = Settings::get(&args, "config.toml", "update_agent_", current_slot)?;
         assert_eq!(active_slot, current_slot);
@@ -192,6 +261,25 @@ fn test_cli_args_override_config_file() {
             download_delay.as_millis(),
             args.download_delay.map(u128::from).unwrap()
         );
+        assert_eq!(download_parallelism, args.download_parallelism.unwrap());
+        assert_eq!(download_bandwidth_limit, args.download_bandwidth_limit);
+        assert_eq!(streaming_install, args.streaming_install);
+        assert_eq!(
+            usb_mount_root.as_deref(),
+            args.usb_mount_root.as_deref().map(Path::new)
+        );
+        assert_eq!(
+            usb_components.as_deref(),
+            args.usb_components.as_deref().map(Path::new)
+        );
+        assert_eq!(
+            peer_mirror.as_ref().map(Url::as_str),
+            args.peer_mirror.as_deref()
+        );
+        assert_eq!(
+            peer_mirror_cert.as_deref(),
+            args.peer_mirror_cert.as_deref().map(Path::new)
+        );
         assert_eq!(token, args.token);
         Ok(())
     })
@@ -216,6 +304,13 @@ fn test_only_setting_config_file_works() {
             noupdate,
             recovery,
             download_delay,
+            download_parallelism,
+            download_bandwidth_limit,
+            streaming_install,
+            usb_mount_root,
+            usb_components,
+            peer_mirror,
+            peer_mirror_cert,
             token,
         } = Settings::get(&args, "config.toml", "update_agent_", Slot::A)?;
         assert_eq!(active_slot, Slot::A);
@@ -234,6 +329,22 @@ fn test_only_setting_config_file_works() {
         assert!(noupdate);
         assert!(recovery);
         assert_eq!(download_delay, Duration::from_millis(3000));
+        assert_eq!(download_parallelism, 2);
+        assert_eq!(download_bandwidth_limit, Some(1000000));
+        assert!(streaming_install);
+        assert_eq!(usb_mount_root.as_deref(), Some(Path::new("/config/media")));
+        assert_eq!(
+            usb_components.as_deref(),
+            Some(Path::new("/config/components.json"))
+        );
+        assert_eq!(
+            peer_mirror.as_ref().map(Url::as_str),
+            Some("https://config.peer/downloads/")
+        );
+        assert_eq!(
+            peer_mirror_cert.as_deref(),
+            Some(Path::new("/config/peer.pem"))
+        );
         assert!(token.is_none());
         Ok(())
     })
@@ -260,6 +371,13 @@ fn test_env_override_config_file() {
             noupdate,
             recovery,
             download_delay,
+            download_parallelism,
+            download_bandwidth_limit,
+            streaming_install,
+            usb_mount_root,
+            usb_components,
+            peer_mirror,
+            peer_mirror_cert,
             token,
         } = Settings::get(&args, "config.toml", "update_agent_", current_slot)?;
         assert_eq!(active_slot, current_slot);
@@ -278,6 +396,22 @@ fn test_env_override_config_file() {
         assert!(noupdate);
         assert!(recovery);
         assert_eq!(download_delay, Duration::from_millis(4000));
+        assert_eq!(download_parallelism, 3);
+        assert_eq!(download_bandwidth_limit, Some(2000000));
+        assert!(streaming_install);
+        assert_eq!(usb_mount_root.as_deref(), Some(Path::new("/env/media")));
+        assert_eq!(
+            usb_components.as_deref(),
+            Some(Path::new("/env/components.json"))
+        );
+        assert_eq!(
+            peer_mirror.as_ref().map(Url::as_str),
+            Some("https://env.peer/downloads/")
+        );
+        assert_eq!(
+            peer_mirror_cert.as_deref(),
+            Some(Path::new("/env/peer.pem"))
+        );
         assert!(token.is_none());
         Ok(())
     })
@@ -324,6 +458,13 @@ fn production_config() {
             noupdate,
             recovery,
             download_delay,
+            download_parallelism,
+            download_bandwidth_limit,
+            streaming_install,
+            usb_mount_root,
+            usb_components,
+            peer_mirror,
+            peer_mirror_cert,
             token,
         } = Settings::get(&args, "config.toml", "update_agent_", Slot::A)?;
         assert_eq!(active_slot, Slot::A);
@@ -342,6 +483,13 @@ fn production_config() {
         assert!(!noupdate);
         assert!(!recovery);
         assert_eq!(download_delay, Duration::from_millis(36000));
+        assert_eq!(download_parallelism, 4);
+        assert_eq!(download_bandwidth_limit, None);
+        assert!(!streaming_install);
+        assert!(usb_mount_root.is_none());
+        assert!(usb_components.is_none());
+        assert!(peer_mirror.is_none());
+        assert!(peer_mirror_cert.is_none());
         assert!(token.is_none());
         Ok(())
     })
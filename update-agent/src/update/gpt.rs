@@ -1,4 +1,8 @@
-use std::{fs::File, io, path::Path};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
 
 use eyre::{ensure, WrapErr as _};
 use gpt::disk::LogicalBlockSize::Lb512;
@@ -9,24 +13,22 @@ use orb_update_agent_core::{
 };
 use tracing::{debug, warn};
 
-use super::Update;
-use crate::{
-    component::Component as RuntimeComponent, mount::unmount_partition_by_label,
-};
+use super::{ReadInstalled, ReadSeek, Update, WriteTarget};
+use crate::mount::unmount_partition_by_label;
 
 // Find all redundant GPT components that are listed in `system_components` buit
 // which were not updated as part of the base update
 // specified in the update manifest
-fn find_not_updated_redundant_gpt_components<'a: 'c, 'b: 'c, 'c>(
+pub(crate) fn find_not_updated_redundant_gpt_components<'a: 'c, 'b: 'c, 'c>(
     system_components: &'a Components,
-    update_components: &'b [RuntimeComponent],
+    updated_components: &'b [&'b str],
 ) -> impl Iterator<Item = (&'a String, &'a components::Gpt)> + 'c {
     system_components
         .iter()
         .filter_map(move |(name, component)| match component {
             Component::Gpt(gpt)
                 if gpt.is_redundant()
-                    && !update_components.iter().any(|rc| rc.name() == name) =>
+                    && !updated_components.contains(&name.as_str()) =>
             {
                 Some((name, gpt))
             }
@@ -38,7 +40,7 @@ fn find_not_updated_redundant_gpt_components<'a: 'c, 'b: 'c, 'c>(
 /// by copying them from the currently active.
 pub fn copy_not_updated_redundant_components(
     claim: &Claim,
-    update_components: &[RuntimeComponent],
+    updated_components: &[&str],
     active_slot: Slot,
     version_map: &mut VersionMap,
     version_map_dst: &Path,
@@ -46,7 +48,7 @@ pub fn copy_not_updated_redundant_components(
     let target_slot = active_slot.opposite();
     for (name, gpt_component) in find_not_updated_redundant_gpt_components(
         claim.system_components(),
-        update_components,
+        updated_components,
     ) {
         let disk = gpt_component
             .get_disk()
@@ -81,15 +83,25 @@ pub fn copy_not_updated_redundant_components(
     Ok(())
 }
 
-impl Update for components::Gpt {
-    fn update<R>(&self, slot: Slot, mut src: &mut R) -> eyre::Result<()>
-    where
-        R: io::Read + io::Seek + ?Sized,
-    {
-        DATADOG
-            .incr("orb.update.count.component.gpt", ["status:started"])
-            .or_log();
-        let disk = self.get_disk().expect("failed to open target GPT device");
+impl ReadInstalled for components::Gpt {
+    fn read_installed(&self, slot: Slot) -> eyre::Result<Box<dyn ReadSeek + '_>> {
+        let disk = self
+            .get_disk()
+            .wrap_err("failed to open source GPT device")?;
+        let part = self.get_partition(&disk, slot)?;
+        let part_len = part.bytes_len(Lb512)?;
+        let disk = disk.take_device();
+        Ok(Box::new(gpt::partition::TakePartition::take(
+            part, disk, Lb512, part_len,
+        )))
+    }
+}
+
+impl WriteTarget for components::Gpt {
+    fn open_target(&self, slot: Slot, len: u64) -> eyre::Result<Box<dyn Write + '_>> {
+        let disk = self
+            .get_disk()
+            .wrap_err("failed to open target GPT device")?;
         let part = self.get_partition(&disk, slot)?;
 
         if !self.is_redundant() {
@@ -101,40 +113,53 @@ impl Update for components::Gpt {
             }
         }
 
-        src.seek(std::io::SeekFrom::Start(0))
-            .wrap_err("failed to seek to start of GPT update source")?;
-        let src_len = src
-            .seek(std::io::SeekFrom::End(0))
-            .wrap_err("failed to seek to end of GPT update source")?;
-        src.seek(std::io::SeekFrom::Start(0))
-            .expect("couldn't re-seek to start of GPT update source!");
-
-        debug!("-- preparing to write {:?} bytes", src_len);
-
         let part_len = part.bytes_len(Lb512)?;
         ensure!(
-            src_len <= part_len,
+            len <= part_len,
             "partition {} is too small to write component of size {:?}",
             part_len,
-            src_len,
+            len,
         );
 
+        let part_start = part.bytes_start(Lb512).wrap_err_with(|| {
+            format!(
+                "failed to get GPT partition offset for partition `{}` (assuming 512-byte LB)",
+                self.label
+            )
+        })?;
         let mut disk = disk.take_device();
-        disk.seek(std::io::SeekFrom::Start(
-            part.bytes_start(Lb512).wrap_err_with(|| {
+        disk.seek(io::SeekFrom::Start(part_start))
+            .wrap_err_with(|| {
                 format!(
-                    "failed to get GPT partition offset for partition `{}` (assuming 512-byte LB)",
-                    self.label
-                )
-            })?,
-        ))
-        .wrap_err_with(|| {
-            format!(
                 "failed to seek to partition offset for partition `{}` in device `{}`",
                 self.label, self.device
             )
-        })?;
-        debug!("-- seeking up to offset {:?}", part.bytes_start(Lb512)?);
+            })?;
+        debug!("-- seeking up to offset {:?}", part_start);
+        Ok(Box::new(disk))
+    }
+}
+
+impl Update for components::Gpt {
+    fn update<R>(&self, slot: Slot, mut src: &mut R) -> eyre::Result<()>
+    where
+        R: io::Read + io::Seek + ?Sized,
+    {
+        DATADOG
+            .incr("orb.update.count.component.gpt", ["status:started"])
+            .or_log();
+
+        src.seek(std::io::SeekFrom::Start(0))
+            .wrap_err("failed to seek to start of GPT update source")?;
+        let src_len = src
+            .seek(std::io::SeekFrom::End(0))
+            .wrap_err("failed to seek to end of GPT update source")?;
+        src.seek(std::io::SeekFrom::Start(0))
+            .expect("couldn't re-seek to start of GPT update source!");
+
+        debug!("-- preparing to write {:?} bytes", src_len);
+
+        let mut disk = self.open_target(slot, src_len)?;
 
         // TODO: Possibly use .by_ref().take(src_len)
         std::io::copy(&mut src, &mut disk)
@@ -1,3 +1,6 @@
+use std::io::Write;
+
+use eyre::bail;
 use orb_update_agent_core::{Component, Slot};
 
 pub mod can;
@@ -25,6 +28,52 @@ impl Update for Component {
     }
 }
 
+/// Marker trait for readers that can also seek, so they can be boxed as trait objects.
+pub trait ReadSeek: std::io::Read + std::io::Seek {}
+
+impl<T: std::io::Read + std::io::Seek> ReadSeek for T {}
+
+/// Read access to what is currently installed for a component on a given slot, for example to
+/// use it as the base of a delta update.
+pub trait ReadInstalled {
+    fn read_installed(&self, slot: Slot) -> eyre::Result<Box<dyn ReadSeek + '_>>;
+}
+
+impl ReadInstalled for Component {
+    fn read_installed(&self, slot: Slot) -> eyre::Result<Box<dyn ReadSeek + '_>> {
+        match self {
+            Component::Gpt(c) => c.read_installed(slot),
+            Component::Raw(c) => c.read_installed(slot),
+            Component::Can(_) => bail!("installed CAN components cannot be read back"),
+            Component::Capsule(_) => {
+                bail!("installed capsule components cannot be read back")
+            }
+        }
+    }
+}
+
+/// Direct write access to where a component is installed on a given slot, so that it can be
+/// written while it is still being downloaded.
+pub trait WriteTarget {
+    /// Opens the target for writing `len` bytes from its start.
+    fn open_target(&self, slot: Slot, len: u64) -> eyre::Result<Box<dyn Write + '_>>;
+}
+
+impl WriteTarget for Component {
+    fn open_target(&self, slot: Slot, len: u64) -> eyre::Result<Box<dyn Write + '_>> {
+        match self {
+            Component::Gpt(c) => c.open_target(slot, len),
+            Component::Raw(c) => c.open_target(slot, len),
+            Component::Can(_) => {
+                bail!("CAN components cannot be written while streaming")
+            }
+            Component::Capsule(_) => {
+                bail!("capsule components cannot be written while streaming")
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests;
 
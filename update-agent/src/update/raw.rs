@@ -7,51 +7,42 @@ use orb_update_agent_core::{
 use std::io::{self, Seek as _, Write};
 use tracing::debug;
 
-use super::Update;
+use super::{ReadInstalled, ReadSeek, Update, WriteTarget};
+use crate::util::Window;
 
-impl Update for components::Raw {
-    fn update<R>(&self, slot: Slot, mut src: &mut R) -> eyre::Result<()>
-    where
-        R: io::Read + io::Seek + ?Sized,
-    {
-        DATADOG
-            .incr("orb.update.count.component.raw", ["status:started"])
-            .or_log();
+impl ReadInstalled for components::Raw {
+    fn read_installed(&self, slot: Slot) -> eyre::Result<Box<dyn ReadSeek + '_>> {
+        let block_dev = self.get_file().wrap_err("failed to open source raw file")?;
+        let window = Window::new(block_dev, self.get_offset(slot), self.size)
+            .wrap_err_with(|| {
+                format!(
+                    "failed to seek to offset `{}` for block device `{}`",
+                    self.get_offset(slot),
+                    self.device
+                )
+            })?;
+        Ok(Box::new(window))
+    }
+}
+
+impl WriteTarget for components::Raw {
+    fn open_target(&self, slot: Slot, len: u64) -> eyre::Result<Box<dyn Write + '_>> {
         let mut block_dev =
             self.get_file().wrap_err("failed to open target raw file")?;
 
-        debug!("-- calculating source length");
-
-        src.seek(std::io::SeekFrom::Start(0))?;
-        let src_len = src.seek(std::io::SeekFrom::End(0))?;
-        src.seek(std::io::SeekFrom::Start(0))?;
-
-        debug!("-- updating with source length {:?}", src_len);
         debug!("-- calculating device length");
-
-        block_dev
-            .seek(std::io::SeekFrom::Start(0))
-            .wrap_err("failed to seek to start of raw update source")?;
         let block_dev_len = block_dev
             .seek(std::io::SeekFrom::End(0))
-            .wrap_err("failed to seek to end of raw update source")?;
-        block_dev
-            .seek(std::io::SeekFrom::Start(0))
-            .expect("couldn't re-seek to start of raw update source!");
-
+            .wrap_err("failed to seek to end of raw update target")?;
         debug!("-- updating with device length {:?}", block_dev_len);
 
-        let offset = if slot == Slot::B && self.is_redundant() {
-            self.size + self.offset
-        } else {
-            self.offset
-        };
+        let offset = self.get_offset(slot);
         debug!("-- setting up offset to be {:?}", offset);
 
         ensure!(
-            block_dev_len >= src_len + offset,
+            block_dev_len >= len + offset,
             "block device is too small to write {} bytes starting at offset {}",
-            src_len,
+            len,
             offset,
         );
         debug!("-- device passed length check");
@@ -65,6 +56,28 @@ impl Update for components::Raw {
                 )
             })?;
         debug!("-- seeking up to offset {:?}", offset);
+        Ok(Box::new(block_dev))
+    }
+}
+
+impl Update for components::Raw {
+    fn update<R>(&self, slot: Slot, mut src: &mut R) -> eyre::Result<()>
+    where
+        R: io::Read + io::Seek + ?Sized,
+    {
+        DATADOG
+            .incr("orb.update.count.component.raw", ["status:started"])
+            .or_log();
+
+        debug!("-- calculating source length");
+
+        src.seek(std::io::SeekFrom::Start(0))?;
+        let src_len = src.seek(std::io::SeekFrom::End(0))?;
+        src.seek(std::io::SeekFrom::Start(0))?;
+
+        debug!("-- updating with source length {:?}", src_len);
+
+        let mut block_dev = self.open_target(slot, src_len)?;
 
         std::io::copy(&mut src, &mut block_dev)
             .wrap_err_with(|| {
@@ -1,7 +1,8 @@
 use std::process::{ExitCode, Termination};
 
 use orb_update_agent::component::Error::{
-    self, GetBytes, InitialLengthRequest, RangeRequest, ResponseStatus,
+    self, InitialLengthRequest, RangeRequest, ReadRange, ResponseStatus, ShortRange,
+    StreamRequest, StreamStatus,
 };
 
 /// Exit codes returned by the update agent. Custom exit codes are taken in accordance with the
@@ -27,7 +28,10 @@ impl From<eyre::Report> for UpdateAgentResult {
                 RangeRequest(..)
                 | InitialLengthRequest(..)
                 | ResponseStatus(..)
-                | GetBytes(..),
+                | ReadRange(..)
+                | ShortRange { .. }
+                | StreamRequest(..)
+                | StreamStatus(..),
             ) => DownloadFailed,
             _ => Failure,
         }
@@ -1,8 +1,10 @@
 use core::fmt::Formatter;
 use std::{
     fs::File,
-    io::copy,
+    io::{self, copy, Read, Seek, SeekFrom},
     path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
 use eyre::{bail, WrapErr as _};
@@ -81,6 +83,18 @@ pub struct Range {
     total_size: u64,
 }
 
+impl Range {
+    /// Offset of the first byte in the range.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Number of bytes in the range.
+    pub fn num_bytes(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
 impl Iterator for HttpRangeIter {
     type Item = Range;
 
@@ -113,3 +127,114 @@ impl std::fmt::Display for Range {
         }
     }
 }
+
+/// Caps the combined throughput of everyone sharing it to a fixed number of bytes per second.
+///
+/// Each call to [`RateLimiter::acquire`] reserves the next free time slot long enough to
+/// transfer the requested bytes at the configured rate and sleeps until that slot has passed.
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    next_free: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            next_free: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Blocks the calling thread until `bytes` more bytes may be transferred.
+    pub fn acquire(&self, bytes: u64) {
+        let cost = Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+        let wake_at = {
+            let mut next_free = self
+                .next_free
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            *next_free = std::cmp::max(*next_free, Instant::now()) + cost;
+            *next_free
+        };
+        std::thread::sleep(wake_at.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Reader that passes everything read from `inner` through an optional [`RateLimiter`].
+pub struct Throttled<R> {
+    inner: R,
+    limiter: Option<RateLimiter>,
+}
+
+impl<R> Throttled<R> {
+    /// Caps reads from `inner` at `bytes_per_sec`, or passes them through unchanged if unset.
+    pub fn new(inner: R, bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            inner,
+            limiter: bytes_per_sec.map(RateLimiter::new),
+        }
+    }
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire(n as u64);
+        }
+        Ok(n)
+    }
+}
+
+/// Read-only view of the `len` bytes of `inner` starting at offset `start`.
+///
+/// Seeking is relative to `start`, and reads end at `start + len`.
+pub struct Window<R> {
+    inner: R,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl<R: Seek> Window<R> {
+    pub fn new(mut inner: R, start: u64, len: u64) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            inner,
+            start,
+            len,
+            pos: 0,
+        })
+    }
+}
+
+impl<R: Read> Read for Window<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = std::cmp::min(buf.len() as u64, self.len.saturating_sub(self.pos));
+        if max == 0 {
+            return Ok(0);
+        }
+        let n = self.inner.read(&mut buf[..max as usize])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Window<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        self.inner.seek(SeekFrom::Start(self.start + new_pos))?;
+        self.pos = new_pos;
+        Ok(new_pos)
+    }
+}
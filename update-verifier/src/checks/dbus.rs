@@ -0,0 +1,63 @@
+//! Checks that a name is owned on a D-Bus bus.
+
+use serde::Deserialize;
+use zbus::{
+    blocking::{fdo::DBusProxy, Connection},
+    names::BusName,
+};
+
+/// The bus on which a name is looked up.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bus {
+    #[default]
+    System,
+    Session,
+}
+
+/// Expects some process to own the well-known name `service` on `bus`.
+#[derive(Debug, Deserialize)]
+pub struct DbusName {
+    pub service: String,
+    #[serde(default)]
+    pub bus: Bus,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("`{0}` is not a valid dbus name: {1}")]
+    InvalidName(String, zbus::names::Error),
+    #[error("failed connecting to the {0:?} bus: {1}")]
+    Connect(Bus, zbus::Error),
+    #[error("failed querying the {0:?} bus: {1}")]
+    Query(Bus, zbus::Error),
+    #[error("name `{name}` has no owner on the {bus:?} bus")]
+    NoOwner { name: String, bus: Bus },
+}
+
+impl super::Check for DbusName {
+    type Error = Error;
+
+    const NAME: &'static str = "dbus name";
+
+    fn check(&self) -> Result<(), Self::Error> {
+        let name = BusName::try_from(self.service.as_str())
+            .map_err(|e| Error::InvalidName(self.service.clone(), e))?;
+        let connection = match self.bus {
+            Bus::System => Connection::system(),
+            Bus::Session => Connection::session(),
+        }
+        .map_err(|e| Error::Connect(self.bus, e))?;
+        let has_owner = DBusProxy::new(&connection)
+            .and_then(|dbus| dbus.name_has_owner(name).map_err(Into::into))
+            .map_err(|e| Error::Query(self.bus, e))?;
+        if has_owner {
+            Ok(())
+        } else {
+            Err(Error::NoOwner {
+                name: self.service.clone(),
+                bus: self.bus,
+            })
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Checks that a filesystem has enough free space.
+
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+/// Expects at least `min_free_bytes` to be available to unprivileged users on the filesystem
+/// containing `path`.
+#[derive(Debug, Deserialize)]
+pub struct DiskSpace {
+    pub path: PathBuf,
+    pub min_free_bytes: u64,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("failed querying filesystem statistics of `{}`: {1}", .0.display())]
+    Statvfs(PathBuf, rustix::io::Errno),
+    #[error("only {free} bytes are free at `{}`, expected at least {min}", .path.display())]
+    NotEnoughSpace { path: PathBuf, free: u64, min: u64 },
+}
+
+impl super::Check for DiskSpace {
+    type Error = Error;
+
+    const NAME: &'static str = "disk space";
+
+    fn check(&self) -> Result<(), Self::Error> {
+        let stat = rustix::fs::statvfs(&self.path)
+            .map_err(|e| Error::Statvfs(self.path.clone(), e))?;
+        let free = stat.f_bavail.saturating_mul(stat.f_frsize);
+        if free >= self.min_free_bytes {
+            Ok(())
+        } else {
+            Err(Error::NotEnoughSpace {
+                path: self.path.clone(),
+                free,
+                min: self.min_free_bytes,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiskSpace, Error};
+    use crate::checks::Check as _;
+
+    #[test]
+    fn free_space_is_compared_against_minimum() {
+        let enough = DiskSpace {
+            path: "/".into(),
+            min_free_bytes: 0,
+        };
+        assert!(enough.check().is_ok());
+
+        let too_much = DiskSpace {
+            path: "/".into(),
+            min_free_bytes: u64::MAX,
+        };
+        assert!(matches!(
+            too_much.check(),
+            Err(Error::NotEnoughSpace { .. })
+        ));
+    }
+}
@@ -0,0 +1,90 @@
+//! Checks that a file has the expected content.
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::PathBuf,
+};
+
+use serde::Deserialize;
+use sha2::{Digest as _, Sha256};
+
+/// Expects the file at `path` to have the hex encoded sha256 hash `sha256`.
+#[derive(Debug, Deserialize)]
+pub struct FileHash {
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("failed reading `{}`: {1}", .0.display())]
+    Read(PathBuf, io::Error),
+    #[error("`{}` has hash `{actual}`, expected `{expected}`", .path.display())]
+    Mismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl super::Check for FileHash {
+    type Error = Error;
+
+    const NAME: &'static str = "file hash";
+
+    fn check(&self) -> Result<(), Self::Error> {
+        let read_err = |e| Error::Read(self.path.clone(), e);
+        let mut file = BufReader::new(File::open(&self.path).map_err(read_err)?);
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher).map_err(read_err)?;
+        let actual = format!("{:x}", hasher.finalize());
+        if actual.eq_ignore_ascii_case(&self.sha256) {
+            Ok(())
+        } else {
+            Err(Error::Mismatch {
+                path: self.path.clone(),
+                expected: self.sha256.clone(),
+                actual,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use super::{Error, FileHash};
+    use crate::checks::Check as _;
+
+    // sha256 of `hello`
+    const HELLO_SHA256: &str =
+        "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn hash_is_compared_case_insensitively() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"hello").unwrap();
+
+        let check = |sha256: &str| FileHash {
+            path: file.path().to_path_buf(),
+            sha256: sha256.to_string(),
+        };
+        assert!(check(HELLO_SHA256).check().is_ok());
+        assert!(check(&HELLO_SHA256.to_uppercase()).check().is_ok());
+        assert!(matches!(
+            check(&HELLO_SHA256.replace('2', "3")).check(),
+            Err(Error::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_file_fails() {
+        let check = FileHash {
+            path: "/nonexistent/file".into(),
+            sha256: HELLO_SHA256.into(),
+        };
+        assert!(matches!(check.check(), Err(Error::Read(..))));
+    }
+}
@@ -0,0 +1,75 @@
+//! Checks that an HTTP endpoint is reachable.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+/// Expects a `GET` request to `url` to succeed within `timeout_ms`.
+///
+/// Meant for endpoints on the orb or the local network, such as a service's status page.
+#[derive(Debug, Deserialize)]
+pub struct Http {
+    pub url: String,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("failed building HTTP client: {0}")]
+    Client(reqwest::Error),
+    #[error("request to `{0}` failed: {1}")]
+    Request(String, reqwest::Error),
+}
+
+impl super::Check for Http {
+    type Error = Error;
+
+    const NAME: &'static str = "http endpoint";
+
+    fn check(&self) -> Result<(), Self::Error> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_millis(self.timeout_ms))
+            .build()
+            .map_err(Error::Client)?;
+        client
+            .get(&self.url)
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .map_err(|e| Error::Request(self.url.clone(), e))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::prelude::*;
+
+    use super::{default_timeout_ms, Error, Http};
+    use crate::checks::Check as _;
+
+    #[test]
+    fn successful_responses_pass() {
+        let server = MockServer::start();
+        let ok = server.mock(|when, then| {
+            when.method(GET).path("/ok");
+            then.status(200);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/broken");
+            then.status(503);
+        });
+
+        let check = |path| Http {
+            url: server.url(path),
+            timeout_ms: default_timeout_ms(),
+        };
+        assert!(check("/ok").check().is_ok());
+        ok.assert();
+        assert!(matches!(check("/broken").check(), Err(Error::Request(..))));
+    }
+}
@@ -1,6 +1,12 @@
 //! A common health check module.
 
+pub mod dbus;
+pub mod disk;
+pub mod file_hash;
+pub mod http;
 pub mod mcu;
+pub mod registry;
+pub mod systemd;
 
 use tracing::{info, instrument};
 
@@ -0,0 +1,314 @@
+//! Health checks loaded from a config file.
+//!
+//! Every release can ship its own acceptance criteria as a TOML file with one `[[check]]` table
+//! per check:
+//!
+//! ```toml
+//! [[check]]
+//! name = "worldcoin-core running"
+//! kind = "systemd-unit"
+//! unit = "worldcoin-core.service"
+//!
+//! [[check]]
+//! name = "enough space for logs"
+//! kind = "disk-space"
+//! severity = "warning"
+//! path = "/usr/persistent"
+//! min_free_bytes = 104857600
+//! ```
+//!
+//! Checks are fatal unless their `severity` is `warning`.
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use super::{
+    dbus::DbusName, disk::DiskSpace, file_hash::FileHash, http::Http,
+    systemd::SystemdUnit, Check,
+};
+
+/// Whether a failed check fails the whole health check.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Fatal,
+    Warning,
+}
+
+/// The check to perform, selected by the `kind` key.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Kind {
+    SystemdUnit(SystemdUnit),
+    DbusName(DbusName),
+    DiskSpace(DiskSpace),
+    Http(Http),
+    FileHash(FileHash),
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::SystemdUnit(_) => "systemd-unit",
+            Kind::DbusName(_) => "dbus-name",
+            Kind::DiskSpace(_) => "disk-space",
+            Kind::Http(_) => "http",
+            Kind::FileHash(_) => "file-hash",
+        }
+    }
+
+    fn run(&self) -> Result<(), String> {
+        fn run<C: Check>(check: &C) -> Result<(), String>
+        where
+            C::Error: fmt::Display,
+        {
+            check.run_check().map_err(|e| e.to_string())
+        }
+        match self {
+            Kind::SystemdUnit(check) => run(check),
+            Kind::DbusName(check) => run(check),
+            Kind::DiskSpace(check) => run(check),
+            Kind::Http(check) => run(check),
+            Kind::FileHash(check) => run(check),
+        }
+    }
+}
+
+/// A single configured check.
+#[derive(Debug, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    #[serde(default)]
+    pub severity: Severity,
+    #[serde(flatten)]
+    pub kind: Kind,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("failed reading health check config `{}`: {1}", .0.display())]
+    Read(PathBuf, io::Error),
+    #[error("failed parsing health check config `{}`: {1}", .0.display())]
+    Parse(PathBuf, toml::de::Error),
+}
+
+/// The checks configured for the current release.
+#[derive(Debug, Default, Deserialize)]
+pub struct Registry {
+    #[serde(default, rename = "check")]
+    pub checks: Vec<Entry>,
+}
+
+impl Registry {
+    /// Loads the checks configured at `path`.
+    ///
+    /// A missing config means that no checks are configured.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|e| Error::Parse(path.to_path_buf(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                warn!(
+                    "health check config `{}` does not exist, no checks configured",
+                    path.display()
+                );
+                Ok(Self::default())
+            }
+            Err(e) => Err(Error::Read(path.to_path_buf(), e)),
+        }
+    }
+
+    /// Runs all configured checks in order, adding their results to `report`.
+    pub fn run(&self, report: &mut Report) {
+        for entry in &self.checks {
+            let start = Instant::now();
+            let result = entry.kind.run();
+            report.push(CheckResult::new(
+                &entry.name,
+                entry.kind.as_str(),
+                entry.severity,
+                result,
+                start,
+            ));
+        }
+    }
+}
+
+/// The outcome of a single check.
+#[derive(Debug, Serialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub kind: String,
+    pub severity: Severity,
+    pub passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+impl CheckResult {
+    /// Records the `result` of a check that was started at `start`.
+    pub fn new(
+        name: &str,
+        kind: &str,
+        severity: Severity,
+        result: Result<(), String>,
+        start: Instant,
+    ) -> Self {
+        let duration_ms =
+            u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX);
+        if let Err(e) = &result {
+            match severity {
+                Severity::Fatal => error!("health check `{name}` failed: {e}"),
+                Severity::Warning => warn!("health check `{name}` failed: {e}"),
+            }
+        }
+        Self {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            severity,
+            passed: result.is_ok(),
+            error: result.err(),
+            duration_ms,
+        }
+    }
+}
+
+/// The structured report of a health check run.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// Whether none of the fatal checks failed.
+    pub passed: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Self {
+            passed: true,
+            checks: Vec::new(),
+        }
+    }
+}
+
+impl Report {
+    pub fn push(&mut self, result: CheckResult) {
+        if !result.passed && result.severity == Severity::Fatal {
+            self.passed = false;
+        }
+        self.checks.push(result);
+    }
+
+    /// Checks that failed and have [`Severity::Fatal`].
+    pub fn fatal_failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.checks
+            .iter()
+            .filter(|check| !check.passed && check.severity == Severity::Fatal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::{CheckResult, Kind, Registry, Report, Severity};
+
+    #[test]
+    fn config_is_parsed() {
+        let registry: Registry = toml::from_str(
+            r#"
+            [[check]]
+            name = "core"
+            kind = "systemd-unit"
+            unit = "worldcoin-core.service"
+
+            [[check]]
+            name = "supervisor"
+            kind = "dbus-name"
+            severity = "warning"
+            service = "org.worldcoin.OrbSupervisor1"
+            bus = "session"
+
+            [[check]]
+            name = "space"
+            kind = "disk-space"
+            path = "/usr/persistent"
+            min_free_bytes = 1024
+
+            [[check]]
+            name = "backend"
+            kind = "http"
+            url = "http://localhost:8080/health"
+
+            [[check]]
+            name = "config"
+            kind = "file-hash"
+            path = "/etc/config"
+            sha256 = "abcd"
+            "#,
+        )
+        .unwrap();
+
+        let kinds: Vec<_> = registry
+            .checks
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.kind.as_str(), entry.severity))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("core", "systemd-unit", Severity::Fatal),
+                ("supervisor", "dbus-name", Severity::Warning),
+                ("space", "disk-space", Severity::Fatal),
+                ("backend", "http", Severity::Fatal),
+                ("config", "file-hash", Severity::Fatal),
+            ]
+        );
+        let Kind::DbusName(dbus) = &registry.checks[1].kind else {
+            panic!("expected a dbus name check");
+        };
+        assert_eq!(dbus.service, "org.worldcoin.OrbSupervisor1");
+        let Kind::DiskSpace(disk) = &registry.checks[2].kind else {
+            panic!("expected a disk space check");
+        };
+        assert_eq!(disk.min_free_bytes, 1024);
+    }
+
+    #[test]
+    fn unknown_kinds_are_rejected() {
+        let config = r#"
+            [[check]]
+            name = "unknown"
+            kind = "telepathy"
+        "#;
+        assert!(toml::from_str::<Registry>(config).is_err());
+    }
+
+    #[test]
+    fn only_fatal_failures_fail_the_report() {
+        let result = |severity, result| {
+            CheckResult::new("check", "test", severity, result, Instant::now())
+        };
+        let mut report = Report::default();
+        assert!(report.passed);
+        report.push(result(Severity::Fatal, Ok(())));
+        report.push(result(Severity::Warning, Err("degraded".into())));
+        assert!(report.passed);
+
+        report.push(result(Severity::Fatal, Err("broken".into())));
+        assert!(!report.passed);
+        assert_eq!(report.fatal_failures().count(), 1);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["passed"], false);
+        assert_eq!(json["checks"][1]["error"], "degraded");
+        assert!(json["checks"][0].get("error").is_none());
+    }
+}
@@ -0,0 +1,57 @@
+//! Checks that a systemd unit is active.
+
+use serde::Deserialize;
+use zbus::{
+    blocking::{Connection, Proxy},
+    zvariant::OwnedObjectPath,
+};
+
+const SYSTEMD_SERVICE: &str = "org.freedesktop.systemd1";
+
+/// Expects `unit` to be in the `active` state.
+#[derive(Debug, Deserialize)]
+pub struct SystemdUnit {
+    pub unit: String,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("failed querying systemd over dbus: {0}")]
+    Dbus(#[from] zbus::Error),
+    #[error("unit `{unit}` is `{state}`, expected `active`")]
+    NotActive { unit: String, state: String },
+}
+
+impl super::Check for SystemdUnit {
+    type Error = Error;
+
+    const NAME: &'static str = "systemd unit";
+
+    fn check(&self) -> Result<(), Self::Error> {
+        let connection = Connection::system()?;
+        let manager = Proxy::new(
+            &connection,
+            SYSTEMD_SERVICE,
+            "/org/freedesktop/systemd1",
+            "org.freedesktop.systemd1.Manager",
+        )?;
+        // Unlike `GetUnit`, `LoadUnit` also succeeds for units that are not loaded, which are
+        // then reported as inactive.
+        let path: OwnedObjectPath = manager.call("LoadUnit", &(self.unit.as_str(),))?;
+        let unit = Proxy::new(
+            &connection,
+            SYSTEMD_SERVICE,
+            path,
+            "org.freedesktop.systemd1.Unit",
+        )?;
+        let state: String = unit.get_property("ActiveState")?;
+        if state == "active" {
+            Ok(())
+        } else {
+            Err(Error::NotActive {
+                unit: self.unit.clone(),
+                state,
+            })
+        }
+    }
+}
@@ -2,23 +2,54 @@
 #![warn(clippy::pedantic, missing_docs)]
 
 use crate::checks::mcu::{Error, Mcu};
+use crate::checks::registry::{CheckResult, Registry, Report, Severity};
 use crate::checks::Check;
 use color_eyre::eyre;
 use orb_build_info::{make_build_info, BuildInfo};
 use orb_slot_ctrl::OrbSlotCtrl;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use tracing::{error, info, instrument, warn};
+use zbus::blocking::{Connection, Proxy};
 
 mod checks;
 
 #[allow(missing_docs)]
 pub const BUILD_INFO: BuildInfo = make_build_info!();
 
+/// Built-in health checks that can be selected to count as fatal failures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum HealthCheck {
+    /// The main microcontroller runs firmware compatible with the current slot.
+    Mcu,
+}
+
+/// Configures which health checks run and how their failures are handled.
+#[derive(Debug)]
+pub struct Config {
+    /// Whether fatal failures roll back to the other slot. Otherwise they are logged, but the
+    /// current slot is kept.
+    pub rollback: bool,
+    /// Failing any of these built-in checks is fatal. Other built-in checks only warn.
+    pub rollback_on: Vec<HealthCheck>,
+    /// The log in which rollbacks are recorded.
+    pub rollback_log: PathBuf,
+    /// The release's health check config. Failing any of its checks is fatal, unless their
+    /// severity is a warning.
+    pub checks: PathBuf,
+    /// Where to write the JSON report of all performed checks, in addition to logging it.
+    pub report: Option<PathBuf>,
+}
+
 /// Performs the system health check.
 ///
 /// # Errors
 /// Can throw errors of `slot-ctrl` library or when calling system health checks.
 #[instrument(err, skip(orb_slot_ctrl))]
-pub fn run_health_check(orb_slot_ctrl: OrbSlotCtrl) -> eyre::Result<()> {
+pub fn run_health_check(
+    orb_slot_ctrl: OrbSlotCtrl,
+    config: &Config,
+) -> eyre::Result<()> {
     // get runtime environment variable to force health check
     let dry_run = std::env::var("UPDATE_VERIFIER_DRY_RUN").is_ok();
 
@@ -31,6 +62,8 @@ pub fn run_health_check(orb_slot_ctrl: OrbSlotCtrl) -> eyre::Result<()> {
             dry_run
         );
 
+        let mut report = Report::default();
+
         // In case rootfs status is NOT Normal, and we know it's the first boot attempt
         // by checking the retry counter
         // we check that the main microcontroller version is compatible with the
@@ -45,31 +78,67 @@ pub fn run_health_check(orb_slot_ctrl: OrbSlotCtrl) -> eyre::Result<()> {
             // on each successful execution, but we might want to check the
             // health check logic multiple times
             if retry_count >= (max_retry_count - 1) {
-                match Mcu::main().run_check() {
-                    Ok(()) => {}
+                let start = Instant::now();
+                let result = Mcu::main().run_check();
+                let severity = match &result {
                     Err(
                         Error::RecoverableVersionMismatch(..)
                         | Error::SecondaryIsMoreRecent(_),
                     ) => {
                         info!("Activating and rebooting for mcu update retry");
-                        if dry_run {
-                            warn!("Dry-run: skipping mcu update retry");
-                        } else {
+                        if !dry_run {
                             Mcu::main().reboot_for_update()?;
                             return Ok(());
                         }
+                        warn!("Dry-run: skipping mcu update retry");
+                        Severity::Warning
                     }
-                    Err(e) => {
-                        error!("Main MCU version check failed: {}", e);
-                        warn!("The main microcontroller might not be compatible, but is going to be used anyway.");
+                    _ if config.rollback_on.contains(&HealthCheck::Mcu) => {
+                        Severity::Fatal
                     }
-                }
+                    _ => Severity::Warning,
+                };
+                report.push(CheckResult::new(
+                    Mcu::NAME,
+                    "mcu",
+                    severity,
+                    result.map_err(|e| e.to_string()),
+                    start,
+                ));
             }
         } else {
             warn!("Could not get retry count or max retry count, skipping main MCU version check");
         }
 
-        info!("system health is OK");
+        // A broken config says nothing about the health of the slot, so it must not trigger a
+        // rollback.
+        match Registry::load(&config.checks) {
+            Ok(registry) => registry.run(&mut report),
+            Err(e) => report.push(CheckResult::new(
+                "health check config",
+                "config",
+                Severity::Warning,
+                Err(e.to_string()),
+                Instant::now(),
+            )),
+        }
+        publish_report(&report, config.report.as_deref());
+
+        if report.passed {
+            info!("system health is OK");
+        } else {
+            let failed: Vec<_> = report
+                .fatal_failures()
+                .map(|check| format!("`{}`", check.name))
+                .collect();
+            let reason = format!("health checks failed: {}", failed.join(", "));
+            if config.rollback
+                && rollback(&orb_slot_ctrl, &reason, &config.rollback_log, dry_run)?
+            {
+                return Ok(());
+            }
+            warn!("{reason}; the current slot is going to be used anyway.");
+        }
 
         info!("setting rootfs status to Normal");
         orb_slot_ctrl.set_current_rootfs_status(orb_slot_ctrl::RootFsStatus::Normal)?;
@@ -79,3 +148,70 @@ pub fn run_health_check(orb_slot_ctrl: OrbSlotCtrl) -> eyre::Result<()> {
     orb_slot_ctrl.reset_current_retry_count_to_max()?;
     Ok(())
 }
+
+/// Logs `report` as JSON and writes it to `path`, if given.
+///
+/// Failing to publish the report is not a reason to reject the current slot, so errors are only
+/// logged.
+fn publish_report(report: &Report, path: Option<&Path>) {
+    let json = match serde_json::to_string_pretty(report) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("failed serializing health check report: {e}");
+            return;
+        }
+    };
+    info!("health check report: {json}");
+    if let Some(path) = path {
+        if let Err(e) = std::fs::write(path, json) {
+            error!(
+                "failed writing health check report to `{}`: {e}",
+                path.display()
+            );
+        }
+    }
+}
+
+/// Rolls back to the other slot and reboots into it.
+///
+/// Returns `false` if the current slot has to be kept, because the other slot is unbootable or
+/// because of a dry run.
+fn rollback(
+    orb_slot_ctrl: &OrbSlotCtrl,
+    reason: &str,
+    log: &Path,
+    dry_run: bool,
+) -> eyre::Result<bool> {
+    if dry_run {
+        warn!("Dry-run: skipping rollback: {reason}");
+        return Ok(false);
+    }
+    info!("rolling back to the other slot: {reason}");
+    match orb_slot_ctrl.rollback(reason, log) {
+        Ok(slot) => info!("slot {slot} is set for the next boot"),
+        Err(e @ orb_slot_ctrl::Error::RollbackTargetUnbootable(_)) => {
+            error!("{e}");
+            return Ok(false);
+        }
+        Err(e @ orb_slot_ctrl::Error::RecordRollback(_)) => warn!("{e}"),
+        Err(e) => return Err(e.into()),
+    }
+    info!("rebooting to complete rollback");
+    reboot()?;
+    Ok(true)
+}
+
+fn reboot() -> eyre::Result<()> {
+    let connection = Connection::system()?;
+
+    let proxy: Proxy<'_> = zbus::blocking::proxy::Builder::new(&connection)
+        .interface("org.freedesktop.login1.Manager")?
+        .path("/org/freedesktop/login1")?
+        .destination("org.freedesktop.login1")?
+        .build()?;
+
+    // reboot right now without asking for interactive authorization (body is `false`)
+    proxy.call_method("Reboot", &false)?;
+
+    Ok(())
+}
@@ -4,10 +4,12 @@ use clap::{
 };
 use color_eyre::eyre::{self, Context};
 use orb_slot_ctrl::{EfiVarDb, OrbSlotCtrl};
-use orb_update_verifier::BUILD_INFO;
+use orb_update_verifier::{Config, HealthCheck, BUILD_INFO};
+use std::path::PathBuf;
 use tracing::error;
 
 const SYSLOG_IDENTIFIER: &str = "worldcoin-update-verifier";
+const DEFAULT_CHECKS_PATH: &str = "/etc/orb-update-verifier/checks.toml";
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -15,7 +17,23 @@ const SYSLOG_IDENTIFIER: &str = "worldcoin-update-verifier";
     about,
     styles = clap_v3_styles(),
 )]
-struct Cli {}
+struct Cli {
+    /// Rolls back to the other slot when fatal health checks fail.
+    #[arg(long)]
+    rollback: bool,
+    /// Built-in health checks whose failures are fatal, separated by commas.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    rollback_on: Vec<HealthCheck>,
+    /// The log in which rollbacks are recorded.
+    #[arg(long, default_value = orb_slot_ctrl::rollback::DEFAULT_LOG_PATH)]
+    rollback_log: PathBuf,
+    /// The health check config of the current release.
+    #[arg(long, default_value = DEFAULT_CHECKS_PATH)]
+    checks: PathBuf,
+    /// Writes a JSON report of all performed health checks to this file.
+    #[arg(long)]
+    report: Option<PathBuf>,
+}
 
 fn clap_v3_styles() -> Styles {
     Styles::styled()
@@ -34,11 +52,18 @@ fn main() -> color_eyre::Result<()> {
 }
 
 fn run() -> eyre::Result<()> {
-    let _args = Cli::parse();
+    let args = Cli::parse();
+    let config = Config {
+        rollback: args.rollback,
+        rollback_on: args.rollback_on,
+        rollback_log: args.rollback_log,
+        checks: args.checks,
+        report: args.report,
+    };
 
     let efi_var_db = EfiVarDb::from_rootfs("/")?;
     let orb_slot_ctrl = OrbSlotCtrl::new(&efi_var_db)?;
-    orb_update_verifier::run_health_check(orb_slot_ctrl)
+    orb_update_verifier::run_health_check(orb_slot_ctrl, &config)
         .wrap_err("update verifier encountered error while checking system health")?;
 
     Ok(())
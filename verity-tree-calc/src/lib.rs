@@ -0,0 +1,10 @@
+//! dm-verity hash trees, compatible with veritysetup.
+//!
+//! [`tree`] computes and verifies the hash tree of an image, [`superblock`] encodes the
+//! parameters of a tree, so that `veritysetup open` and `veritysetup verify` can use it
+//! without passing them explicitly.
+
+#![forbid(unsafe_code)]
+
+pub mod superblock;
+pub mod tree;
@@ -1,23 +1,113 @@
 #![forbid(unsafe_code)]
 
-use std::io::{self, Write};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read as _, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{ensure, eyre, WrapErr as _};
+use data_encoding::{HEXLOWER, HEXLOWER_PERMISSIVE};
 use orb_build_info::{make_build_info, BuildInfo};
-use tracing::debug;
+use tracing::{debug, info};
+use uuid::Uuid;
+use verity_tree_calc::{
+    superblock::{self, Superblock, SUPERBLOCK_SIZE},
+    tree::{self, HashTree, Params},
+};
 
 // TODO @oldgalileo document the math and magic consts
 
 const BUILD_INFO: BuildInfo = make_build_info!();
 
+const DEFAULT_BLOCK_SIZE: u32 = 4096;
+const DEFAULT_SALT_SIZE: usize = 32;
+
 #[derive(Parser, Debug)]
-#[command(about, author, version=BUILD_INFO.version, styles=make_clap_v3_styles())]
+#[command(
+    about,
+    author,
+    version = BUILD_INFO.version,
+    styles = make_clap_v3_styles(),
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+)]
 struct Args {
-    #[clap(value_parser)]
-    data_size: u64,
+    /// Prints the padding of an image of this size to 4096 byte blocks, and the offset
+    /// of its hash tree.
+    #[clap(value_parser, required = true)]
+    data_size: Option<u64>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Computes the hash tree of an image, writes it to the hash file and prints the
+    /// root hash, like `veritysetup format`.
+    Format(FormatArgs),
+    /// Checks an image and its hash tree against a root hash, like `veritysetup verify`.
+    Verify(VerifyArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct FormatArgs {
+    #[command(flatten)]
+    files: FileArgs,
+    #[command(flatten)]
+    params: ParamsArgs,
+    /// The uuid stored in the superblock. Defaults to a random one.
+    #[arg(long, conflicts_with = "no_superblock")]
+    uuid: Option<Uuid>,
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyArgs {
+    #[command(flatten)]
+    files: FileArgs,
+    /// The expected root hash, as hex.
+    root_hash: String,
+    /// Only with `--no-superblock`, otherwise they are read from the superblock.
+    #[command(flatten)]
+    params: ParamsArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct FileArgs {
+    /// The image with the data.
+    data: PathBuf,
+    /// The file with the hash tree. May be the image itself, with a `--hash-offset`
+    /// behind the data.
+    hash: PathBuf,
+    /// The offset of the superblock in the hash file, in bytes. The hash tree starts
+    /// at the next hash block.
+    #[arg(long, default_value_t = 0)]
+    hash_offset: u64,
+    /// There is no superblock, the hash tree starts at `--hash-offset`.
+    #[arg(long)]
+    no_superblock: bool,
 }
 
-fn main() {
+#[derive(clap::Args, Debug)]
+struct ParamsArgs {
+    /// The size of the hashed data blocks [default: 4096].
+    #[arg(long)]
+    data_block_size: Option<u32>,
+    /// The size of the hash tree blocks [default: 4096].
+    #[arg(long)]
+    hash_block_size: Option<u32>,
+    /// The number of hashed data blocks. Defaults to the whole image, whose size must
+    /// then be a multiple of the data block size.
+    #[arg(long)]
+    data_blocks: Option<u64>,
+    /// The salt as hex, or `-` for no salt. Formatting defaults to 32 random bytes.
+    #[arg(long)]
+    salt: Option<String>,
+}
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
     tracing_subscriber::FmtSubscriber::builder()
         .with_writer(io::stderr)
         .init();
@@ -25,7 +115,215 @@ fn main() {
     let args = Args::parse();
     debug!(args = ?args, "parsed arguments");
 
-    let data_blocks = ((args.data_size + 4095) & !4095) / 4096;
+    match args.command {
+        Some(Command::Format(args)) => {
+            let root_hash = format_image(&args)?;
+            println!("{}", HEXLOWER.encode(&root_hash));
+        }
+        Some(Command::Verify(args)) => {
+            verify_image(&args)?;
+            info!("image matches the root hash");
+        }
+        None => {
+            let data_size = args.data_size.expect("required without a subcommand");
+            let (padding, hash_position) = legacy_offsets(data_size);
+            io::stdout()
+                .write_all(format!("{padding} {hash_position}").as_bytes())
+                .unwrap();
+        }
+    }
+    Ok(())
+}
+
+fn format_image(args: &FormatArgs) -> color_eyre::Result<[u8; tree::DIGEST_SIZE]> {
+    let FormatArgs {
+        files,
+        params,
+        uuid,
+    } = args;
+    ensure!(
+        !files.no_superblock || params.salt.is_some(),
+        "--salt is required with --no-superblock, the random salt would get lost"
+    );
+    let salt = match &params.salt {
+        Some(salt) => parse_salt(salt)?,
+        None => {
+            let mut salt = vec![0; DEFAULT_SALT_SIZE];
+            getrandom::getrandom(&mut salt).wrap_err("failed to generate a salt")?;
+            salt
+        }
+    };
+    let params = params.to_params(&files.data, salt)?;
+    let tree_offset = files.hash_tree_offset(params.hash_block_size);
+    if same_file(&files.data, &files.hash)? {
+        let data_end = params.data_blocks * u64::from(params.data_block_size);
+        ensure!(
+            files.hash_offset >= data_end,
+            "--hash-offset must be at least {data_end}, to not overwrite the data"
+        );
+    }
+
+    let data = File::open(&files.data)
+        .wrap_err_with(|| format!("failed to open {}", files.data.display()))?;
+    let tree = HashTree::compute(&params, BufReader::new(data))
+        .wrap_err("failed to compute the hash tree")?;
+    debug!(
+        levels = ?params.level_sizes(),
+        size = params.hash_tree_size(),
+        offset = tree_offset,
+        "computed hash tree"
+    );
+
+    let mut hash = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&files.hash)
+        .wrap_err_with(|| format!("failed to open {}", files.hash.display()))?;
+    (|| {
+        if !files.no_superblock {
+            let superblock = Superblock {
+                uuid: uuid.unwrap_or_else(Uuid::new_v4),
+                params: params.clone(),
+            };
+            hash.seek(SeekFrom::Start(files.hash_offset))?;
+            hash.write_all(&superblock.to_bytes())?;
+        }
+        hash.seek(SeekFrom::Start(tree_offset))?;
+        let mut writer = BufWriter::new(&mut hash);
+        tree.write_to(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        hash.sync_all()
+    })()
+    .wrap_err_with(|| format!("failed to write to {}", files.hash.display()))?;
+
+    Ok(*tree.root_hash())
+}
+
+fn verify_image(args: &VerifyArgs) -> color_eyre::Result<()> {
+    let VerifyArgs {
+        files,
+        root_hash,
+        params: params_args,
+    } = args;
+    let root_hash = HEXLOWER_PERMISSIVE
+        .decode(root_hash.as_bytes())
+        .wrap_err("root hash is not hex")?;
+    let mut hash = File::open(&files.hash)
+        .wrap_err_with(|| format!("failed to open {}", files.hash.display()))?;
+    let params = if files.no_superblock {
+        let salt = params_args
+            .salt
+            .as_deref()
+            .map_or(Ok(Vec::new()), parse_salt)?;
+        params_args.to_params(&files.data, salt)?
+    } else {
+        ensure!(
+            params_args.is_empty(),
+            "the parameters are read from the superblock, pass them only with \
+             --no-superblock"
+        );
+        let mut bytes = [0; SUPERBLOCK_SIZE];
+        hash.seek(SeekFrom::Start(files.hash_offset))
+            .and_then(|_| hash.read_exact(&mut bytes))
+            .wrap_err("failed to read the superblock")?;
+        Superblock::parse(&bytes)?.params
+    };
+    debug!(?params, "verifying");
+    hash.seek(SeekFrom::Start(
+        files.hash_tree_offset(params.hash_block_size),
+    ))?;
+
+    let data = File::open(&files.data)
+        .wrap_err_with(|| format!("failed to open {}", files.data.display()))?;
+    tree::verify(
+        &params,
+        BufReader::new(data),
+        BufReader::new(hash),
+        &root_hash,
+    )?;
+    Ok(())
+}
+
+impl FileArgs {
+    fn hash_tree_offset(&self, hash_block_size: u32) -> u64 {
+        if self.no_superblock {
+            self.hash_offset
+        } else {
+            superblock::hash_tree_offset(self.hash_offset, hash_block_size)
+        }
+    }
+}
+
+impl ParamsArgs {
+    fn is_empty(&self) -> bool {
+        let Self {
+            data_block_size,
+            hash_block_size,
+            data_blocks,
+            salt,
+        } = self;
+        data_block_size.is_none()
+            && hash_block_size.is_none()
+            && data_blocks.is_none()
+            && salt.is_none()
+    }
+
+    fn to_params(&self, data: &Path, salt: Vec<u8>) -> color_eyre::Result<Params> {
+        let data_block_size = self.data_block_size.unwrap_or(DEFAULT_BLOCK_SIZE);
+        let data_blocks = match self.data_blocks {
+            Some(data_blocks) => data_blocks,
+            None => {
+                let data_size = data
+                    .metadata()
+                    .wrap_err_with(|| format!("failed to stat {}", data.display()))?
+                    .len();
+                let padding =
+                    data_size.next_multiple_of(u64::from(data_block_size)) - data_size;
+                ensure!(
+                    padding == 0,
+                    "image size {data_size} is not a multiple of the data block size \
+                     {data_block_size}, pad it by {padding} bytes or pass --data-blocks"
+                );
+                data_size / u64::from(data_block_size)
+            }
+        };
+        let params = Params {
+            data_block_size,
+            hash_block_size: self.hash_block_size.unwrap_or(DEFAULT_BLOCK_SIZE),
+            data_blocks,
+            salt,
+        };
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+fn parse_salt(salt: &str) -> color_eyre::Result<Vec<u8>> {
+    if salt == "-" {
+        return Ok(Vec::new());
+    }
+    HEXLOWER_PERMISSIVE
+        .decode(salt.as_bytes())
+        .map_err(|err| eyre!("salt is not hex: {err}"))
+}
+
+fn same_file(a: &Path, b: &Path) -> color_eyre::Result<bool> {
+    let Ok(b) = b.canonicalize() else {
+        // The hash file doesn't exist yet.
+        return Ok(false);
+    };
+    let a = a
+        .canonicalize()
+        .wrap_err_with(|| format!("failed to resolve {}", a.display()))?;
+    Ok(a == b)
+}
+
+/// Returns the padding of an image of `data_size` bytes to 4096 byte blocks, and the
+/// offset that orb-os places its hash tree at.
+fn legacy_offsets(data_size: u64) -> (u64, u64) {
+    let data_blocks = ((data_size + 4095) & !4095) / 4096;
     let hash_block_size = 4096;
     let digest_size = 32;
     let hash_per_block_bits: u32 = ((hash_block_size / digest_size) as u64).ilog2();
@@ -52,16 +350,7 @@ fn main() {
     // let hash_position = ((hash_position + 4096 - 1) / 4096) + 1;
     // let hash_position = hash_position * 4096;
 
-    io::stdout()
-        .write_all(
-            format!(
-                "{} {}",
-                (data_blocks * 4096) - args.data_size,
-                hash_position
-            )
-            .as_bytes(),
-        )
-        .unwrap();
+    ((data_blocks * 4096) - data_size, hash_position)
 }
 
 fn make_clap_v3_styles() -> clap::builder::Styles {
@@ -75,6 +364,84 @@ fn make_clap_v3_styles() -> clap::builder::Styles {
 
 #[cfg(test)]
 mod test {
-    // TODO @oldgalileo write some tests pls to document how this is supposed
-    // to be used 👉👈
+    use std::fs;
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Args {
+        Args::try_parse_from([&["verity-tree-calc"], args].concat()).unwrap()
+    }
+
+    #[test]
+    fn test_legacy_offsets() {
+        assert!(parse(&["4097"]).command.is_none());
+        assert_eq!(legacy_offsets(4096), (0, 4096));
+        assert_eq!(legacy_offsets(4097), (4095, 8192));
+        assert_eq!(legacy_offsets(1 << 30), (0, 8192));
+    }
+
+    #[test]
+    fn test_format_and_verify_appended_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("image");
+        let image_str = image.to_str().unwrap();
+        fs::write(&image, vec![0x5A; 64 * 4096]).unwrap();
+
+        let Some(Command::Format(format_args)) = parse(&[
+            "format",
+            image_str,
+            image_str,
+            "--hash-offset",
+            "262144",
+            "--salt",
+            "00ff",
+        ])
+        .command
+        else {
+            panic!("expected the format subcommand");
+        };
+        let root_hash = HEXLOWER.encode(&format_image(&format_args).unwrap());
+
+        let verify_args = |root_hash: &str| {
+            let Some(Command::Verify(args)) = parse(&[
+                "verify",
+                image_str,
+                image_str,
+                root_hash,
+                "--hash-offset",
+                "262144",
+            ])
+            .command
+            else {
+                panic!("expected the verify subcommand");
+            };
+            args
+        };
+        verify_image(&verify_args(&root_hash)).unwrap();
+        assert!(verify_image(&verify_args(&"00".repeat(32))).is_err());
+
+        // Superblock and a single hash block behind the data.
+        let contents = fs::read(&image).unwrap();
+        assert_eq!(contents.len(), 66 * 4096);
+        let superblock =
+            Superblock::parse(contents[64 * 4096..][..512].try_into().unwrap())
+                .unwrap();
+        assert_eq!(superblock.params.data_blocks, 64);
+        assert_eq!(superblock.params.salt, [0x00, 0xFF]);
+    }
+
+    #[test]
+    fn test_format_rejects_overlapping_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("image");
+        let image_str = image.to_str().unwrap();
+        fs::write(&image, vec![0; 4 * 4096]).unwrap();
+        let Some(Command::Format(format_args)) =
+            parse(&["format", image_str, image_str, "--hash-offset", "4096"]).command
+        else {
+            panic!("expected the format subcommand");
+        };
+        assert!(format_image(&format_args).is_err());
+        assert_eq!(fs::read(&image).unwrap(), vec![0; 4 * 4096]);
+    }
 }
@@ -0,0 +1,190 @@
+//! The veritysetup superblock, which stores the parameters of a hash tree in front of it.
+//!
+//! The layout is `struct verity_sb` of cryptsetup, all integers are little endian:
+//!
+//! | Offset | Size | Field                        |
+//! | ------ | ---- | ---------------------------- |
+//! | 0      | 8    | signature `"verity\0\0"`     |
+//! | 8      | 4    | version, always 1            |
+//! | 12     | 4    | hash type, always 1          |
+//! | 16     | 16   | uuid                         |
+//! | 32     | 32   | algorithm, NUL padded        |
+//! | 64     | 4    | data block size              |
+//! | 68     | 4    | hash block size              |
+//! | 72     | 8    | number of data blocks        |
+//! | 80     | 2    | salt size                    |
+//! | 82     | 6    | padding                      |
+//! | 88     | 256  | salt, zero padded            |
+//! | 344    | 168  | padding                      |
+
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::tree::{Params, ParamsError, ALGORITHM};
+
+/// The size of the superblock on disk.
+pub const SUPERBLOCK_SIZE: usize = 512;
+/// The largest salt that fits into the superblock.
+pub const MAX_SALT_SIZE: usize = 256;
+
+const SIGNATURE: &[u8; 8] = b"verity\0\0";
+const VERSION: u32 = 1;
+const HASH_TYPE: u32 = 1;
+
+/// Error returned by [`Superblock::parse`].
+#[derive(Error, Debug)]
+pub enum SuperblockError {
+    /// The signature doesn't match, there is no superblock.
+    #[error("no verity superblock found")]
+    Signature,
+    /// The superblock version is not 1.
+    #[error("unsupported superblock version {0}")]
+    Version(u32),
+    /// The hash type is not 1, the only one supported by veritysetup by default.
+    #[error("unsupported hash type {0}")]
+    HashType(u32),
+    /// The hash algorithm is not sha256.
+    #[error("unsupported hash algorithm {0:?}")]
+    Algorithm(String),
+    /// The parameters stored in the superblock are invalid.
+    #[error("invalid parameters in superblock")]
+    Params(#[from] ParamsError),
+}
+
+/// A veritysetup superblock.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Superblock {
+    /// Identifies the hash tree, e.g. for `veritysetup dump`.
+    pub uuid: Uuid,
+    /// The parameters of the hash tree.
+    pub params: Params,
+}
+
+impl Superblock {
+    /// Encodes the superblock.
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; SUPERBLOCK_SIZE] {
+        let params = &self.params;
+        let mut bytes = [0; SUPERBLOCK_SIZE];
+        bytes[0..8].copy_from_slice(SIGNATURE);
+        bytes[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        bytes[12..16].copy_from_slice(&HASH_TYPE.to_le_bytes());
+        bytes[16..32].copy_from_slice(self.uuid.as_bytes());
+        bytes[32..32 + ALGORITHM.len()].copy_from_slice(ALGORITHM.as_bytes());
+        bytes[64..68].copy_from_slice(&params.data_block_size.to_le_bytes());
+        bytes[68..72].copy_from_slice(&params.hash_block_size.to_le_bytes());
+        bytes[72..80].copy_from_slice(&params.data_blocks.to_le_bytes());
+        let salt_size = u16::try_from(params.salt.len())
+            .expect("params are validated to have a salt of at most 256 bytes");
+        bytes[80..82].copy_from_slice(&salt_size.to_le_bytes());
+        bytes[88..88 + params.salt.len()].copy_from_slice(&params.salt);
+        bytes
+    }
+
+    /// Decodes and validates a superblock.
+    pub fn parse(bytes: &[u8; SUPERBLOCK_SIZE]) -> Result<Self, SuperblockError> {
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+        };
+        if &bytes[0..8] != SIGNATURE {
+            return Err(SuperblockError::Signature);
+        }
+        let version = u32_at(8);
+        if version != VERSION {
+            return Err(SuperblockError::Version(version));
+        }
+        let hash_type = u32_at(12);
+        if hash_type != HASH_TYPE {
+            return Err(SuperblockError::HashType(hash_type));
+        }
+        let algorithm = &bytes[32..64];
+        let algorithm_len = algorithm.iter().position(|&b| b == 0).unwrap_or(32);
+        let algorithm = String::from_utf8_lossy(&algorithm[..algorithm_len]);
+        if algorithm != ALGORITHM {
+            return Err(SuperblockError::Algorithm(algorithm.into_owned()));
+        }
+        let salt_size = usize::from(u16::from_le_bytes([bytes[80], bytes[81]]));
+        if salt_size > MAX_SALT_SIZE {
+            return Err(ParamsError::SaltSize(salt_size).into());
+        }
+        let params = Params {
+            data_block_size: u32_at(64),
+            hash_block_size: u32_at(68),
+            data_blocks: u64::from_le_bytes(bytes[72..80].try_into().unwrap()),
+            salt: bytes[88..88 + salt_size].to_vec(),
+        };
+        params.validate()?;
+        Ok(Self {
+            uuid: Uuid::from_bytes(bytes[16..32].try_into().unwrap()),
+            params,
+        })
+    }
+}
+
+/// Returns the offset of the hash tree in the hash device, if the superblock is written
+/// at `hash_offset`. Same as veritysetup, the tree starts at the first hash block after
+/// the superblock.
+#[must_use]
+pub fn hash_tree_offset(hash_offset: u64, hash_block_size: u32) -> u64 {
+    (hash_offset + SUPERBLOCK_SIZE as u64).next_multiple_of(u64::from(hash_block_size))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn superblock() -> Superblock {
+        Superblock {
+            uuid: "a5f3e4f2-4f86-4c5e-9a0e-d1b1f6d4c2a1".parse().unwrap(),
+            params: Params {
+                data_block_size: 4096,
+                hash_block_size: 4096,
+                data_blocks: 0x0102_0304,
+                salt: vec![0xAA; 32],
+            },
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        let bytes = superblock().to_bytes();
+        assert_eq!(&bytes[0..16], b"verity\0\0\x01\0\0\0\x01\0\0\0");
+        assert_eq!(&bytes[32..40], b"sha256\0\0");
+        assert_eq!(&bytes[64..72], &[0, 0x10, 0, 0, 0, 0x10, 0, 0]);
+        assert_eq!(&bytes[72..80], &[4, 3, 2, 1, 0, 0, 0, 0]);
+        assert_eq!(&bytes[80..82], &[32, 0]);
+        assert_eq!(&bytes[88..120], &[0xAA; 32]);
+        assert!(bytes[120..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let superblock = superblock();
+        assert_eq!(
+            Superblock::parse(&superblock.to_bytes()).unwrap(),
+            superblock
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid() {
+        let mut bytes = superblock().to_bytes();
+        bytes[32..38].copy_from_slice(b"sha512");
+        assert!(matches!(
+            Superblock::parse(&bytes),
+            Err(SuperblockError::Algorithm(algorithm)) if algorithm == "sha512"
+        ));
+        assert!(matches!(
+            Superblock::parse(&[0; SUPERBLOCK_SIZE]),
+            Err(SuperblockError::Signature)
+        ));
+    }
+
+    #[test]
+    fn test_hash_tree_offset() {
+        assert_eq!(hash_tree_offset(0, 4096), 4096);
+        assert_eq!(hash_tree_offset(8192, 4096), 12288);
+        assert_eq!(hash_tree_offset(512, 4096), 4096);
+        assert_eq!(hash_tree_offset(0, 512), 512);
+    }
+}
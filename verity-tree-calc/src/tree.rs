@@ -0,0 +1,335 @@
+//! dm-verity hash trees, in the format of veritysetup (format version 1).
+//!
+//! Every data block is hashed as `sha256(salt || block)`. The digests are packed into
+//! hash blocks, zero padded to the hash block size, which are hashed the same way into
+//! the next level, until a level fits into a single hash block. The root hash is the
+//! hash of that block, or of the only data block if there is just one. On disk, the
+//! levels are stored top level first.
+
+use std::io::{self, Read, Write};
+
+use sha2::{Digest as _, Sha256};
+use thiserror::Error;
+
+use crate::superblock::MAX_SALT_SIZE;
+
+/// The hash algorithm of the tree, as named by veritysetup.
+pub const ALGORITHM: &str = "sha256";
+/// The size of a digest of [`ALGORITHM`].
+pub const DIGEST_SIZE: usize = 32;
+
+const MIN_BLOCK_SIZE: u32 = 512;
+const MAX_BLOCK_SIZE: u32 = 512 * 1024;
+
+/// Error returned by [`Params::validate`].
+#[derive(Error, Debug)]
+pub enum ParamsError {
+    /// The block size is not supported by dm-verity.
+    #[error(
+        "block size {0} is not a power of two between {MIN_BLOCK_SIZE} and \
+         {MAX_BLOCK_SIZE}"
+    )]
+    BlockSize(u32),
+    /// The salt doesn't fit into the superblock.
+    #[error("salt of {0} bytes is larger than {MAX_SALT_SIZE} bytes")]
+    SaltSize(usize),
+    /// There is no data to hash.
+    #[error("there must be at least one data block")]
+    NoData,
+}
+
+/// Error returned by [`verify`].
+#[derive(Error, Debug)]
+pub enum VerifyError {
+    /// Reading the data or the hash tree failed.
+    #[error("failed to read the data or the hash tree")]
+    Io(#[from] io::Error),
+    /// The data doesn't match the root hash.
+    #[error("root hash doesn't match the data")]
+    RootHash,
+    /// The hash tree on disk doesn't match the data. Level 0 hashes the data blocks.
+    #[error("hash block {block} of level {level} doesn't match the data")]
+    HashBlock {
+        /// The level of the hash block.
+        level: usize,
+        /// The index of the hash block within its level.
+        block: usize,
+    },
+}
+
+/// The parameters of a hash tree.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Params {
+    /// The size of the hashed blocks of data, in bytes.
+    pub data_block_size: u32,
+    /// The size of the blocks of the hash tree, in bytes.
+    pub hash_block_size: u32,
+    /// The number of hashed data blocks.
+    pub data_blocks: u64,
+    /// Prepended to every hashed block.
+    pub salt: Vec<u8>,
+}
+
+impl Params {
+    /// Checks that dm-verity supports the parameters.
+    pub fn validate(&self) -> Result<(), ParamsError> {
+        for block_size in [self.data_block_size, self.hash_block_size] {
+            if !block_size.is_power_of_two()
+                || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size)
+            {
+                return Err(ParamsError::BlockSize(block_size));
+            }
+        }
+        if self.salt.len() > MAX_SALT_SIZE {
+            return Err(ParamsError::SaltSize(self.salt.len()));
+        }
+        if self.data_blocks == 0 {
+            return Err(ParamsError::NoData);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of hash blocks of every level, starting with level 0, which
+    /// hashes the data blocks.
+    #[must_use]
+    pub fn level_sizes(&self) -> Vec<u64> {
+        let digests_per_block = u64::from(self.hash_block_size) / DIGEST_SIZE as u64;
+        let mut sizes = Vec::new();
+        let mut blocks = self.data_blocks;
+        while blocks > 1 {
+            blocks = blocks.div_ceil(digests_per_block);
+            sizes.push(blocks);
+        }
+        sizes
+    }
+
+    /// Returns the size of the hash tree on disk, in bytes.
+    #[must_use]
+    pub fn hash_tree_size(&self) -> u64 {
+        self.level_sizes().iter().sum::<u64>() * u64::from(self.hash_block_size)
+    }
+
+    fn digest(&self, block: &[u8]) -> [u8; DIGEST_SIZE] {
+        Sha256::new()
+            .chain_update(&self.salt)
+            .chain_update(block)
+            .finalize()
+            .into()
+    }
+}
+
+/// A computed hash tree.
+#[derive(Debug)]
+pub struct HashTree {
+    /// Starting with level 0, which hashes the data blocks.
+    levels: Vec<Vec<u8>>,
+    root_hash: [u8; DIGEST_SIZE],
+}
+
+impl HashTree {
+    /// Computes the hash tree of the first `params.data_blocks` blocks of `data`.
+    ///
+    /// `params` must be [valid](Params::validate).
+    pub fn compute(params: &Params, mut data: impl Read) -> io::Result<Self> {
+        let hash_block_size = params.hash_block_size as usize;
+        let mut block = vec![0; params.data_block_size as usize];
+        let mut digests = Vec::new();
+        for _ in 0..params.data_blocks {
+            data.read_exact(&mut block)?;
+            digests.extend_from_slice(&params.digest(&block));
+        }
+        let mut levels = Vec::new();
+        while digests.len() > DIGEST_SIZE {
+            let mut level = digests;
+            level.resize(level.len().next_multiple_of(hash_block_size), 0);
+            digests = level
+                .chunks(hash_block_size)
+                .flat_map(|block| params.digest(block))
+                .collect();
+            levels.push(level);
+        }
+        debug_assert_eq!(
+            levels
+                .iter()
+                .map(|level| (level.len() / hash_block_size) as u64)
+                .collect::<Vec<_>>(),
+            params.level_sizes()
+        );
+        Ok(Self {
+            levels,
+            root_hash: digests
+                .try_into()
+                .expect("there is at least one data block"),
+        })
+    }
+
+    /// Returns the root hash, which authenticates all data blocks.
+    #[must_use]
+    pub fn root_hash(&self) -> &[u8; DIGEST_SIZE] {
+        &self.root_hash
+    }
+
+    /// Writes the hash tree in its on-disk layout, top level first.
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        for level in self.levels.iter().rev() {
+            writer.write_all(level)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that `data` matches `root_hash`, and that `hash_tree` is the hash tree of
+/// `data` in its on-disk layout.
+///
+/// `params` must be [valid](Params::validate).
+pub fn verify(
+    params: &Params,
+    data: impl Read,
+    mut hash_tree: impl Read,
+    root_hash: &[u8],
+) -> Result<(), VerifyError> {
+    let tree = HashTree::compute(params, data)?;
+    if tree.root_hash() != root_hash {
+        return Err(VerifyError::RootHash);
+    }
+    let hash_block_size = params.hash_block_size as usize;
+    for (level, computed) in tree.levels.iter().enumerate().rev() {
+        let mut stored = vec![0; computed.len()];
+        hash_tree.read_exact(&mut stored)?;
+        let mismatch = computed
+            .chunks(hash_block_size)
+            .zip(stored.chunks(hash_block_size))
+            .position(|(computed, stored)| computed != stored);
+        if let Some(block) = mismatch {
+            return Err(VerifyError::HashBlock { level, block });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn params(data_blocks: u64) -> Params {
+        Params {
+            data_block_size: 512,
+            hash_block_size: 512,
+            data_blocks,
+            salt: b"salt".to_vec(),
+        }
+    }
+
+    fn data(params: &Params) -> Vec<u8> {
+        (0..params.data_blocks * u64::from(params.data_block_size))
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    fn sha256(parts: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().to_vec()
+    }
+
+    #[test]
+    fn test_level_sizes() {
+        // 16 digests fit into a 512 byte hash block.
+        assert_eq!(params(1).level_sizes(), [0; 0]);
+        assert_eq!(params(2).level_sizes(), [1]);
+        assert_eq!(params(16).level_sizes(), [1]);
+        assert_eq!(params(17).level_sizes(), [2, 1]);
+        assert_eq!(params(257).level_sizes(), [17, 2, 1]);
+        assert_eq!(params(257).hash_tree_size(), 20 * 512);
+        let params = Params {
+            data_block_size: 4096,
+            hash_block_size: 4096,
+            data_blocks: 262_144,
+            salt: Vec::new(),
+        };
+        assert_eq!(params.level_sizes(), [2048, 16, 1]);
+    }
+
+    #[test]
+    fn test_single_block() {
+        let params = params(1);
+        let data = data(&params);
+        let tree = HashTree::compute(&params, data.as_slice()).unwrap();
+        assert_eq!(tree.root_hash().as_slice(), sha256(&[b"salt", &data]));
+        let mut hash_tree = Vec::new();
+        tree.write_to(&mut hash_tree).unwrap();
+        assert!(hash_tree.is_empty());
+    }
+
+    #[test]
+    fn test_two_levels() {
+        let params = params(17);
+        let data = data(&params);
+        let level0 = data
+            .chunks(512)
+            .flat_map(|block| sha256(&[b"salt", block]))
+            .chain(std::iter::repeat(0))
+            .take(2 * 512)
+            .collect::<Vec<_>>();
+        let level1 = level0
+            .chunks(512)
+            .flat_map(|block| sha256(&[b"salt", block]))
+            .chain(std::iter::repeat(0))
+            .take(512)
+            .collect::<Vec<_>>();
+
+        let tree = HashTree::compute(&params, data.as_slice()).unwrap();
+        assert_eq!(tree.root_hash().as_slice(), sha256(&[b"salt", &level1]));
+        let mut hash_tree = Vec::new();
+        tree.write_to(&mut hash_tree).unwrap();
+        assert_eq!(hash_tree, [level1, level0].concat());
+    }
+
+    #[test]
+    fn test_verify() {
+        let params = params(257);
+        let mut data = data(&params);
+        let tree = HashTree::compute(&params, data.as_slice()).unwrap();
+        let root_hash = *tree.root_hash();
+        let mut hash_tree = Vec::new();
+        tree.write_to(&mut hash_tree).unwrap();
+        verify(&params, data.as_slice(), hash_tree.as_slice(), &root_hash).unwrap();
+
+        // The top level is stored first, so this is the first block of level 0.
+        hash_tree[3 * 512] ^= 1;
+        assert!(matches!(
+            verify(&params, data.as_slice(), hash_tree.as_slice(), &root_hash),
+            Err(VerifyError::HashBlock { level: 0, block: 0 })
+        ));
+
+        data[0] ^= 1;
+        assert!(matches!(
+            verify(&params, data.as_slice(), hash_tree.as_slice(), &root_hash),
+            Err(VerifyError::RootHash)
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        params(1).validate().unwrap();
+        assert!(matches!(params(0).validate(), Err(ParamsError::NoData)));
+        let invalid_block_size = Params {
+            hash_block_size: 1000,
+            ..params(1)
+        };
+        assert!(matches!(
+            invalid_block_size.validate(),
+            Err(ParamsError::BlockSize(1000))
+        ));
+        let invalid_salt = Params {
+            salt: vec![0; 257],
+            ..params(1)
+        };
+        assert!(matches!(
+            invalid_salt.validate(),
+            Err(ParamsError::SaltSize(257))
+        ));
+    }
+}